@@ -0,0 +1,272 @@
+//! Generates typed parameter enums from the bundled device schemas when the `codegen` feature is enabled.
+
+use serde_yaml::{Mapping, Value};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/schema");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("generated.rs");
+    if env::var("CARGO_FEATURE_CODEGEN").is_err() {
+        return;
+    }
+    let mut code = String::new();
+    for path in schemas() {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let body = fs::read_to_string(&path).unwrap();
+        let mut device: Value = serde_yaml::from_str(&body).unwrap();
+        resolve_includes(&mut device);
+        gen_device(&device, &mut code).unwrap();
+    }
+    fs::write(out, code).unwrap();
+}
+
+/// The bundled device schemas, the files of `src/schema` naming a device. Files without a
+/// name only hold parameters shared by several devices.
+fn schemas() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir("src/schema")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+        .filter(|path| {
+            let body = fs::read_to_string(path).unwrap();
+            let doc: Value = serde_yaml::from_str(&body).unwrap();
+            doc.get("name").is_some()
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Same as `schema::resolve_includes`, for bundled files
+fn resolve_includes(doc: &mut Value) {
+    let load = |file: &Value| -> Value {
+        let path = Path::new("src/schema").join(file.as_str().expect("include file"));
+        println!("cargo:rerun-if-changed={}", path.display());
+        let mut included = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        resolve_includes(&mut included);
+        included
+    };
+    let own = doc.as_mapping_mut().expect("schema mapping");
+    let extends = own.remove(&Value::from("extends"));
+    let include = own.remove(&Value::from("include"));
+    if let Some(file) = extends {
+        let mut base = load(&file);
+        let base_map = base.as_mapping_mut().unwrap();
+        base_map.remove(&Value::from("name"));
+        base_map.remove(&Value::from("aliases"));
+        fill(doc, base);
+    }
+    for file in include
+        .as_ref()
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+    {
+        let mut shared = Mapping::new();
+        shared.insert(Value::from("parameters"), load(file)["parameters"].clone());
+        fill(doc, Value::Mapping(shared));
+    }
+}
+
+fn fill(doc: &mut Value, base: Value) {
+    if let (Value::Mapping(doc), Value::Mapping(base)) = (doc, base) {
+        for (key, value) in base {
+            match doc.get_mut(&key) {
+                Some(own) => fill(own, value),
+                None => {
+                    doc.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+fn gen_device(device: &Value, code: &mut String) -> std::fmt::Result {
+    let name = device["name"].as_str().expect("device name");
+    writeln!(code, "/// Typed parameters of the {}", name)?;
+    writeln!(code, "pub mod {} {{", name.to_lowercase())?;
+    writeln!(code, "    use crate::schema::TypedParam;")?;
+    if let Some(params) = device["parameters"].as_mapping() {
+        for (pname, param) in params {
+            gen_param(pname.as_str().expect("param name"), param, code)?;
+        }
+    }
+    writeln!(code, "}}")
+}
+
+fn gen_param(name: &str, param: &Value, code: &mut String) -> std::fmt::Result {
+    let mut variants = vec![];
+    let mut range = false;
+    let mut value_type = "i16";
+    let mut notes = false;
+    let mut text = false;
+    let mut dump = false;
+    // modes with fields carry their `Field=value` assignments
+    let mut modes = vec![];
+    for (mode, schema) in param["modes"].as_mapping().into_iter().flatten() {
+        let mode = mode.as_str().unwrap();
+        if schema.get("fields").is_some() {
+            modes.push((ident(mode), mode.to_string()));
+        } else {
+            variants.push((ident(mode), mode.to_string()));
+        }
+    }
+    for bounds in param["bounds"].as_sequence().into_iter().flatten() {
+        match bounds["type"].as_str() {
+            Some("Values") => {
+                for (value, _) in bounds.as_mapping().unwrap() {
+                    let value = value.as_str().unwrap();
+                    if value != "type" {
+                        variants.push((ident(value), value.to_string()));
+                    }
+                }
+            }
+            Some("Range") => range = true,
+            Some("Wide") => {
+                range = true;
+                value_type = "u32";
+            }
+            Some("NoteSeq") => notes = true,
+            Some("Text") => text = true,
+            Some("Dump") => dump = true,
+            _ => {}
+        }
+    }
+
+    writeln!(code, "    #[derive(Debug, Clone, PartialEq)]")?;
+    // variants are named after the schema's values, such as `NRPN` or `LowNote`
+    writeln!(
+        code,
+        "    #[allow(clippy::enum_variant_names, clippy::upper_case_acronyms)]"
+    )?;
+    writeln!(code, "    pub enum {} {{", name)?;
+    for (variant, _) in &variants {
+        writeln!(code, "        {},", variant)?;
+    }
+    for (variant, _) in &modes {
+        writeln!(code, "        {}(Vec<String>),", variant)?;
+    }
+    if range {
+        writeln!(code, "        Value({}),", value_type)?;
+    }
+    if notes {
+        writeln!(code, "        Notes(Vec<String>),")?;
+    }
+    if text {
+        writeln!(code, "        Text(String),")?;
+    }
+    if dump {
+        writeln!(code, "        Dump(String),")?;
+    }
+    writeln!(code, "    }}")?;
+
+    writeln!(code, "    impl TypedParam for {} {{", name)?;
+    writeln!(code, "        const NAME: &'static str = {:?};", name)?;
+    writeln!(code, "        fn value_ids(&self) -> Vec<String> {{")?;
+    writeln!(code, "            match self {{")?;
+    for (variant, value) in &variants {
+        writeln!(
+            code,
+            "                {}::{} => vec![{:?}.to_string()],",
+            name, variant, value
+        )?;
+    }
+    for (variant, mode) in &modes {
+        writeln!(
+            code,
+            "                {}::{}(fields) => std::iter::once({:?}.to_string()).chain(fields.iter().cloned()).collect(),",
+            name, variant, mode
+        )?;
+    }
+    if range {
+        writeln!(
+            code,
+            "                {}::Value(v) => vec![v.to_string()],",
+            name
+        )?;
+    }
+    if notes {
+        writeln!(code, "                {}::Notes(n) => n.clone(),", name)?;
+    }
+    if text {
+        writeln!(
+            code,
+            "                {}::Text(t) => vec![t.clone()],",
+            name
+        )?;
+    }
+    if dump {
+        writeln!(
+            code,
+            "                {}::Dump(d) => vec![d.clone()],",
+            name
+        )?;
+    }
+    writeln!(code, "            }}")?;
+    writeln!(code, "        }}")?;
+    writeln!(
+        code,
+        "        fn from_value_ids(ids: &[String]) -> Option<Self> {{"
+    )?;
+    if notes {
+        writeln!(code, "            Some({}::Notes(ids.to_vec()))", name)?;
+    } else {
+        writeln!(code, "            match ids {{")?;
+        for (variant, value) in &variants {
+            writeln!(
+                code,
+                "                [id] if id == {:?} => Some({}::{}),",
+                value, name, variant
+            )?;
+        }
+        for (variant, mode) in &modes {
+            writeln!(
+                code,
+                "                [mode, fields @ ..] if mode == {:?} => Some({}::{}(fields.to_vec())),",
+                mode, name, variant
+            )?;
+        }
+        if dump {
+            writeln!(
+                code,
+                "                [id] => Some({}::Dump(id.clone())),",
+                name
+            )?;
+        }
+        if range {
+            writeln!(
+                code,
+                "                [id] => id.parse().ok().map({}::Value),",
+                name
+            )?;
+        }
+        if text {
+            writeln!(
+                code,
+                "                ids => Some({}::Text(ids.join(\" \"))),",
+                name
+            )?;
+        } else {
+            writeln!(code, "                _ => None,")?;
+        }
+        writeln!(code, "            }}")?;
+    }
+    writeln!(code, "        }}")?;
+    writeln!(code, "    }}")
+}
+
+/// Turn a value name such as `1/16` into a valid identifier such as `V1_16`
+fn ident(value: &str) -> String {
+    let mut ident: String = value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, 'V');
+    }
+    ident
+}
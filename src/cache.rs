@@ -0,0 +1,81 @@
+use crate::devices::{DeviceType, Identity, Result};
+use crate::snapshot::Snapshot;
+use linked_hash_map::LinkedHashMap;
+use std::fs;
+use std::path::PathBuf;
+
+fn dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("la_bruteforce"))
+}
+
+/// Name of the cache file of a device: its identity when it replied with one, so that units
+/// swapping ports keep their values, else its unit
+fn file_name(dev_type: &DeviceType, unit: usize, identity: Option<&Identity>) -> String {
+    match identity {
+        Some(id) => format!(
+            "{}-{}-{:04x}-{:04x}-{}.yaml",
+            dev_type,
+            hex::encode(&id.manufacturer),
+            id.family,
+            id.model,
+            id.firmware_version()
+        ),
+        None => format!("{}-{}.yaml", dev_type, unit),
+    }
+}
+
+/// File naming the identity cache file of the device last connected as a unit, for
+/// `get --cached` to find it without connecting
+fn link_path(dev_type: &DeviceType, unit: usize) -> Option<PathBuf> {
+    dir().map(|dir| dir.join(format!("{}-{}.link", dev_type, unit)))
+}
+
+/// Last known values of a device, read by `get --cached`. Without an identity, the file of the
+/// device last connected as the unit is used.
+pub fn path(dev_type: &DeviceType, unit: usize, identity: Option<&Identity>) -> Option<PathBuf> {
+    let dir = dir()?;
+    if identity.is_none() {
+        if let Some(linked) = link_path(dev_type, unit).and_then(|p| fs::read_to_string(p).ok()) {
+            return Some(dir.join(linked.trim()));
+        }
+    }
+    Some(dir.join(file_name(dev_type, unit, identity)))
+}
+
+/// Cached values of a device, empty if none were cached
+pub fn load(
+    dev_type: &DeviceType,
+    unit: usize,
+    identity: Option<&Identity>,
+) -> Result<LinkedHashMap<String, Vec<String>>> {
+    match path(dev_type, unit, identity) {
+        Some(path) if path.exists() => Ok(Snapshot::load(&path)?.values),
+        _ => Ok(LinkedHashMap::new()),
+    }
+}
+
+/// Merge newly read or written values into the cache of a device connected as a unit
+pub fn update(
+    dev_type: &DeviceType,
+    unit: usize,
+    identity: Option<&Identity>,
+    values: &LinkedHashMap<String, Vec<String>>,
+) -> Result<()> {
+    let (dir, link) = match (dir(), link_path(dev_type, unit)) {
+        (Some(dir), Some(link)) => (dir, link),
+        _ => return Ok(()),
+    };
+    fs::create_dir_all(&dir)?;
+    let name = file_name(dev_type, unit, identity);
+    if identity.is_some() {
+        fs::write(&link, &name)?;
+    } else if link.exists() {
+        fs::remove_file(&link)?;
+    }
+    let path = dir.join(name);
+    let mut cached = load(dev_type, unit, identity)?;
+    for (param, value) in values {
+        cached.insert(param.clone(), value.clone());
+    }
+    Snapshot::new(dev_type.to_string(), cached).save(Some(&path))
+}
@@ -0,0 +1,103 @@
+use serde::Deserialize;
+
+use crate::devices::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// User settings, read from `~/.config/la_bruteforce/config.toml`
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// Named groups of devices, usable anywhere a device name is expected
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+
+    /// Device used when a command's device name is omitted
+    pub device: Option<String>,
+
+    /// Output port of the default device, when it can not be found by its name
+    pub port: Option<String>,
+
+    /// Short names for parameters, e.g. `bend = "BendRange"`
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+
+    /// Backup files that `daemon` restores when a device is connected, by device name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, PathBuf>,
+}
+
+pub fn path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("la_bruteforce").join("config.toml"))
+}
+
+/// Load the user config, or an empty config if there is none
+pub fn load() -> Result<Config> {
+    match path() {
+        Some(path) if path.exists() => Ok(toml::from_str(&fs::read_to_string(path)?)?),
+        _ => Ok(Config::default()),
+    }
+}
+
+impl Config {
+    /// The devices designated by a name, which is either a group or a single device
+    pub fn devices(&self, name: &str) -> Vec<String> {
+        match self.groups.get(name) {
+            Some(group) => group.clone(),
+            None => vec![name.to_string()],
+        }
+    }
+
+    /// The parameter designated by a name, which is either an alias or a parameter name
+    pub fn param_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    /// The port configured for a device, if it is the default device
+    pub fn port(&self, device_name: &str) -> Option<&str> {
+        match &self.device {
+            Some(device) if device == device_name => self.port.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Config;
+
+    #[test]
+    fn test_groups() {
+        let config: Config = toml::from_str(
+            r#"
+[groups]
+live-rig = ["MicroBrute", "BeatStep"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.devices("live-rig"), vec!["MicroBrute", "BeatStep"]);
+        assert_eq!(config.devices("MicroBrute"), vec!["MicroBrute"]);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+device = "MicroBrute"
+port = "MicroBrute MIDI 1"
+
+[aliases]
+bend = "BendRange"
+
+[profiles]
+MicroBrute = "live.json"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.param_name("bend"), "BendRange");
+        assert_eq!(config.param_name("Gate"), "Gate");
+        assert_eq!(config.port("MicroBrute"), Some("MicroBrute MIDI 1"));
+        assert_eq!(config.port("MicroBrute/2"), None);
+        assert_eq!(config.profiles["MicroBrute"].to_str(), Some("live.json"));
+    }
+}
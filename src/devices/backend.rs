@@ -0,0 +1,374 @@
+//! Access to MIDI ports, through midir or through an in-memory mock standing in for devices in
+//! tests. Devices send and receive through a backend, so their queries and updates can run
+//! without hardware.
+
+use crate::devices::{self, DeviceError, MidiPort, Result, CLIENT_NAME};
+
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// Called with the timestamp and bytes of each message received on an input port
+pub type Callback = Box<dyn FnMut(u64, &[u8]) + Send>;
+
+pub trait MidiBackend: Send + Sync {
+    fn output_ports(&self) -> Vec<MidiPort>;
+    fn input_ports(&self) -> Vec<MidiPort>;
+    fn connect_output(&self, port: &MidiPort) -> Result<Box<dyn MidiSender>>;
+    /// Pass messages received on an input port to `callback`, until the listener is dropped
+    fn connect_input(&self, port: &MidiPort, callback: Callback) -> Result<Box<dyn MidiListener>>;
+
+    /// Input port of exactly this name
+    fn input_port(&self, name: &str) -> Option<MidiPort> {
+        self.input_ports()
+            .into_iter()
+            .find(|port| port.name == name)
+    }
+
+    /// Pass messages received on the input port of this name to `callback`
+    fn listen(&self, port_name: &str, callback: Callback) -> Result<Box<dyn MidiListener>> {
+        let port = self
+            .input_port(port_name)
+            .ok_or_else(|| DeviceError::NoInputPort {
+                port_name: port_name.to_string(),
+            })?;
+        self.connect_input(&port, callback)
+    }
+}
+
+pub trait MidiSender: Send {
+    fn send(&mut self, message: &[u8]) -> Result<()>;
+}
+
+/// An open input port, closed when dropped
+pub trait MidiListener: Send {}
+
+/// Ports of the system, as seen by midir
+pub struct MidirBackend;
+
+impl MidiBackend for MidirBackend {
+    fn output_ports(&self) -> Vec<MidiPort> {
+        match MidiOutput::new(CLIENT_NAME) {
+            Ok(midi_out) => devices::output_ports(&midi_out),
+            Err(_) => vec![],
+        }
+    }
+
+    fn input_ports(&self) -> Vec<MidiPort> {
+        match MidiInput::new(CLIENT_NAME) {
+            Ok(midi_in) => devices::input_ports(&midi_in),
+            Err(_) => vec![],
+        }
+    }
+
+    fn connect_output(&self, port: &MidiPort) -> Result<Box<dyn MidiSender>> {
+        let midi_out = MidiOutput::new(CLIENT_NAME)?;
+        Ok(Box::new(midi_out.connect(port.number, &port.name)?))
+    }
+
+    fn connect_input(
+        &self,
+        port: &MidiPort,
+        mut callback: Callback,
+    ) -> Result<Box<dyn MidiListener>> {
+        let midi_in = MidiInput::new(CLIENT_NAME)?;
+        let mut stream = SysexStream::default();
+        Ok(Box::new(midi_in.connect(
+            port.number,
+            &port.name,
+            move |ts, bytes, _| {
+                for message in stream.push(bytes) {
+                    callback(ts, &message)
+                }
+            },
+            (),
+        )?))
+    }
+}
+
+/// Joins sysex messages that some backends deliver in several parts, until their 0xF7 arrives.
+/// Other messages pass through, including real-time messages within a sysex message.
+#[derive(Default)]
+pub struct SysexStream {
+    pending: Vec<u8>,
+}
+
+impl SysexStream {
+    /// Complete messages ended by some bytes
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        let mut rest = bytes;
+        while let Some(&first) = rest.first() {
+            if first == 0xf0 {
+                if !self.pending.is_empty() {
+                    debug!("dropping unterminated sysex {}", hex::encode(&self.pending));
+                }
+                self.pending.clear();
+            } else if self.pending.is_empty() {
+                // not within a sysex message
+                messages.push(rest.to_vec());
+                break;
+            } else if first >= 0xf8 {
+                messages.push(vec![first]);
+                rest = &rest[1..];
+                continue;
+            }
+            match rest.iter().position(|b| *b == 0xf7 || *b >= 0xf8) {
+                Some(end) if rest[end] == 0xf7 => {
+                    self.pending.extend_from_slice(&rest[..=end]);
+                    messages.push(self.pending.split_off(0));
+                    rest = &rest[end + 1..];
+                }
+                Some(end) => {
+                    self.pending.extend_from_slice(&rest[..end]);
+                    rest = &rest[end..];
+                }
+                None => {
+                    self.pending.extend_from_slice(rest);
+                    break;
+                }
+            }
+        }
+        messages
+    }
+}
+
+impl MidiSender for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        Ok(MidiOutputConnection::send(self, message)?)
+    }
+}
+
+impl MidiListener for MidiInputConnection<()> {}
+
+/// Messages remembered by `EchoFilter`, echoes arriving soon after what they repeat
+const ECHO_WINDOW: usize = 32;
+
+/// Drops messages received that repeat one sent, for devices echoing their input to their
+/// output, whose echoes would otherwise be read as replies
+pub struct EchoFilter {
+    inner: Arc<dyn MidiBackend>,
+    sent: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl EchoFilter {
+    /// Filter the echoes of a backend if the device echoes its input
+    pub fn wrap(backend: Arc<dyn MidiBackend>, echoes_input: bool) -> Arc<dyn MidiBackend> {
+        if echoes_input {
+            Arc::new(EchoFilter {
+                inner: backend,
+                sent: Arc::default(),
+            })
+        } else {
+            backend
+        }
+    }
+}
+
+impl MidiBackend for EchoFilter {
+    fn output_ports(&self) -> Vec<MidiPort> {
+        self.inner.output_ports()
+    }
+
+    fn input_ports(&self) -> Vec<MidiPort> {
+        self.inner.input_ports()
+    }
+
+    fn connect_output(&self, port: &MidiPort) -> Result<Box<dyn MidiSender>> {
+        Ok(Box::new(EchoedSender {
+            inner: self.inner.connect_output(port)?,
+            sent: self.sent.clone(),
+        }))
+    }
+
+    fn connect_input(
+        &self,
+        port: &MidiPort,
+        mut callback: Callback,
+    ) -> Result<Box<dyn MidiListener>> {
+        let sent = self.sent.clone();
+        self.inner.connect_input(
+            port,
+            Box::new(move |ts, message| {
+                let mut sent = sent.lock().unwrap();
+                match sent.iter().position(|msg| msg.as_slice() == message) {
+                    Some(echo) => {
+                        debug!("dropping echo {}", hex::encode(message));
+                        sent.remove(echo);
+                    }
+                    None => {
+                        drop(sent);
+                        callback(ts, message)
+                    }
+                }
+            }),
+        )
+    }
+}
+
+struct EchoedSender {
+    inner: Box<dyn MidiSender>,
+    sent: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl MidiSender for EchoedSender {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        {
+            let mut sent = self.sent.lock().unwrap();
+            if sent.len() == ECHO_WINDOW {
+                sent.pop_front();
+            }
+            sent.push_back(message.to_vec());
+        }
+        self.inner.send(message)
+    }
+}
+
+/// Devices simulated in memory: every message sent is recorded, and those matching a script
+/// are answered on the input port of the same name
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<Mock>>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Mock {
+    ports: Vec<String>,
+    sent: Vec<(String, Vec<u8>)>,
+    /// Replies to messages starting with some bytes
+    script: Vec<(Vec<u8>, Vec<Vec<u8>>)>,
+    listeners: Vec<(usize, String, Callback)>,
+    next_listener: usize,
+}
+
+#[cfg(test)]
+impl MockBackend {
+    pub fn new(port_names: &[&str]) -> Self {
+        let backend = MockBackend::default();
+        backend.state.lock().unwrap().ports = port_names.iter().map(|s| s.to_string()).collect();
+        backend
+    }
+
+    /// Answer messages starting with `request` with `replies`. Earlier scripts take precedence.
+    pub fn reply(&self, request: &[u8], replies: Vec<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        state.script.push((request.to_vec(), replies));
+    }
+
+    /// Rename a port, as if its device was plugged back under another name. Messages sent to the
+    /// old name fail.
+    pub fn rename(&self, name: &str, new_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        for port in state.ports.iter_mut().filter(|port| *port == name) {
+            *port = new_name.to_string();
+        }
+    }
+
+    /// Messages sent so far, on any port
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.sent.iter().map(|(_, msg)| msg.clone()).collect()
+    }
+
+    pub fn port(&self, name: &str) -> MidiPort {
+        self.output_ports()
+            .into_iter()
+            .find(|port| port.name == name)
+            .expect("mock port")
+    }
+
+    fn ports(&self) -> Vec<MidiPort> {
+        let state = self.state.lock().unwrap();
+        state
+            .ports
+            .iter()
+            .enumerate()
+            .map(|(number, name)| MidiPort {
+                number,
+                name: name.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl MidiBackend for MockBackend {
+    fn output_ports(&self) -> Vec<MidiPort> {
+        self.ports()
+    }
+
+    fn input_ports(&self) -> Vec<MidiPort> {
+        self.ports()
+    }
+
+    fn connect_output(&self, port: &MidiPort) -> Result<Box<dyn MidiSender>> {
+        Ok(Box::new(MockSender {
+            state: self.state.clone(),
+            port_name: port.name.clone(),
+        }))
+    }
+
+    fn connect_input(&self, port: &MidiPort, callback: Callback) -> Result<Box<dyn MidiListener>> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_listener;
+        state.next_listener += 1;
+        state.listeners.push((id, port.name.clone(), callback));
+        Ok(Box::new(MockListener {
+            state: self.state.clone(),
+            id,
+        }))
+    }
+}
+
+#[cfg(test)]
+struct MockSender {
+    state: Arc<Mutex<Mock>>,
+    port_name: String,
+}
+
+#[cfg(test)]
+impl MidiSender for MockSender {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.ports.contains(&self.port_name) {
+            return Err(Box::new(DeviceError::NoOutputPort {
+                port_name: self.port_name.clone(),
+            }));
+        }
+        state.sent.push((self.port_name.clone(), message.to_vec()));
+        let replies = state
+            .script
+            .iter()
+            .find(|(request, _)| message.starts_with(request))
+            .map(|(_, replies)| replies.clone())
+            .unwrap_or_default();
+        for reply in replies {
+            for (_, port_name, callback) in state.listeners.iter_mut() {
+                if *port_name == self.port_name {
+                    callback(0, &reply);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct MockListener {
+    state: Arc<Mutex<Mock>>,
+    id: usize,
+}
+
+#[cfg(test)]
+impl MidiListener for MockListener {}
+
+#[cfg(test)]
+impl Drop for MockListener {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.listeners.retain(|(id, _, _)| *id != self.id);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::devices::backend::{MidiBackend, MidirBackend};
+use crate::devices::{self, MidiPort};
+use crate::devices::{sysex, Connection, Correlator, DeviceError, Identity};
+use crate::devices::{Descriptor, Device, Overrides};
+use crate::schema::{self, Bounds, Form, NoteSeq, Param};
+
+use devices::Result;
+use linked_hash_map::LinkedHashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+fn sysex_data_code<'a>(param: &Param<'a>) -> &'a [u8] {
+    param.schema.sysex.get(Form::Update)
+}
+
+fn sysex_query_code<'a>(param: &Param<'a>) -> &'a [u8] {
+    param.schema.sysex.get(Form::Query)
+}
+
+/// Arturia synths sharing the MicroBrute protocol, where messages carry a message id
+#[derive(Debug)]
+pub struct BruteDescriptor {
+    /// Name of the device schema
+    pub name: &'static str,
+}
+
+impl BruteDescriptor {
+    fn schema(&self) -> &'static schema::Device {
+        schema::device(self.name).expect("brute schema")
+    }
+}
+
+impl Descriptor for BruteDescriptor {
+    fn ports(&self) -> Vec<MidiPort> {
+        MidirBackend
+            .output_ports()
+            .into_iter()
+            .filter_map(|port| {
+                if self.schema().matches_port(&port.name) {
+                    Some(port)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn connect(
+        &self,
+        backend: Arc<dyn MidiBackend>,
+        port: &MidiPort,
+        overrides: &Overrides,
+    ) -> Result<Box<dyn Device>> {
+        let connection = Connection::identified(self.schema(), backend, port, overrides)?;
+        // ids of the messages sent after the identity request start at 1
+        Ok(Box::new(BruteDevice {
+            connection,
+            msg_id: 1,
+        }))
+    }
+
+    fn encode(&self, param: &str, value_ids: &[String]) -> Result<Vec<Vec<u8>>> {
+        encode(self.schema(), param, value_ids, 0)
+    }
+
+    fn encode_query(&self, param: &str) -> Result<Vec<Vec<u8>>> {
+        encode_query(self.schema(), param, 0)
+    }
+
+    fn decode(&self, message: &[u8]) -> LinkedHashMap<String, Vec<String>> {
+        devices::decode_message(self.schema(), message)
+    }
+}
+
+pub struct BruteDevice {
+    connection: Connection,
+    msg_id: usize,
+}
+
+impl Device for BruteDevice {
+    fn query(&mut self, params: &[String]) -> Result<LinkedHashMap<String, Vec<String>>> {
+        let schema = self.connection.schema;
+        let correlator = Correlator::default();
+        let replies = correlator.clone();
+        let id_pos = schema.reply.and_then(|reply| reply.id_pos);
+        let sysex_replies =
+            self.connection
+                .listen(&self.connection.in_port_name, move |msg, result| {
+                    if let Some(id_pos) = id_pos {
+                        let param =
+                            devices::reply_param(schema, msg).map(|(p, _, _)| p.to_string());
+                        if !replies.accept(msg.get(id_pos).copied(), param) {
+                            return;
+                        }
+                    }
+                    devices::decode_reply(schema, msg, result)
+                })?;
+        let mut sent = 0;
+        for param_str in params {
+            let param = schema.param(param_str)?;
+            if let Some(firmwares) =
+                devices::unsupported_firmware(&param, &[], self.connection.identity.as_ref())
+            {
+                warn!("skipping {}, it needs {}", param, firmwares);
+                continue;
+            }
+            for msg in encode_query(schema, param_str, self.msg_id as u8)? {
+                correlator.expect(self.msg_id as u8, param.to_string());
+                self.connection.send(&msg)?;
+                self.msg_id += 1;
+                sent += 1;
+            }
+        }
+        let values = sysex_replies.close_wait(sent, self.connection.timeout);
+        if id_pos.is_some() {
+            for param in correlator.missing() {
+                warn!("no reply to the query of {}", param);
+            }
+        }
+        Ok(values)
+    }
+
+    fn update(&mut self, param_str: &str, value_ids: &[String]) -> Result<()> {
+        let schema = self.connection.schema;
+        let param = schema.param(param_str)?;
+        if let Some(firmwares) =
+            devices::unsupported_firmware(&param, value_ids, self.connection.identity.as_ref())
+        {
+            warn!("{} needs {}, the device may ignore it", param, firmwares);
+        }
+        for msg in encode(schema, param_str, value_ids, self.msg_id as u8)? {
+            self.connection.send(&msg)?;
+            self.msg_id += 1;
+        }
+        Ok(())
+    }
+
+    fn identity(&self) -> Option<&Identity> {
+        self.connection.identity.as_ref()
+    }
+
+    fn ping(&mut self, count: usize, interval: Duration) -> Result<usize> {
+        let msg_id = &mut self.msg_id;
+        self.connection.ping(count, interval, |query_code| {
+            let body = [&[0x01, *msg_id as u8], query_code].concat();
+            *msg_id += 1;
+            body
+        })
+    }
+
+    fn watch(&mut self) -> Result<()> {
+        self.connection.watch()
+    }
+}
+
+/// Sysex messages requesting the value of a parameter, numbered from `msg_id`
+fn encode_query(schema: &schema::Device, param_str: &str, msg_id: u8) -> Result<Vec<Vec<u8>>> {
+    let param = schema.param(param_str)?;
+    let header = schema.header();
+    let query_code = sysex_query_code(&param);
+    Ok(match (param.index, blocks(&param)) {
+        (Some(idx), Some(seq)) => {
+            // 0x01 MSGID(u8) 0x23,0x3b(SEQ) SEQ_IDX(u8 0 - 7) SEQ_OFFSET(u8) SEQ_LEN(u8)
+            (0..seq.blocks())
+                .map(|block| {
+                    sysex(
+                        &header,
+                        &[
+                            &[0x01, msg_id.wrapping_add(block as u8)],
+                            query_code,
+                            &[idx, seq.offset_code(block), seq.block_len.unwrap_or(0)],
+                        ],
+                        schema.checksum,
+                    )
+                })
+                .collect()
+        }
+        (index, _) => {
+            let index: Vec<u8> = index.into_iter().collect();
+            vec![sysex(
+                &header,
+                &[&[0x01, msg_id], query_code, &index],
+                schema.checksum,
+            )]
+        }
+    })
+}
+
+/// Whether a parameter is a raw dump, split in fragments on devices that use them
+fn is_dump(param: &Param) -> bool {
+    matches!(param.bounds().first(), Some(Bounds::Dump(_)))
+}
+
+/// Bounds of a sequence of notes sent and received in blocks, if the parameter is one
+fn blocks(param: &Param) -> Option<NoteSeq> {
+    match param.bounds().first() {
+        Some(Bounds::NoteSeq(seq)) if seq.block_len.is_some() => Some(*seq),
+        _ => None,
+    }
+}
+
+/// Sysex messages setting a parameter, numbered from `msg_id`
+fn encode(
+    schema: &schema::Device,
+    param_str: &str,
+    value_ids: &[String],
+    msg_id: u8,
+) -> Result<Vec<Vec<u8>>> {
+    let param = schema.param(param_str)?;
+    let header = schema.header();
+    if param.schema.modes.is_some() {
+        return Ok(devices::modal_codes(&param, value_ids)?
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                sysex(
+                    &header,
+                    &[&[0x01, msg_id.wrapping_add(i as u8)], body],
+                    schema.checksum,
+                )
+            })
+            .collect());
+    }
+    let mut bcodes = devices::bound_codes(&param, value_ids)?;
+    let mut messages = vec![];
+    match (param.index, blocks(&param)) {
+        (Some(seq_idx), Some(seq)) => {
+            // 0x01 MSGID(u8) SEQ(0x23, 0x3a) SEQ_ID(u8) SEQ_OFFSET(u8) SEQ_LEN(u8) SEQ_NOTES(0 padded, start@ C0=0x30, C#0 0x31... rest=0x7f)
+            let block_len = seq.block_len.unwrap_or(0).max(1) as usize;
+            let notes = bcodes.len();
+            let blocks = notes.max(1).div_ceil(block_len);
+            bcodes.resize(blocks * block_len, 0x00);
+            for block in 0..blocks {
+                let offset = block * block_len;
+                messages.push(sysex(
+                    &header,
+                    &[
+                        &[0x01, msg_id.wrapping_add(block as u8)],
+                        sysex_data_code(&param),
+                        &[
+                            seq_idx,
+                            seq.offset_code(block),
+                            notes.saturating_sub(offset).min(block_len) as u8,
+                        ],
+                        &bcodes[offset..offset + block_len],
+                    ],
+                    schema.checksum,
+                ));
+            }
+        }
+        (index, _) => {
+            let index: Vec<u8> = index.into_iter().collect();
+            if bcodes.is_empty() {
+                return Err(Box::new(DeviceError::MissingValue {
+                    param_name: param_str.to_string(),
+                }));
+            }
+            let split = schema
+                .fragments
+                .filter(|fragments| is_dump(&param) && fragments.max_data.is_some())
+                .and_then(|fragments| {
+                    let mut payload = [&[0x01, msg_id], sysex_data_code(&param), &index].concat();
+                    // placeholder of the continuation marker, set in each fragment
+                    payload.insert(fragments.marker_pos.min(payload.len()), 0x00);
+                    payload.extend_from_slice(&bcodes);
+                    fragments.split(&payload, fragments.max_data.unwrap_or(0))
+                });
+            match split {
+                Some(split) => {
+                    for fragment in split {
+                        messages.push(sysex(&header, &[&fragment], schema.checksum));
+                    }
+                }
+                // values too short to be split fit in one message
+                None => messages.push(sysex(
+                    &header,
+                    &[&[0x01, msg_id], sysex_data_code(&param), &index, &bcodes],
+                    schema.checksum,
+                )),
+            }
+        }
+    }
+    Ok(messages)
+}
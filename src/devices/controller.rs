@@ -0,0 +1,172 @@
+//! Devices whose parameters are set and queried with plain sysex messages, without message ids,
+//! described entirely by their schema. Arturia controllers sharing the BeatStep protocol set
+//! values with `02 00 <param> <control> <value>` and query them with `01 00 <param> <control>`.
+
+use crate::devices::backend::{MidiBackend, MidirBackend};
+use crate::devices::{self, MidiPort};
+use crate::devices::{sysex, Connection, Identity};
+use crate::devices::{Descriptor, Device, Overrides};
+use crate::schema::{self, Form, Param};
+
+use devices::Result;
+use linked_hash_map::LinkedHashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+// Pad and encoder modes and their fields are described by schema/ArturiaControls.yaml, e.g.
+// Encoder/2:CC Option=Relative1 CC=45 Channel=4 Min=0 Max=127
+// Pad/1:Note Option=Gate Channel=Global Note=G#1
+//
+// arturia  beatstep           field   control (0x70..) value
+// 00 20 6b 7f       42 02 00  01      70               09
+
+fn sysex_data_code<'a>(param: &Param<'a>) -> &'a [u8] {
+    param.schema.sysex.get(Form::Update)
+}
+
+fn sysex_query_code<'a>(param: &Param<'a>) -> &'a [u8] {
+    param.schema.sysex.get(Form::Query)
+}
+
+#[derive(Debug)]
+pub struct ControllerDescriptor {
+    /// Name of the device schema
+    pub name: &'static str,
+}
+
+impl ControllerDescriptor {
+    fn schema(&self) -> &'static schema::Device {
+        schema::device(self.name).expect("controller schema")
+    }
+}
+
+impl Descriptor for ControllerDescriptor {
+    fn ports(&self) -> Vec<MidiPort> {
+        MidirBackend
+            .output_ports()
+            .into_iter()
+            .filter_map(|port| {
+                if self.schema().matches_port(&port.name) {
+                    Some(port)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn connect(
+        &self,
+        backend: Arc<dyn MidiBackend>,
+        port: &MidiPort,
+        overrides: &Overrides,
+    ) -> Result<Box<dyn Device>> {
+        let connection = Connection::identified(self.schema(), backend, port, overrides)?;
+        Ok(Box::new(ControllerDevice { connection }))
+    }
+
+    fn encode(&self, param: &str, value_ids: &[String]) -> Result<Vec<Vec<u8>>> {
+        encode(self.schema(), param, value_ids)
+    }
+
+    fn encode_query(&self, param: &str) -> Result<Vec<Vec<u8>>> {
+        encode_query(self.schema(), param)
+    }
+
+    fn decode(&self, message: &[u8]) -> LinkedHashMap<String, Vec<String>> {
+        devices::decode_message(self.schema(), message)
+    }
+}
+
+pub struct ControllerDevice {
+    connection: Connection,
+}
+
+impl Device for ControllerDevice {
+    fn query(&mut self, params: &[String]) -> Result<LinkedHashMap<String, Vec<String>>> {
+        let schema = self.connection.schema;
+        let sysex_replies = self
+            .connection
+            .listen(&self.connection.in_port_name, move |msg, result| {
+                devices::decode_reply(schema, msg, result)
+            })?;
+        let mut sent = 0;
+        for param_str in params {
+            let param = schema.param(param_str)?;
+            if let Some(firmwares) =
+                devices::unsupported_firmware(&param, &[], self.connection.identity.as_ref())
+            {
+                warn!("skipping {}, it needs {}", param, firmwares);
+                continue;
+            }
+            for msg in encode_query(schema, param_str)? {
+                self.connection.send(&msg)?;
+                sent += 1;
+            }
+        }
+        Ok(sysex_replies.close_wait(sent, self.connection.timeout))
+    }
+
+    fn update(&mut self, param_str: &str, value_ids: &[String]) -> Result<()> {
+        let schema = self.connection.schema;
+        let param = schema.param(param_str)?;
+        if let Some(firmwares) =
+            devices::unsupported_firmware(&param, value_ids, self.connection.identity.as_ref())
+        {
+            warn!("{} needs {}, the device may ignore it", param, firmwares);
+        }
+        for msg in encode(schema, param_str, value_ids)? {
+            self.connection.send(&msg)?;
+        }
+        Ok(())
+    }
+
+    fn identity(&self) -> Option<&Identity> {
+        self.connection.identity.as_ref()
+    }
+
+    fn ping(&mut self, count: usize, interval: Duration) -> Result<usize> {
+        self.connection.ping(count, interval, <[u8]>::to_vec)
+    }
+
+    fn watch(&mut self) -> Result<()> {
+        self.connection.watch()
+    }
+}
+
+/// Sysex messages requesting the value of a parameter.
+/// The fields of every mode of a modal parameter are requested, as its mode is not known yet.
+fn encode_query(schema: &schema::Device, param_str: &str) -> Result<Vec<Vec<u8>>> {
+    let param = schema.param(param_str)?;
+    let index: Vec<u8> = param.index.into_iter().collect();
+    let mut query_codes = vec![sysex_query_code(&param)];
+    for (_, mode) in param.modes() {
+        for field in mode.fields.values() {
+            let query_code = field.sysex.get(Form::Query);
+            if !query_codes.contains(&query_code) {
+                query_codes.push(query_code);
+            }
+        }
+    }
+    Ok(query_codes
+        .iter()
+        .map(|query_code| sysex(&schema.header(), &[query_code, &index], schema.checksum))
+        .collect())
+}
+
+/// Sysex messages setting a parameter
+fn encode(schema: &schema::Device, param_str: &str, value_ids: &[String]) -> Result<Vec<Vec<u8>>> {
+    let param = schema.param(param_str)?;
+    let bodies = if param.schema.modes.is_some() {
+        devices::modal_codes(&param, value_ids)?
+    } else {
+        let index: Vec<u8> = param.index.into_iter().collect();
+        let bcodes = devices::bound_codes(&param, value_ids)?;
+        vec![[sysex_data_code(&param), &index, &bcodes].concat()]
+    };
+    Ok(bodies
+        .iter()
+        .map(|body| sysex(&schema.header(), &[body], schema.checksum))
+        .collect())
+}
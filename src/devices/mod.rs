@@ -1,38 +1,121 @@
 use std::iter::Iterator;
 
+use midir::MidiInput;
 use midir::MidiOutput;
-use midir::{MidiInput, MidiInputConnection};
 
-//mod beatstep;
-mod microbrute;
+pub mod backend;
+mod brute;
+mod controller;
+mod nrpn;
+#[cfg(feature = "async")]
+pub mod task;
+#[cfg(feature = "usb")]
+pub mod usb;
 
+use serde::Serialize;
 use snafu::Snafu;
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::sleep;
+use tracing::{debug, trace, warn};
 
+use linked_hash_map::LinkedHashMap;
 use std::error::Error;
 use strum::IntoEnumIterator;
-use linked_hash_map::LinkedHashMap;
+
+use crate::schema::{
+    self, Bounds, Checksum, Firmware, Firmwares, Form, Fragments, Mode, NoteSeq, Param, Protocol,
+};
+use backend::{EchoFilter, MidiBackend, MidiListener, MidiSender, MidirBackend};
 
 pub const CLIENT_NAME: &str = "LaBruteForce";
 
+/// A rest step of a sequence
+pub const REST: &str = "_";
+/// A step of a sequence holding the note of the previous step
+pub const TIE: &str = "-";
+
 pub type Result<T> = ::std::result::Result<T, Box<dyn ::std::error::Error>>;
 
-pub type MidiValue = u8;
+/// Wait before the first retry of unanswered queries, doubled for each following one
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Attempts at reopening the port of a device that went away, the first after `RECONNECT_DELAY`,
+/// then each twice as long after the previous one
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_DELAY: Duration = Duration::from_millis(250);
 
-static ARTURIA: &[u8] = &[0x00, 0x20, 0x6b];
 static REALTIME: u8 = 0x7e;
 static IDENTITY_REPLY: &[u8] = &[REALTIME, 0x01, 0x06, 0x02];
+/// Universal Identity Request, to all devices on a port
+static IDENTITY_REQUEST: &[u8] = &[0xf0, REALTIME, 0x7f, 0x06, 0x01, 0xf7];
+
+/// Contents of a Universal Identity Reply
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub manufacturer: Vec<u8>,
+    pub family: u16,
+    pub model: u16,
+    pub firmware: [u8; 4],
+}
+
+impl Identity {
+    /// Parse the payload following the `IDENTITY_REPLY` header
+    pub fn parse(msg: &[u8]) -> Option<Identity> {
+        // manufacturer ids are one byte, or three bytes starting with 0
+        let id_len = if msg.first() == Some(&0) { 3 } else { 1 };
+        if msg.len() < id_len + 8 {
+            return None;
+        }
+        let body = &msg[id_len..];
+        let mut firmware = [0; 4];
+        firmware.copy_from_slice(&body[4..8]);
+        Some(Identity {
+            manufacturer: msg[..id_len].to_vec(),
+            family: u16::from(body[0]) | u16::from(body[1]) << 7,
+            model: u16::from(body[2]) | u16::from(body[3]) << 7,
+            firmware,
+        })
+    }
+
+    /// Whether the identity is that of a device, by its manufacturer and identity codes
+    pub fn is(&self, device: &schema::Device) -> bool {
+        self.manufacturer == device.manufacturer
+            && device
+                .identity
+                .is_none_or(|codes| self.family == codes.family && self.model == codes.model)
+    }
+
+    pub fn firmware_version(&self) -> String {
+        let parts: Vec<String> = self.firmware.iter().map(u8::to_string).collect();
+        parts.join(".")
+    }
+}
 
 pub struct MidiNote {
     note: u8,
 }
 
+impl MidiNote {
+    /// The note of a MIDI note number, from C0 at 12 to G9 at 127
+    pub fn from_number(note: u8) -> Option<MidiNote> {
+        if (12..0x80).contains(&note) {
+            Some(MidiNote { note })
+        } else {
+            None
+        }
+    }
+
+    pub fn number(&self) -> u8 {
+        self.note
+    }
+}
+
 impl Display for MidiNote {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let oct = (self.note - 12) / 12;
@@ -77,7 +160,7 @@ impl FromStr for MidiNote {
             item = iter.next();
             if let Some(sharp) = item {
                 if sharp == '#' {
-                    note = note + 1;
+                    note += 1;
                     item = iter.next();
                 }
             }
@@ -111,231 +194,2924 @@ pub fn output_ports(midi_client: &MidiOutput) -> Vec<MidiPort> {
     v
 }
 
-fn input_port(midi: &MidiInput, name4: &str) -> Option<MidiPort> {
+pub fn input_ports(midi: &MidiInput) -> Vec<MidiPort> {
+    let mut v = vec![];
     for number in 0..midi.port_count() {
         if let Ok(name) = midi.port_name(number) {
-            if name4.eq(&name) {
-                return Some(MidiPort { name, number });
+            v.push(MidiPort { name, number })
+        }
+    }
+    v
+}
+
+/// Whether an output port can be opened, it may be in use by another program
+pub fn can_open_output(port: &MidiPort) -> bool {
+    MidiOutput::new(CLIENT_NAME)
+        .map(|midi| midi.connect(port.number, "Probe").is_ok())
+        .unwrap_or(false)
+}
+
+/// Whether an input port can be opened, it may be in use by another program
+pub fn can_open_input(port: &MidiPort) -> bool {
+    MidiInput::new(CLIENT_NAME)
+        .map(|midi| midi.connect(port.number, "Probe", |_, _, _| {}, ()).is_ok())
+        .unwrap_or(false)
+}
+
+/// Find a port by exact name, or else by name prefix
+pub fn find_port(ports: Vec<MidiPort>, name: &str) -> Option<MidiPort> {
+    if let Some(port) = ports.iter().find(|p| p.name == name) {
+        return Some(port.clone());
+    }
+    ports.into_iter().find(|p| p.name.starts_with(name))
+}
+
+/// The port of a device that may have been plugged back in: the one of the same name, or else
+/// the first one the device's schema matches
+fn relocate(ports: Vec<MidiPort>, schema: &schema::Device, name: &str) -> Option<MidiPort> {
+    if let Some(port) = ports.iter().find(|p| p.name == name) {
+        return Some(port.clone());
+    }
+    ports.into_iter().find(|p| schema.matches_port(&p.name))
+}
+
+/// Open the output port of a device again after sending to it failed, as when it was unplugged
+/// and plugged back. `port_name` is updated if the port came back under another name.
+pub fn reconnect(
+    backend: &dyn MidiBackend,
+    schema: &schema::Device,
+    port_name: &mut String,
+) -> Result<Box<dyn MidiSender>> {
+    let mut delay = RECONNECT_DELAY;
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        sleep(delay);
+        delay *= 2;
+        if let Some(port) = relocate(backend.output_ports(), schema, port_name) {
+            match backend.connect_output(&port) {
+                Ok(connection) => {
+                    if port.name != *port_name {
+                        warn!("{} is back as {}", port_name, port.name);
+                    }
+                    *port_name = port.name;
+                    return Ok(connection);
+                }
+                Err(err) => debug!("reconnection attempt {} failed: {}", attempt, err),
             }
         }
     }
-    None
+    Err(Box::new(DeviceError::NoOutputPort {
+        port_name: port_name.clone(),
+    }))
 }
 
-pub fn sysex_query_init<D>(
+/// Keep listening to the input port of a device until interrupted, through `listen`. If the port
+/// goes away, listening starts again once it is back, possibly under another name.
+pub fn keep_listening<F>(
+    backend: &dyn MidiBackend,
+    schema: &schema::Device,
     port_name: &str,
-    match_header: &'static [u8],
-    decode: D,
-) -> Result<SysexQuery>
+    mut listen: F,
+) -> Result<()>
 where
-    D: Fn(&[u8], &mut LinkedHashMap<String, Vec<String>>) + Send + 'static,
+    F: FnMut(&str) -> Result<SysexQuery>,
 {
-    let midi_in = MidiInput::new(CLIENT_NAME)?;
-    if let Some(in_port) = input_port(&midi_in, port_name) {
-        Ok(SysexQuery(midi_in.connect(
-            in_port.number,
-            "Query Results",
-            move |_ts, message, result_map| {
-                if message[0] == 0xf0
-                    && message[message.len() - 1] == 0xf7
-                    && message[1..].starts_with(match_header)
-                {
-                    let subslice = &message[match_header.len() + 1..message.len() - 1];
-                    decode(subslice, result_map);
+    let mut port_name = port_name.to_string();
+    let mut listener = Some(listen(&port_name)?);
+    loop {
+        sleep(Duration::from_secs(1));
+        let ports = backend.input_ports();
+        if listener.is_some() {
+            if !ports.iter().any(|port| port.name == port_name) {
+                warn!("{} went away, waiting for it to come back", port_name);
+                listener = None;
+            }
+        } else if let Some(port) = relocate(ports, schema, &port_name) {
+            match listen(&port.name) {
+                Ok(reopened) => {
+                    warn!("{} is back", port.name);
+                    port_name = port.name;
+                    listener = Some(reopened);
                 }
-            },
-            LinkedHashMap::new(),
-        )?))
-    } else {
-        Err(Box::new(DeviceError::NoInputPort {
-            port_name: port_name.to_string(),
-        }))
+                Err(err) => debug!("could not listen to {} yet: {}", port.name, err),
+            }
+        }
     }
 }
 
-pub struct SysexQuery(MidiInputConnection<LinkedHashMap<String, Vec<String>>>);
+/// Name of the input port replies come from, for a device on output port `out_name`: the
+/// `in_port` override if given, else the input port of the same name, else the first one
+/// starting with the schema's `input_port_prefix`
+pub fn matching_input_port(
+    backend: &dyn MidiBackend,
+    schema: &schema::Device,
+    out_name: &str,
+    in_port: Option<&str>,
+) -> String {
+    let ports = backend.input_ports();
+    let found = match in_port {
+        Some(in_port) => find_port(ports, in_port),
+        None if ports.iter().any(|port| port.name == out_name) => None,
+        None => schema.input_port_prefix.as_ref().and_then(|prefix| {
+            ports
+                .into_iter()
+                .find(|port| port.name.starts_with(prefix.as_str()))
+        }),
+    };
+    match found {
+        Some(port) => port.name,
+        None => in_port.unwrap_or(out_name).to_string(),
+    }
+}
 
-impl SysexQuery {
-    pub fn close_wait(self, wait_millis: u64) -> LinkedHashMap<String, Vec<String>> {
-        sleep(Duration::from_millis(wait_millis));
-        self.0.close().1
+/// Print every sysex message received on an input port, until interrupted
+pub fn sniff(port_name: &str) -> Result<()> {
+    let midi_in = MidiInput::new(CLIENT_NAME)?;
+    let in_port =
+        find_port(input_ports(&midi_in), port_name).ok_or_else(|| DeviceError::NoInputPort {
+            port_name: port_name.to_string(),
+        })?;
+    let descriptors: Vec<Box<dyn Descriptor + Send>> =
+        DeviceType::iter().map(|dev| dev.descriptor()).collect();
+    let mut stream = backend::SysexStream::default();
+    let _conn = midi_in.connect(
+        in_port.number,
+        "Sniffer",
+        move |ts, bytes, _| {
+            for message in stream.push(bytes) {
+                if message.first() != Some(&0xf0) {
+                    continue;
+                }
+                println!(
+                    "{:>10.3} {}",
+                    ts as f64 / 1_000_000.0,
+                    hex::encode(&message)
+                );
+                for descriptor in &descriptors {
+                    for (param, value) in descriptor.decode(&message) {
+                        println!("{:>10} {} {}", "", param, value.join(" "));
+                    }
+                }
+            }
+        },
+        (),
+    )?;
+    loop {
+        sleep(Duration::from_secs(1));
     }
 }
 
-#[derive(Debug, EnumString, IntoStaticStr, EnumIter, Display)]
-pub enum DeviceType {
-    MicroBrute,
-    //    BeatStep,
+/// Values decoded from a sysex message, or why they could not be
+pub type Decoded = std::result::Result<LinkedHashMap<String, Vec<String>>, String>;
+
+/// What a sysex message says, for each kind of device whose header it starts with
+pub fn decode_any(message: &[u8]) -> Vec<(DeviceType, Decoded)> {
+    DeviceType::iter()
+        .filter(|dev| {
+            let schema = dev.schema();
+            schema.protocol == Protocol::Sysex
+                && message.first() == Some(&0xf0)
+                && message[1..].starts_with(&schema.header())
+        })
+        .map(|dev| {
+            let values = dev.descriptor().decode(message);
+            if values.is_empty() {
+                let reason = undecoded(dev.schema(), message);
+                (dev, Err(reason))
+            } else {
+                (dev, Ok(values))
+            }
+        })
+        .collect()
 }
 
-impl DeviceType {
-    pub fn descriptor(&self) -> Box<dyn Descriptor> {
-        match self {
-            DeviceType::MicroBrute => Box::new(microbrute::MicroBruteDescriptor {}),
-            //            DeviceType::BeatStep => Box::new(beatstep::BeatStepDescriptor {}),
+/// Why a message starting with a device's header carries no value
+fn undecoded(schema: &schema::Device, message: &[u8]) -> String {
+    let start = schema.header().len() + 1;
+    if message.len() <= start || message.last() != Some(&0xf7) {
+        return "incomplete message".to_string();
+    }
+    let mut payload = &message[start..message.len() - 1];
+    if let Some(checksum) = schema.checksum {
+        match checksum.verify(payload) {
+            Some(verified) => payload = verified,
+            None => return "bad checksum".to_string(),
+        }
+    }
+    let reply = match schema.reply {
+        Some(reply) => reply,
+        None => return "replies are not described by the schema".to_string(),
+    };
+    match reply_param(schema, payload) {
+        Some((param, _, data)) => format!("invalid {} value {}", param, hex::encode(data)),
+        None => format!(
+            "no parameter matches byte {}",
+            start + reply.sysex_pos.unwrap_or(reply.code_pos)
+        ),
+    }
+}
+
+/// Send sysex messages to an output port, pausing between messages
+pub fn send_raw(port_name: &str, messages: &[Vec<u8>], delay: Duration) -> Result<()> {
+    let midi_out = MidiOutput::new(CLIENT_NAME)?;
+    let port =
+        find_port(output_ports(&midi_out), port_name).ok_or_else(|| DeviceError::NoOutputPort {
+            port_name: port_name.to_string(),
+        })?;
+    let mut conn = midi_out.connect(port.number, &port.name)?;
+    for (i, message) in messages.iter().enumerate() {
+        if i > 0 {
+            sleep(delay);
         }
+        debug!("→ {} {}", port.name, hex::encode(message));
+        conn.send(message)?;
     }
+    Ok(())
 }
 
-pub trait Descriptor {
-    fn globals(&self) -> Vec<String>;
-    fn bounds(&self, param: &str) -> Result<Bounds>;
-    fn ports(&self) -> Vec<MidiPort>;
-    fn connect(&self, midi_client: MidiOutput, port: &MidiPort) -> Result<Box<dyn Device>>;
+/// Record sysex messages from an input port, after sending an optional trigger message
+/// to the output port of the same name. Stops when no message arrived for `idle`.
+pub fn receive_raw(
+    port_name: &str,
+    trigger: Option<&[u8]>,
+    idle: Duration,
+) -> Result<Vec<Vec<u8>>> {
+    let midi_in = MidiInput::new(CLIENT_NAME)?;
+    let in_port =
+        find_port(input_ports(&midi_in), port_name).ok_or_else(|| DeviceError::NoInputPort {
+            port_name: port_name.to_string(),
+        })?;
+    let (tx, rx) = mpsc::channel();
+    let _conn = midi_in.connect(
+        in_port.number,
+        "Receive",
+        move |ts, message, _| {
+            debug!("← {} {}", ts, hex::encode(message));
+            if message.first() == Some(&0xf0) {
+                let _ = tx.send(message.to_vec());
+            }
+        },
+        (),
+    )?;
+    if let Some(trigger) = trigger {
+        send_raw(&in_port.name, &[trigger.to_vec()], Duration::from_millis(0))?;
+    }
+    let mut messages = vec![];
+    while let Ok(message) = rx.recv_timeout(idle) {
+        messages.push(message);
+    }
+    Ok(messages)
 }
 
-pub trait Parameter {
-    //    fn from_sysex(message: &[u8]) -> &Parameter;
-    //    fn from_str(name: &str) -> &Parameter;
+/// Identity of the device on each output port, from its reply on the input port of the
+/// same name. Every port is asked at once, and replies are awaited for `timeout`.
+pub fn scan(timeout: Duration) -> Result<Vec<(MidiPort, Option<Identity>)>> {
+    let midi_out = MidiOutput::new(CLIENT_NAME)?;
+    Ok(scan_ports(output_ports(&midi_out), timeout))
 }
 
-pub trait Device {
-    fn query(&mut self, params: &[String]) -> Result<LinkedHashMap<String, Vec<String>>>;
-    fn update(&mut self, param: &str, value_ids: &[String]) -> Result<()>;
+/// Identity of the device on each of some output ports, like `scan`
+pub fn scan_ports(ports: Vec<MidiPort>, timeout: Duration) -> Vec<(MidiPort, Option<Identity>)> {
+    static ID_KEY: &str = "ID";
+    let mut queries = vec![];
+    for port in ports {
+        let query = sysex_query_init(
+            &MidirBackend,
+            &port.name,
+            IDENTITY_REPLY,
+            None,
+            None,
+            |msg, result| {
+                let _ = result.insert(ID_KEY.to_string(), vec![hex::encode(msg)]);
+            },
+        )
+        .and_then(|query| {
+            send_raw(
+                &port.name,
+                &[IDENTITY_REQUEST.to_vec()],
+                Duration::from_millis(0),
+            )?;
+            Ok(query)
+        });
+        match query {
+            Ok(query) => queries.push((port, Some(query))),
+            Err(err) => {
+                debug!("not scanning {}: {}", port.name, err);
+                queries.push((port, None));
+            }
+        }
+    }
+    sleep(timeout);
+    queries
+        .into_iter()
+        .map(|(port, query)| {
+            let identity = query
+                .and_then(|query| query.close().remove(ID_KEY))
+                .and_then(|reply| hex::decode(&reply[0]).ok())
+                .and_then(|msg| Identity::parse(&msg));
+            (port, identity)
+        })
+        .collect()
 }
 
-#[derive(Debug, Clone)]
-pub enum Bounds {
-    /// List of raw value and display name pairs
-    Discrete(Vec<(MidiValue, &'static str)>),
+/// Name of the MIDI backend midir was built with
+pub fn backend_name() -> &'static str {
+    if cfg!(feature = "jack") {
+        "JACK"
+    } else if cfg!(target_os = "linux") {
+        "ALSA"
+    } else if cfg!(target_os = "macos") {
+        "CoreMIDI"
+    } else if cfg!(target_os = "windows") {
+        "WinMM"
+    } else {
+        "unknown"
+    }
+}
 
-    /// Raw value offset and display value bounds (Low to High, inclusive)
-    Range(u8, (MidiValue, MidiValue)),
+/// Known sysex related limitations of the current backend
+pub fn backend_limitations() -> &'static [&'static str] {
+    if cfg!(feature = "jack") {
+        &["JACK MIDI ports are separate from ALSA ports, devices may need a2jmidid to appear"]
+    } else if cfg!(target_os = "windows") {
+        &[
+            "sysex input buffer is limited to 1024 bytes, longer messages are truncated",
+            "ports can not be opened by more than one program at a time",
+        ]
+    } else {
+        &[]
+    }
+}
 
-    /// Sequence of notes with offset from std MIDI note value
-    NoteSeq(u8),
+/// The backend is chosen at build time, fail if another one is requested
+pub fn check_backend(name: &str) -> Result<()> {
+    if name.eq_ignore_ascii_case(backend_name()) {
+        Ok(())
+    } else {
+        Err(Box::new(DeviceError::BackendUnavailable {
+            backend: name.to_string(),
+            current: backend_name().to_string(),
+        }))
+    }
 }
 
-#[derive(Debug, Snafu)]
-pub enum DeviceError {
-    UnknownDevice {
-        device_name: String,
-    },
-    UnknownParameter {
-        param_name: String,
-    },
-    EmptyParameter,
-    UnknownValue {
-        value_name: String,
-    },
-    NoConnectedDevice {
-        device_name: String,
-    },
-    NoOutputPort {
-        port_name: String,
-    },
-    NoInputPort {
-        port_name: String,
-    },
-    InvalidParam {
-        device_name: String,
-        param_name: String,
-    },
-    NoValueReceived,
-    ValueOutOfBound {
-        value_name: String,
-    },
-    NoIdentificationReply,
-    WrongId {
-        id: Vec<u8>,
-    },
-    NoteParse {
-        note: String,
-    },
-    MissingValue {
-        param_name: String,
-    },
-    TooManyValues {
-        param_name: String,
-    },
-    ReadSizeError,
+/// Reassembles logical messages that a device splits across multiple sysex messages
+pub struct Joiner {
+    fragments: Fragments,
+    pending: Vec<u8>,
 }
 
-pub fn bound_str(bounds: Bounds, vcode: &[u8]) -> Option<String> {
-    if let Some(first) = vcode.get(0) {
-        match bounds {
-            Bounds::Discrete(values) => {
-                for v in &values {
-                    if v.0 == *first {
-                        return Some(v.1.to_string());
+impl Joiner {
+    pub fn new(fragments: Fragments) -> Self {
+        Joiner {
+            fragments,
+            pending: vec![],
+        }
+    }
+
+    /// Returns the complete payload once its last fragment has been received
+    pub fn push(&mut self, payload: &[u8]) -> Option<Vec<u8>> {
+        let marker_pos = self.fragments.marker_pos;
+        if payload.len() <= marker_pos || payload.len() < self.fragments.data_pos {
+            // not a fragment
+            return Some(payload.to_vec());
+        }
+        if !self.pending.is_empty() && !self.continues(payload) {
+            // the last fragment of the pending message was lost
+            warn!("dropping incomplete reply {}", hex::encode(&self.pending));
+            self.pending.clear();
+        }
+        if self.pending.is_empty() {
+            self.pending.extend_from_slice(payload);
+        } else {
+            self.pending
+                .extend_from_slice(&payload[self.fragments.data_pos..]);
+        }
+        if payload[marker_pos] == self.fragments.more {
+            None
+        } else {
+            self.pending[marker_pos] = payload[marker_pos];
+            Some(self.pending.split_off(0))
+        }
+    }
+
+    /// Whether a fragment follows the pending ones, repeating the bytes preceding their data,
+    /// such as the message id, but for the marker
+    fn continues(&self, payload: &[u8]) -> bool {
+        let head = &payload[..self.fragments.data_pos];
+        head.iter()
+            .zip(&self.pending)
+            .enumerate()
+            .all(|(pos, (byte, pending))| pos == self.fragments.marker_pos || byte == pending)
+    }
+}
+
+pub fn sysex_query_init<D>(
+    backend: &dyn MidiBackend,
+    port_name: &str,
+    match_header: &[u8],
+    fragments: Option<Fragments>,
+    checksum: Option<Checksum>,
+    decode: D,
+) -> Result<SysexQuery>
+where
+    D: Fn(&[u8], &mut LinkedHashMap<String, Vec<String>>) + Send + 'static,
+{
+    let mut joiner = fragments.map(Joiner::new);
+    let match_header = match_header.to_vec();
+    let (tx, replies) = mpsc::channel();
+    let values = Arc::new(Mutex::new(LinkedHashMap::new()));
+    let result_map = values.clone();
+    let listener = backend.listen(
+        port_name,
+        Box::new(move |ts, message| {
+            debug!("← {} {}", ts, hex::encode(message));
+            if message[0] == 0xf0
+                && message[message.len() - 1] == 0xf7
+                && message[1..].starts_with(&match_header)
+            {
+                let mut subslice = &message[match_header.len() + 1..message.len() - 1];
+                if let Some(checksum) = checksum {
+                    match checksum.verify(subslice) {
+                        Some(payload) => subslice = payload,
+                        None => {
+                            warn!("dropping reply with bad checksum {}", hex::encode(message));
+                            return;
+                        }
                     }
                 }
-            }
-            Bounds::Range(offset, (lo, hi)) => {
-                if *first >= lo && *first <= hi {
-                    return Some((*first + offset).to_string());
+                let mut result_map = result_map.lock().unwrap();
+                match joiner.as_mut() {
+                    Some(joiner) => match joiner.push(subslice) {
+                        Some(joined) => decode(&joined, &mut result_map),
+                        None => return,
+                    },
+                    None => decode(subslice, &mut result_map),
                 }
+                let _ = tx.send(());
             }
-            Bounds::NoteSeq(offset) => {
-                return Some(
-                    vcode
-                        .iter()
-                        .map(|note| {
-                            MidiNote {
-                                note: (*note - offset),
-                            }
-                            .to_string()
-                        })
-                        .collect::<Vec<String>>()
-                        .join(","),
+        }),
+    )?;
+    Ok(SysexQuery {
+        listener,
+        values,
+        replies,
+    })
+}
+
+pub struct SysexQuery {
+    listener: Box<dyn MidiListener>,
+    values: Arc<Mutex<LinkedHashMap<String, Vec<String>>>>,
+    /// Signaled as each reply is decoded
+    replies: mpsc::Receiver<()>,
+}
+
+impl SysexQuery {
+    /// Close once `expected` replies have been decoded, or when `timeout` has elapsed
+    pub fn close_wait(
+        self,
+        expected: usize,
+        timeout: Duration,
+    ) -> LinkedHashMap<String, Vec<String>> {
+        wait_replies(&self.replies, expected, timeout);
+        self.close()
+    }
+
+    /// Close with the replies decoded so far
+    pub fn close(self) -> LinkedHashMap<String, Vec<String>> {
+        drop(self.listener);
+        let mut values = self.values.lock().unwrap();
+        std::mem::replace(&mut *values, LinkedHashMap::new())
+    }
+}
+
+/// Wait for `expected` signals, or until `timeout` has elapsed. Returns how many were received.
+pub fn wait_replies(replies: &mpsc::Receiver<()>, expected: usize, timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    let mut received = 0;
+    while received < expected {
+        match replies.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(()) => received += 1,
+            Err(_) => break,
+        }
+    }
+    received
+}
+
+/// Matches replies to the queries they answer by the message id they repeat, so that missing,
+/// duplicate or unexpected replies are reported rather than mistaken for other values
+#[derive(Clone, Default)]
+pub struct Correlator(Arc<Mutex<Queries>>);
+
+#[derive(Default)]
+struct Queries {
+    /// Parameter queried by each message id, and whether it was answered
+    sent: LinkedHashMap<u8, (String, bool)>,
+    /// Queries whose message id was reused before they were answered
+    lost: Vec<String>,
+}
+
+impl Correlator {
+    /// Expect a reply about a parameter to the message with this id
+    pub fn expect(&self, msg_id: u8, param: String) {
+        let mut queries = self.0.lock().expect("queries");
+        if let Some((lost, false)) = queries.sent.insert(msg_id, (param, false)) {
+            queries.lost.push(lost);
+        }
+    }
+
+    /// Whether a reply answers a pending query, warning about those that do not
+    pub fn accept(&self, msg_id: Option<u8>, param: Option<String>) -> bool {
+        let msg_id = match msg_id {
+            Some(msg_id) => msg_id,
+            None => {
+                warn!("dropping reply without message id");
+                return false;
+            }
+        };
+        let mut queries = self.0.lock().expect("queries");
+        match queries.sent.get_mut(&msg_id) {
+            None => warn!("dropping reply to unknown message {}", msg_id),
+            Some((_, true)) => warn!("dropping duplicate reply to message {}", msg_id),
+            Some((expected, answered)) => {
+                *answered = true;
+                if param.as_ref() == Some(expected) {
+                    return true;
+                }
+                warn!(
+                    "dropping reply to message {} about {}, expected {}",
+                    msg_id,
+                    param.as_deref().unwrap_or("unknown parameter"),
+                    expected
                 );
             }
         }
+        false
+    }
+
+    /// Parameters whose queries were not answered
+    pub fn missing(&self) -> Vec<String> {
+        let queries = self.0.lock().expect("queries");
+        let mut missing = queries.lost.clone();
+        for (param, answered) in queries.sent.values() {
+            if !answered && !missing.contains(param) {
+                missing.push(param.clone());
+            }
+        }
+        missing
     }
-    None
 }
 
-pub fn bound_codes(bounds: Bounds, bound_ids: &[String], reqs: (usize, usize)) -> Result<Vec<u8>> {
-    if bound_ids.len() < reqs.0 {
-        return Err(Box::new(DeviceError::MissingValue {
-            param_name: "param".to_string(),
-        }));
+#[derive(Debug, EnumString, IntoStaticStr, EnumIter, Display)]
+pub enum DeviceType {
+    MicroBrute,
+    BeatStep,
+    BeatStepPro,
+    KeyStep37,
+    KeyStepPro,
+    MiniBrute2,
+    MiniBrute2S,
+    DrumBrute,
+    DrumBruteImpact,
+    MicroFreak,
+    MatrixBrute,
+    PolyBrute,
+    MiniLabMk2,
+    KeyLabMk2,
+    KeyLabEssential,
+    BassStation2,
+    Minilogue,
+    Monologue,
+    Mopho,
+}
+
+/// Category of an error, reported by the exit code of the program
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Other = 1,
+    NotConnected = 3,
+    UnknownName = 4,
+    InvalidValue = 5,
+    DeviceReply = 6,
+    File = 7,
+}
+
+impl ErrorKind {
+    pub fn of(err: &(dyn Error + 'static)) -> ErrorKind {
+        if let Some(err) = err.downcast_ref::<DeviceError>() {
+            err.kind()
+        } else if err.is::<strum::ParseError>() {
+            ErrorKind::UnknownName
+        } else if err.is::<std::num::ParseIntError>() || err.is::<hex::FromHexError>() {
+            ErrorKind::InvalidValue
+        } else if err.is::<std::io::Error>()
+            || err.is::<serde_yaml::Error>()
+            || err.is::<toml::de::Error>()
+        {
+            ErrorKind::File
+        } else {
+            ErrorKind::Other
+        }
     }
-    if bound_ids.len() > reqs.1 {
-        return Err(Box::new(DeviceError::TooManyValues {
-            param_name: "param".to_string(),
-        }));
+}
+
+impl DeviceError {
+    pub fn kind(&self) -> ErrorKind {
+        use DeviceError::*;
+        match self {
+            NoConnectedDevice { .. }
+            | NoOutputPort { .. }
+            | NoInputPort { .. }
+            | InputPortRequired { .. }
+            | BackendUnavailable { .. } => ErrorKind::NotConnected,
+            UnknownDevice { .. }
+            | UnknownParameter { .. }
+            | NotASequence { .. }
+            | UnknownField { .. } => ErrorKind::UnknownName,
+            EmptyParameter
+            | UnknownValue { .. }
+            | ValueOutOfBound { .. }
+            | ConstraintViolated { .. }
+            | NoteParse { .. }
+            | InvalidStep { .. }
+            | MissingValue { .. }
+            | TooManyValues { .. }
+            | EmptyInput
+            | InputTooLong { .. }
+            | InvalidCharacter { .. }
+            | CurrentValueRequired { .. } => ErrorKind::InvalidValue,
+            NoValueReceived | NoIdentificationReply | RestoreFailed { .. } => {
+                ErrorKind::DeviceReply
+            }
+            SyxFormat { .. }
+            | MidiFileFormat { .. }
+            | WrongDevice { .. }
+            | SchemaInvalid { .. }
+            | SchemaInclude { .. } => ErrorKind::File,
+            NothingToUndo | Cancelled | QueryUnsupported { .. } => ErrorKind::Other,
+        }
     }
-    match bounds {
-        Bounds::Discrete(values) => {
-            let b_id = bound_ids.get(0).unwrap();
-            for v in &values {
-                if v.1.eq(b_id) {
-                    return Ok(vec![v.0]);
+}
+
+impl DeviceType {
+    /// Parse a device name with an optional unit number, e.g. `MicroBrute/2` for the second
+    /// connected MicroBrute. Units are numbered from 1 and default to 1.
+    pub fn from_name(name: &str) -> Result<(DeviceType, usize)> {
+        let mut parts = name.splitn(2, '/');
+        let schema = schema::device(parts.next().unwrap_or_default())?;
+        let dev_type =
+            DeviceType::from_str(&schema.name).map_err(|_| DeviceError::UnknownDevice {
+                device_name: name.to_string(),
+                hint: Hint::default(),
+            })?;
+        let unit = match parts.next() {
+            Some(unit) => match usize::from_str(unit) {
+                Ok(unit) if unit > 0 => unit,
+                _ => {
+                    return Err(Box::new(DeviceError::UnknownDevice {
+                        device_name: name.to_string(),
+                        hint: Hint::default(),
+                    }))
                 }
+            },
+            None => 1,
+        };
+        Ok((dev_type, unit))
+    }
+
+    /// Ports of all connected units of this device type, in unit order
+    pub fn ports(&self) -> Vec<MidiPort> {
+        let mut ports = self.descriptor().ports();
+        ports.sort_by(|a, b| a.name.cmp(&b.name));
+        ports
+    }
+
+    pub fn schema(&self) -> &'static schema::Device {
+        schema::device(self.into()).expect("device schema")
+    }
+
+    pub fn descriptor(&self) -> Box<dyn Descriptor + Send> {
+        let name = self.into();
+        match self {
+            DeviceType::MicroBrute
+            | DeviceType::MiniBrute2
+            | DeviceType::MiniBrute2S
+            | DeviceType::DrumBrute
+            | DeviceType::DrumBruteImpact
+            | DeviceType::MicroFreak
+            | DeviceType::MatrixBrute => Box::new(brute::BruteDescriptor { name }),
+            DeviceType::BeatStep
+            | DeviceType::BeatStepPro
+            | DeviceType::KeyStep37
+            | DeviceType::KeyStepPro
+            | DeviceType::PolyBrute
+            | DeviceType::MiniLabMk2
+            | DeviceType::KeyLabMk2
+            | DeviceType::KeyLabEssential
+            | DeviceType::BassStation2
+            | DeviceType::Minilogue
+            | DeviceType::Monologue => Box::new(controller::ControllerDescriptor { name }),
+            DeviceType::Mopho => Box::new(nrpn::NrpnDescriptor { name }),
+        }
+    }
+}
+
+/// Settings of a connection given on the command line, overriding the device schema's
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    /// Input port replies are read from, instead of the one matching the output port
+    pub in_port: Option<String>,
+    /// How long to wait for replies
+    pub timeout: Option<Duration>,
+    /// Pause between consecutive messages
+    pub send_delay: Option<Duration>,
+}
+
+/// Keeps consecutive messages to a device at least `delay` apart
+pub struct Throttle {
+    delay: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(delay: Duration) -> Self {
+        Throttle { delay, last: None }
+    }
+
+    /// Wait until the next message can be sent
+    pub fn wait(&mut self) {
+        if let Some(last) = self.last {
+            let elapsed = last.elapsed();
+            if elapsed < self.delay {
+                sleep(self.delay - elapsed);
             }
-            Err(Box::new(DeviceError::UnknownValue {
-                value_name: b_id.to_owned(),
-            }))
         }
-        Bounds::Range(offset, (lo, hi)) => {
-            let b_id = bound_ids.get(0).unwrap();
-            let val = u8::from_str(b_id)?;
-            if val >= lo && val <= hi {
-                Ok(vec![val - offset])
-            } else {
-                Err(Box::new(DeviceError::ValueOutOfBound {
-                    value_name: b_id.to_owned(),
-                }))
+        self.last = Some(Instant::now());
+    }
+}
+
+/// Ports of a connected device, shared by the protocols: messages sent are spaced by a throttle
+/// and sent again once the device is back if it went away, and replies are read from the
+/// matching input port
+pub struct Connection {
+    pub schema: &'static schema::Device,
+    midi_connection: Box<dyn MidiSender>,
+    pub backend: Arc<dyn MidiBackend>,
+    port_name: String,
+    /// Input port replies are read from
+    pub in_port_name: String,
+    /// Whether replies from the device can be received
+    pub has_input: bool,
+    /// How long to wait for replies
+    pub timeout: Duration,
+    /// Spacing of the messages sent
+    throttle: Throttle,
+    pub identity: Option<Identity>,
+}
+
+impl Connection {
+    /// Open the output port of a device, without identifying it
+    pub fn open(
+        schema: &'static schema::Device,
+        backend: Arc<dyn MidiBackend>,
+        port: &MidiPort,
+        overrides: &Overrides,
+    ) -> Result<Self> {
+        let backend = EchoFilter::wrap(backend, schema.echoes_input);
+        Ok(Connection {
+            schema,
+            midi_connection: backend.connect_output(port)?,
+            in_port_name: matching_input_port(
+                backend.as_ref(),
+                schema,
+                &port.name,
+                overrides.in_port.as_deref(),
+            ),
+            backend,
+            port_name: port.name.to_owned(),
+            has_input: true,
+            timeout: overrides.timeout.unwrap_or_else(|| schema.reply_timeout()),
+            throttle: Throttle::new(overrides.send_delay.unwrap_or_else(|| schema.send_delay())),
+            identity: None,
+        })
+    }
+
+    /// Open the output port of a sysex device and identify it. A device without an input port
+    /// can still be written to.
+    pub fn identified(
+        schema: &'static schema::Device,
+        backend: Arc<dyn MidiBackend>,
+        port: &MidiPort,
+        overrides: &Overrides,
+    ) -> Result<Self> {
+        let mut connection = Connection::open(schema, backend, port, overrides)?;
+        match connection.identify() {
+            Ok(identity) => {
+                if !identity.is(schema) {
+                    warn!(
+                        "{} identifies as manufacturer {} family {:04x} model {:04x}, which is not a {}",
+                        port.name,
+                        hex::encode(&identity.manufacturer),
+                        identity.family,
+                        identity.model,
+                        schema.name
+                    );
+                }
+                connection.identity = Some(identity)
             }
+            Err(err) => match err.downcast_ref::<DeviceError>() {
+                Some(DeviceError::NoInputPort { .. }) => {
+                    warn!(
+                        "no MIDI input port for {}, device could not be identified and values can only be set",
+                        port.name
+                    );
+                    connection.has_input = false;
+                }
+                _ => return Err(err),
+            },
         }
-        Bounds::NoteSeq(offset) => {
-            let mut bcode = Vec::with_capacity(bound_ids.len());
-            for b_id in bound_ids {
-                bcode.push(MidiNote::from_str(b_id)?.note + offset);
+        Ok(connection)
+    }
+
+    pub fn send(&mut self, message: &[u8]) -> Result<()> {
+        self.throttle.wait();
+        debug!("→ {} {}", self.port_name, hex::encode(message));
+        if let Err(err) = self.midi_connection.send(message) {
+            warn!(
+                "could not send to {}: {}, reconnecting",
+                self.port_name, err
+            );
+            let old_name = self.port_name.clone();
+            self.midi_connection =
+                reconnect(self.backend.as_ref(), self.schema, &mut self.port_name)?;
+            if self.in_port_name == old_name {
+                self.in_port_name = self.port_name.clone();
             }
-            Ok(bcode)
+            self.midi_connection.send(message)?;
         }
+        Ok(())
     }
-}
 
-fn sysex(vendor: &[u8], parts: &[&[u8]]) -> Vec<u8> {
-    let mut msg = Vec::with_capacity(64);
-    msg.push(0xf0);
-    msg.extend_from_slice(vendor);
-    for p in parts {
-        msg.extend_from_slice(p);
+    pub fn require_input(&self) -> Result<()> {
+        if self.has_input {
+            Ok(())
+        } else {
+            Err(Box::new(DeviceError::InputPortRequired {
+                port_name: self.in_port_name.clone(),
+            }))
+        }
+    }
+
+    /// Ask the device for its identity, ignoring replies of other manufacturers' devices
+    pub fn identify(&mut self) -> Result<Identity> {
+        static ID_KEY: &str = "ID";
+        let manufacturer = self.schema.manufacturer.clone();
+        let sysex_replies = sysex_query_init(
+            self.backend.as_ref(),
+            &self.in_port_name,
+            IDENTITY_REPLY,
+            None,
+            None,
+            move |msg, result| {
+                if msg.starts_with(&manufacturer) {
+                    let _ = result.insert(ID_KEY.to_string(), vec![hex::encode(msg)]);
+                } else {
+                    debug!("ignoring identity reply {}", hex::encode(msg));
+                }
+            },
+        )?;
+        self.send(IDENTITY_REQUEST)?;
+        let reply = sysex_replies
+            .close_wait(1, self.timeout)
+            .remove(ID_KEY)
+            .ok_or(DeviceError::NoIdentificationReply)?;
+        Ok(Identity::parse(&hex::decode(&reply[0])?).ok_or(DeviceError::NoIdentificationReply)?)
+    }
+
+    /// Start collecting the sysex replies of the device on a port, passed to `decode`
+    pub fn listen<D>(&self, port_name: &str, decode: D) -> Result<SysexQuery>
+    where
+        D: Fn(&[u8], &mut LinkedHashMap<String, Vec<String>>) + Send + 'static,
+    {
+        self.require_input()?;
+        sysex_query_init(
+            self.backend.as_ref(),
+            port_name,
+            &self.schema.header(),
+            self.schema.fragments,
+            self.schema.checksum,
+            decode,
+        )
+    }
+
+    /// Count the replies to queries of the first parameter without index, sent `count` times
+    /// `interval` apart. `body` gives the sysex of a query, after the device's header, from the
+    /// parameter's query code.
+    pub fn ping<F>(&mut self, count: usize, interval: Duration, mut body: F) -> Result<usize>
+    where
+        F: FnMut(&[u8]) -> Vec<u8>,
+    {
+        static PING_KEY: &str = "PING";
+        let param = self
+            .schema
+            .params()
+            .into_iter()
+            .find(|p| p.index.is_none())
+            .ok_or(DeviceError::EmptyParameter)?;
+        let sysex_replies = self.listen(&self.in_port_name, |_msg, result| {
+            result
+                .entry(PING_KEY.to_string())
+                .or_insert_with(Vec::new)
+                .push(String::new());
+        })?;
+        let header = self.schema.header();
+        for _ in 0..count {
+            let body = body(param.schema.sysex.get(Form::Query));
+            self.send(&sysex(&header, &[&body], self.schema.checksum))?;
+            sleep(interval);
+        }
+        Ok(sysex_replies
+            .close_wait(count, self.timeout)
+            .get(PING_KEY)
+            .map_or(0, Vec::len))
+    }
+
+    /// Print the values sent by the device until interrupted
+    pub fn watch(&self) -> Result<()> {
+        self.require_input()?;
+        let schema = self.schema;
+        keep_listening(
+            self.backend.as_ref(),
+            schema,
+            &self.in_port_name,
+            |port_name| {
+                self.listen(port_name, move |msg, _result| {
+                    let mut values = LinkedHashMap::new();
+                    decode_reply(schema, msg, &mut values);
+                    for (param, value) in values {
+                        println!("{}: {}", param, value.join(" "));
+                    }
+                })
+            },
+        )
+    }
+}
+
+pub trait Descriptor {
+    fn ports(&self) -> Vec<MidiPort>;
+    /// Open a device on an output port, with settings overriding those of its schema
+    fn connect(
+        &self,
+        backend: Arc<dyn MidiBackend>,
+        port: &MidiPort,
+        overrides: &Overrides,
+    ) -> Result<Box<dyn Device>>;
+    /// Sysex messages that `Device::update` would send, with message ids starting at 0
+    fn encode(&self, param: &str, value_ids: &[String]) -> Result<Vec<Vec<u8>>>;
+    /// Sysex messages that `Device::query` would send, with message ids starting at 0
+    fn encode_query(&self, param: &str) -> Result<Vec<Vec<u8>>>;
+    /// Parameter values carried by a complete sysex message, if it was sent by this kind of device
+    fn decode(&self, message: &[u8]) -> LinkedHashMap<String, Vec<String>>;
+}
+
+pub trait Device: Send {
+    fn query(&mut self, params: &[String]) -> Result<LinkedHashMap<String, Vec<String>>>;
+    /// Query parameters, asking again up to `retries` times for those left unanswered,
+    /// waiting twice as long before each retry
+    fn query_retrying(
+        &mut self,
+        params: &[String],
+        retries: u32,
+    ) -> Result<LinkedHashMap<String, Vec<String>>> {
+        let mut values = self.query(params)?;
+        let mut delay = RETRY_DELAY;
+        for retry in 1..=retries {
+            let missing: Vec<String> = params
+                .iter()
+                .filter(|param| !values.contains_key(*param))
+                .cloned()
+                .collect();
+            if missing.is_empty() {
+                break;
+            }
+            sleep(delay);
+            delay *= 2;
+            debug!("retry {} of {}: {}", retry, retries, missing.join(" "));
+            for (param, value) in self.query(&missing)? {
+                if missing.contains(&param) {
+                    debug!("{} answered after {} retries", param, retry);
+                }
+                values.insert(param, value);
+            }
+        }
+        Ok(values)
+    }
+    fn update(&mut self, param: &str, value_ids: &[String]) -> Result<()>;
+    /// Identity reported by the device when connecting, if it could be read
+    fn identity(&self) -> Option<&Identity>;
+    /// Send `count` read-only queries `interval` apart, returning the number of replies received
+    fn ping(&mut self, count: usize, interval: Duration) -> Result<usize>;
+    /// Print parameter changes sent by the device until interrupted
+    fn watch(&mut self) -> Result<()>;
+}
+
+#[derive(Debug, Snafu)]
+pub enum DeviceError {
+    #[snafu(display("unknown device {}{}", device_name, hint))]
+    UnknownDevice { device_name: String, hint: Hint },
+    #[snafu(display("unknown parameter {}{}", param_name, hint))]
+    UnknownParameter { param_name: String, hint: Hint },
+    #[snafu(display("parameter has no value"))]
+    EmptyParameter,
+    #[snafu(display("{} is not a sequence", param_name))]
+    NotASequence { param_name: String },
+    #[snafu(display("unknown value {}{}", value_name, hint))]
+    UnknownValue { value_name: String, hint: Hint },
+    #[snafu(display("no connected device {}", device_name))]
+    NoConnectedDevice { device_name: String },
+    #[snafu(display("no MIDI output port {}", port_name))]
+    NoOutputPort { port_name: String },
+    #[snafu(display("no MIDI input port {}", port_name))]
+    NoInputPort { port_name: String },
+    #[snafu(display(
+        "No MIDI input port matching {}, values can not be read. Check that the device's input port is enabled and visible in `la_bruteforce ports`",
+        port_name
+    ))]
+    InputPortRequired { port_name: String },
+    #[snafu(display(
+        "MIDI backend {} is not available, this build uses {}. JACK support requires building with `--features jack`",
+        backend,
+        current
+    ))]
+    BackendUnavailable { backend: String, current: String },
+    #[snafu(display(
+        "{} values can not be read, it does not echo NRPN parameters",
+        device_name
+    ))]
+    QueryUnsupported { device_name: String },
+    #[snafu(display("no value received from device"))]
+    NoValueReceived,
+    #[snafu(display("value {} out of bounds", value_name))]
+    ValueOutOfBound { value_name: String },
+    #[snafu(display("device did not reply to identity request"))]
+    NoIdentificationReply,
+    #[snafu(display("invalid note {}", note))]
+    NoteParse { note: String },
+    #[snafu(display("invalid note {} at step {}", note, step))]
+    InvalidStep { note: String, step: usize },
+    #[snafu(display("missing value for {}", param_name))]
+    MissingValue { param_name: String },
+    #[snafu(display("too many values for {}", param_name))]
+    TooManyValues { param_name: String },
+    #[snafu(display("empty input"))]
+    EmptyInput,
+    #[snafu(display("input {} is longer than {} characters", input, max_len))]
+    InputTooLong { input: String, max_len: usize },
+    #[snafu(display("invalid character {} in {}", character, input))]
+    InvalidCharacter { input: String, character: String },
+    #[snafu(display("invalid .syx file at byte {}", pos))]
+    SyxFormat { pos: usize },
+    #[snafu(display("invalid MIDI file at byte {}", pos))]
+    MidiFileFormat { pos: usize },
+    #[snafu(display("file is for device {}, not {}", found, expected))]
+    WrongDevice { expected: String, found: String },
+    #[snafu(display("{} parameters could not be restored", failed))]
+    RestoreFailed { failed: usize },
+    #[snafu(display(
+        "relative value {} needs the current value read from the device, it cannot be used with --dry-run",
+        value_name
+    ))]
+    CurrentValueRequired { value_name: String },
+    #[snafu(display("no change to undo"))]
+    NothingToUndo,
+    #[snafu(display("cancelled"))]
+    Cancelled,
+    #[snafu(display("unknown field {} of mode {}{}", field_name, mode, hint))]
+    UnknownField {
+        field_name: String,
+        mode: String,
+        hint: Hint,
+    },
+    #[snafu(display("{} schema issues found", count))]
+    SchemaInvalid { count: usize },
+    #[snafu(display(
+        "{} {} is not valid while {} is {}",
+        param_name,
+        value_name,
+        other,
+        other_value
+    ))]
+    ConstraintViolated {
+        param_name: String,
+        value_name: String,
+        other: String,
+        other_value: String,
+    },
+    #[snafu(display("schema include {} not found, or included recursively", file))]
+    SchemaInclude { file: String },
+}
+
+/// Known names closest to a mistyped one, suggested after the error
+#[derive(Debug, Default)]
+pub struct Hint(Vec<String>);
+
+/// Most suggestions shown
+const MAX_HINTS: usize = 3;
+
+impl Hint {
+    /// Names from `known` that `input` is likely a typo or a prefix of, closest first
+    pub fn closest<'a>(input: &str, known: impl IntoIterator<Item = &'a str>) -> Hint {
+        let input = input.to_ascii_lowercase();
+        let mut near: Vec<(usize, &str)> = known
+            .into_iter()
+            .filter_map(|name| {
+                let lower = name.to_ascii_lowercase();
+                let distance = edit_distance(&input, &lower);
+                if distance <= (input.len() / 3).max(1) {
+                    Some((distance, name))
+                } else if !input.is_empty() && lower.starts_with(&input) {
+                    Some((lower.len() - input.len(), name))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        near.sort();
+        near.dedup_by(|a, b| a.1.eq_ignore_ascii_case(b.1));
+        Hint(
+            near.into_iter()
+                .take(MAX_HINTS)
+                .map(|(_, name)| name.to_string())
+                .collect(),
+        )
+    }
+}
+
+impl Display for Hint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0.as_slice() {
+            [] => Ok(()),
+            [name] => write!(f, ", did you mean {}?", name),
+            [names @ .., last] => write!(f, ", did you mean {} or {}?", names.join(", "), last),
+        }
+    }
+}
+
+/// Single character insertions, deletions and substitutions turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diag
+            } else {
+                1 + diag.min(above).min(row[j])
+            };
+            diag = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// The step of a sequence code: a rest, a tie, a note, or `?` and the code if it is below the
+/// lowest note
+fn note_str(seq: &NoteSeq, code: u8) -> String {
+    if Some(code) == seq.rest {
+        REST.to_string()
+    } else if Some(code) == seq.tie {
+        TIE.to_string()
+    } else {
+        code.checked_sub(seq.sysex_offset)
+            .and_then(MidiNote::from_number)
+            .map_or_else(|| format!("?{}", code), |note| note.to_string())
+    }
+}
+
+pub fn bound_str(bounds: &[Bounds], vcode: &[u8]) -> Option<String> {
+    let first = vcode.first()?;
+    for bound in schema::resolution_order(bounds) {
+        match bound {
+            Bounds::Values(values) => {
+                for (name, value) in values {
+                    if value.code() == *first {
+                        return Some(name.to_string());
+                    }
+                }
+            }
+            Bounds::Range(range) => {
+                if let Some(value) = range.value(*first) {
+                    return Some(range.display(value));
+                }
+            }
+            Bounds::NoteSeq(seq) => {
+                return Some(
+                    vcode
+                        .iter()
+                        .map(|code| note_str(seq, *code))
+                        .collect::<Vec<String>>()
+                        .join(","),
+                );
+            }
+            Bounds::Wide(wide) => {
+                let display = u64::from(wide.decode(vcode)?) + u64::from(wide.sysex_offset);
+                if display >= u64::from(wide.lo) && display <= u64::from(wide.hi) {
+                    return Some(display.to_string());
+                }
+            }
+            Bounds::Text(text) => {
+                let chars: String = vcode
+                    .iter()
+                    .take(text.max_len as usize)
+                    .take_while(|code| **code != 0)
+                    .map(|code| *code as char)
+                    .collect();
+                return Some(match text.pad {
+                    Some(pad) => chars.trim_end_matches(pad as char).to_string(),
+                    None => chars,
+                });
+            }
+            Bounds::Dump(dump) => {
+                let data = dump.decode(vcode);
+                if data.len() <= dump.max_len as usize {
+                    return Some(hex::encode(data));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Sysex messages setting the values of a snapshot, checking them all before any is sent
+pub fn encode_values(
+    dev_type: &DeviceType,
+    values: &LinkedHashMap<String, Vec<String>>,
+) -> Result<LinkedHashMap<String, Vec<Vec<u8>>>> {
+    let descriptor = dev_type.descriptor();
+    let mut encoded = LinkedHashMap::new();
+    for (param_name, value_ids) in values {
+        encoded.insert(
+            param_name.clone(),
+            descriptor.encode(param_name, value_ids)?,
+        );
+    }
+    Ok(encoded)
+}
+
+pub fn bound_codes(param: &Param, bound_ids: &[String]) -> Result<Vec<u8>> {
+    bound_match(param, bound_ids).map(|(_, bcode)| bcode)
+}
+
+/// The bounds accepting a value, and the value's raw codes
+pub fn bound_match<'a>(param: &Param<'a>, bound_ids: &[String]) -> Result<(&'a Bounds, Vec<u8>)> {
+    bounds_match(param.bounds(), &param.to_string(), bound_ids)
+}
+
+fn bounds_match<'a>(
+    bounds: &'a [Bounds],
+    param_name: &str,
+    bound_ids: &[String],
+) -> Result<(&'a Bounds, Vec<u8>)> {
+    for b_id in bound_ids {
+        match bounds.first() {
+            // dumps are long hex strings, bounded by their own max_len
+            Some(Bounds::Dump(_)) if !b_id.is_empty() => {}
+            _ => schema::check_input(b_id, |c| c == ' ' || c.is_ascii_graphic())?,
+        }
+    }
+    let reqs = bounds.first().map_or((1, 1), Bounds::value_count);
+    if bound_ids.len() < reqs.0 {
+        return Err(Box::new(DeviceError::MissingValue {
+            param_name: param_name.to_string(),
+        }));
+    }
+    if bound_ids.len() > reqs.1 {
+        return Err(Box::new(DeviceError::TooManyValues {
+            param_name: param_name.to_string(),
+        }));
+    }
+    let value_name = bound_ids.join(" ");
+    let mut err: Box<dyn Error> = Box::new(DeviceError::UnknownValue {
+        hint: Hint::closest(&value_name, value_names(bounds)),
+        value_name,
+    });
+    for bound in schema::resolution_order(bounds) {
+        match bound_code(bound, bound_ids) {
+            Ok(bcode) => return Ok((bound, bcode)),
+            // report a well-formed value outside its range rather than a parse failure
+            Err(e) => match e.downcast_ref::<DeviceError>() {
+                Some(DeviceError::ValueOutOfBound { .. }) => err = e,
+                _ if bounds.len() == 1 => err = e,
+                _ => {}
+            },
+        }
+    }
+    Err(err)
+}
+
+/// Decode a complete sysex message of a schema-described device, dropping messages of other
+/// devices and those with a bad checksum
+pub fn decode_message(
+    schema: &schema::Device,
+    message: &[u8],
+) -> LinkedHashMap<String, Vec<String>> {
+    let mut values = LinkedHashMap::new();
+    let header = schema.header();
+    if message.len() > header.len() + 1
+        && message[1..].starts_with(&header)
+        && message.last() == Some(&0xf7)
+    {
+        let payload = &message[header.len() + 1..message.len() - 1];
+        match schema.checksum {
+            Some(checksum) => match checksum.verify(payload) {
+                Some(payload) => decode_reply(schema, payload, &mut values),
+                None => warn!("dropping reply with bad checksum {}", hex::encode(message)),
+            },
+            None => decode_reply(schema, payload, &mut values),
+        }
+    }
+    values
+}
+
+/// Decode a reply of a schema-described device into parameter values.
+/// Notes of sequences replied in several messages are appended to those received before.
+pub fn decode_reply(
+    device: &schema::Device,
+    payload: &[u8],
+    values: &mut LinkedHashMap<String, Vec<String>>,
+) {
+    let (param, field, data) = match reply_param(device, payload) {
+        Some(found) => found,
+        None => {
+            warn!("unknown reply {}", hex::encode(payload));
+            return;
+        }
+    };
+    if let Some(field_sysex) = field {
+        // fields are queried after the mode, and only those of the current mode are kept
+        let modal = values.get_mut(&param.to_string());
+        let mode = modal
+            .as_ref()
+            .and_then(|modal| modal.first())
+            .and_then(|mode_name| {
+                param
+                    .modes()
+                    .into_iter()
+                    .find(|(name, _)| name == mode_name)
+            });
+        if let (Some(modal), Some((_, mode))) = (modal, mode) {
+            for (field_name, field) in &mode.fields {
+                if field.sysex.get(Form::Reply) == field_sysex {
+                    match bound_str(&field.bounds, data) {
+                        Some(value) => modal.push(format!("{}={}", field_name, value)),
+                        None => warn!(
+                            "{} {} unbound value code {}",
+                            param,
+                            field_name,
+                            hex::encode(data)
+                        ),
+                    }
+                }
+            }
+        }
+        return;
+    }
+    if param.schema.modes.is_some() {
+        match data.get(..1).and_then(|code| modal_str(&param, code, &[])) {
+            Some(modal) => {
+                let _ = values.insert(param.to_string(), modal);
+            }
+            None => warn!("{} unknown mode code {}", param, hex::encode(data)),
+        }
+        return;
+    }
+    match param.bounds().first() {
+        Some(Bounds::NoteSeq(seq)) => {
+            let (position, data) = match (seq.block_len, data) {
+                (Some(_), [offset, len, block @ ..]) => (
+                    Some(seq.offset_notes(*offset)),
+                    &block[..(*len as usize).min(block.len())],
+                ),
+                (Some(_), _) => {
+                    warn!("{} short block {}", param, hex::encode(data));
+                    return;
+                }
+                (None, data) => (None, data),
+            };
+            // sequences are padded with zeroes
+            let notes: Vec<String> = data
+                .iter()
+                .take_while(|code| **code != 0)
+                .map(|code| note_str(seq, *code))
+                .collect();
+            match position {
+                Some(position) => {
+                    let sequence = values.entry(param.to_string()).or_default();
+                    merge_block(sequence, position, notes);
+                }
+                None => {
+                    let _ = values.insert(param.to_string(), notes);
+                }
+            }
+        }
+        _ => match bound_str(param.bounds(), data) {
+            Some(value) => {
+                let _ = values.insert(param.to_string(), vec![value]);
+            }
+            None => warn!("{} unbound value code {}", param, hex::encode(data)),
+        },
+    }
+}
+
+/// Place the notes of a block in a sequence, at the position of its first note. Blocks may
+/// arrive in any order, or twice when queries are retried; steps of blocks not received yet
+/// are shown as `?`.
+fn merge_block(sequence: &mut Vec<String>, position: usize, notes: Vec<String>) {
+    if notes.is_empty() {
+        return;
+    }
+    let end = position + notes.len();
+    if sequence.len() < end {
+        sequence.resize(end, "?".to_string());
+    }
+    sequence.splice(position..end, notes);
+}
+
+/// A reply's parameter, field sysex and data, see `reply_param`
+type ReplyParam<'a, 'p> = (Param<'a>, Option<&'a [u8]>, &'p [u8]);
+
+/// The parameter a reply is about, the sysex of the field it is about if the parameter is
+/// modal, and the reply data following the parameter's index
+fn reply_param<'a, 'p>(
+    device: &'a schema::Device,
+    payload: &'p [u8],
+) -> Option<ReplyParam<'a, 'p>> {
+    let reply = device.reply?;
+    for (name, schema) in &device.parameters {
+        let fields = schema
+            .modes
+            .iter()
+            .flatten()
+            .flat_map(|(_, mode)| mode.fields.values())
+            .map(|field| Some(field.sysex.get(Form::Reply)));
+        for field in std::iter::once(None).chain(fields) {
+            let sysex = field.unwrap_or_else(|| schema.sysex.get(Form::Reply));
+            let end = match reply.sysex_end(payload, sysex) {
+                Some(end) => end,
+                None => continue,
+            };
+            let mut data = &payload[end..];
+            let mut index = None;
+            if let Some(range) = &schema.index {
+                // parameters sharing a sysex are told apart by their index
+                match data.split_first() {
+                    Some((idx, rest)) if range.value(*idx).is_some() => {
+                        index = Some(*idx);
+                        data = rest;
+                    }
+                    _ => continue,
+                }
+            }
+            trace!("reply {} matches parameter {}", hex::encode(sysex), name);
+            let param = Param {
+                name,
+                schema,
+                index,
+                mode: None,
+            };
+            return Some((param, field, data.get(schema.reply_skip..)?));
+        }
+    }
+    None
+}
+
+/// Check values of a parameter against its constraints, given the current values of the
+/// parameters they depend on. Constraints on parameters of unknown value are not checked.
+pub fn check_constraints(
+    param: &Param,
+    value_ids: &[String],
+    current: &LinkedHashMap<String, Vec<String>>,
+) -> Result<()> {
+    for constraint in &param.schema.constraints {
+        let other_value = match current.get(&constraint.param) {
+            Some(other_value) => other_value,
+            None => continue,
+        };
+        if other_value.len() == 1
+            && constraint.when.contains(&other_value[0])
+            && bounds_match(&constraint.bounds, &param.to_string(), value_ids).is_err()
+        {
+            return Err(Box::new(DeviceError::ConstraintViolated {
+                param_name: param.to_string(),
+                value_name: value_ids.join(" "),
+                other: constraint.param.clone(),
+                other_value: other_value.join(" "),
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// Firmware versions required by a parameter, or by the bounds of its values,
+/// if the identified device does not run one of them
+pub fn unsupported_firmware(
+    param: &Param,
+    value_ids: &[String],
+    identity: Option<&Identity>,
+) -> Option<Firmwares> {
+    let firmware = Firmware(identity?.firmware);
+    if !param.schema.firmware.contains(firmware) {
+        return Some(param.schema.firmware);
+    }
+    if value_ids.is_empty() || param.schema.modes.is_some() {
+        return None;
+    }
+    let (bounds, _) = bound_match(param, value_ids).ok()?;
+    Some(bounds.firmware()).filter(|firmwares| !firmwares.contains(firmware))
+}
+
+/// A modal parameter's mode name, mode and `Field=value` assignments
+pub type ModalValues<'a> = (&'a str, &'a Mode, Vec<(&'a str, &'a str)>);
+
+/// Split the values of a modal parameter into its mode and `Field=value` assignments.
+/// The mode is either part of the parameter name, as in `Pad/5:Note`, or the first value.
+pub fn modal_values<'a>(param: &Param<'a>, value_ids: &'a [String]) -> Result<ModalValues<'a>> {
+    let (mode_name, assignments) = match param.mode {
+        Some(mode) => (mode, value_ids),
+        None => match value_ids.split_first() {
+            Some((mode, rest)) => (mode.as_str(), rest),
+            None => {
+                return Err(Box::new(DeviceError::MissingValue {
+                    param_name: param.to_string(),
+                }))
+            }
+        },
+    };
+    let modes = param.modes();
+    let (mode_name, mode) = modes
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(mode_name))
+        .copied()
+        .ok_or_else(|| DeviceError::UnknownValue {
+            value_name: mode_name.to_string(),
+            hint: Hint::closest(mode_name, modes.iter().map(|(name, _)| *name)),
+        })?;
+    let mut fields = vec![];
+    for assignment in assignments {
+        let mut parts = assignment.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(field), Some(value)) => fields.push((field, value)),
+            _ => {
+                return Err(Box::new(DeviceError::UnknownValue {
+                    value_name: assignment.to_string(),
+                    hint: Hint::default(),
+                }))
+            }
+        }
+    }
+    Ok((mode_name, mode, fields))
+}
+
+/// Message bodies setting a modal parameter: one selecting the mode, then one per field given.
+/// The mode is set by `<param sysex> <index> <mode sysex>`, a field by `<field sysex> <index> <value>`.
+pub fn modal_codes(param: &Param, value_ids: &[String]) -> Result<Vec<Vec<u8>>> {
+    let (mode_name, mode, fields) = modal_values(param, value_ids)?;
+    let index: Vec<u8> = param.index.into_iter().collect();
+    let mut bodies = vec![[param.schema.sysex.get(Form::Update), &index, &mode.sysex].concat()];
+    for (field_name, value) in fields {
+        let field = mode
+            .fields
+            .get(field_name)
+            .ok_or_else(|| DeviceError::UnknownField {
+                field_name: field_name.to_string(),
+                mode: mode_name.to_string(),
+                hint: Hint::closest(field_name, mode.fields.keys().map(String::as_str)),
+            })?;
+        let (_, code) = bounds_match(&field.bounds, field_name, &[value.to_string()])?;
+        bodies.push([field.sysex.get(Form::Update), &index, &code].concat());
+    }
+    Ok(bodies)
+}
+
+/// Display values of a modal parameter, from its mode code and the codes of its fields
+pub fn modal_str(
+    param: &Param,
+    mode_code: &[u8],
+    field_codes: &[(Vec<u8>, Vec<u8>)],
+) -> Option<Vec<String>> {
+    let (mode_name, mode) = param
+        .modes()
+        .into_iter()
+        .find(|(_, mode)| mode.sysex == mode_code)?;
+    let mut values = vec![mode_name.to_string()];
+    for (field_name, field) in &mode.fields {
+        if let Some((_, code)) = field_codes
+            .iter()
+            .find(|(sysex, _)| sysex[..] == *field.sysex.get(Form::Reply))
+        {
+            values.push(format!(
+                "{}={}",
+                field_name,
+                bound_str(&field.bounds, code)?
+            ));
+        }
+    }
+    Some(values)
+}
+
+/// Whether a value is relative to the current one: `+N`, `-N` or `toggle`.
+/// Signed values of parameters accepting negative numbers are absolute.
+pub fn is_relative(param: &Param, value_id: &str) -> bool {
+    let signed = param.bounds().iter().any(|bounds| match bounds {
+        Bounds::Range(range) => range.is_signed(),
+        _ => false,
+    });
+    value_id == TOGGLE
+        || (!signed && value_id.starts_with(['+', '-']) && i16::from_str(value_id).is_ok())
+}
+
+const TOGGLE: &str = "toggle";
+
+/// New value of a parameter after a relative change, stepping through its possible values
+/// in declaration order. `toggle` is only valid for parameters with exactly two values.
+pub fn relative_value(param: &Param, current: &[String], change: &str) -> Result<Vec<String>> {
+    let bounds = param.bounds();
+    let unknown = || DeviceError::UnknownValue {
+        value_name: current.join(" "),
+        hint: Hint::default(),
+    };
+    let value = match current {
+        [value] => value,
+        _ => return Err(Box::new(unknown())),
+    };
+    let mut offset = 0;
+    let mut pos = None;
+    for bounds in bounds {
+        if let Some(step) = step_position(bounds, value) {
+            pos = Some(offset + step);
+            break;
+        }
+        offset += step_count(bounds);
+    }
+    let pos = pos.ok_or_else(unknown)?;
+    let count: i64 = bounds.iter().map(step_count).sum();
+    let mut new_pos = if change == TOGGLE {
+        if count != 2 {
+            return Err(Box::new(DeviceError::UnknownValue {
+                value_name: change.to_string(),
+                hint: Hint::default(),
+            }));
+        }
+        1 - pos
+    } else {
+        pos + i64::from(i16::from_str(change)?)
+    };
+    if new_pos >= 0 {
+        for bounds in bounds {
+            if new_pos < step_count(bounds) {
+                return Ok(vec![step_value(bounds, new_pos)]);
+            }
+            new_pos -= step_count(bounds);
+        }
+    }
+    Err(Box::new(DeviceError::ValueOutOfBound {
+        value_name: change.to_string(),
+    }))
+}
+
+/// Number of values of some bounds that a relative change steps through
+fn step_count(bounds: &Bounds) -> i64 {
+    match bounds {
+        Bounds::Values(values) => values.len() as i64,
+        Bounds::Range(range) => i64::from(range.hi) - i64::from(range.lo) + 1,
+        Bounds::Wide(wide) => i64::from(wide.hi) - i64::from(wide.lo) + 1,
+        Bounds::NoteSeq(_) | Bounds::Text(_) | Bounds::Dump(_) => 0,
+    }
+}
+
+/// Position of a value among the values of some bounds, if it is one of them
+fn step_position(bounds: &Bounds, value: &str) -> Option<i64> {
+    match bounds {
+        Bounds::Values(values) => values
+            .keys()
+            .position(|name| name == value)
+            .map(|pos| pos as i64),
+        Bounds::Range(range) => range
+            .step(value)
+            .filter(|step| (range.lo..=range.hi).contains(step))
+            .map(|step| i64::from(step) - i64::from(range.lo)),
+        Bounds::Wide(wide) => u32::from_str(value)
+            .ok()
+            .filter(|value| (wide.lo..=wide.hi).contains(value))
+            .map(|value| i64::from(value) - i64::from(wide.lo)),
+        Bounds::NoteSeq(_) | Bounds::Text(_) | Bounds::Dump(_) => None,
+    }
+}
+
+/// Value at a position among the values of some bounds, less than their `step_count`
+fn step_value(bounds: &Bounds, pos: i64) -> String {
+    match bounds {
+        Bounds::Values(values) => values.keys().nth(pos as usize).cloned().unwrap_or_default(),
+        Bounds::Range(range) => range.display(range.lo + pos as i16),
+        Bounds::Wide(wide) => (wide.lo + pos as u32).to_string(),
+        Bounds::NoteSeq(_) | Bounds::Text(_) | Bounds::Dump(_) => String::new(),
+    }
+}
+
+/// Names and aliases of the values of some bounds
+fn value_names(bounds: &[Bounds]) -> Vec<&str> {
+    let mut names = vec![];
+    for bound in bounds {
+        if let Bounds::Values(values) = bound {
+            for (name, value) in values {
+                names.push(name.as_str());
+                names.extend(value.aliases().iter().map(String::as_str));
+            }
+        }
+    }
+    names
+}
+
+fn bound_code(bounds: &Bounds, bound_ids: &[String]) -> Result<Vec<u8>> {
+    match bounds {
+        Bounds::Values(values) => {
+            let b_id = bound_ids.first().unwrap();
+            for (name, value) in values {
+                if value.is_named(name, b_id) {
+                    return Ok(vec![value.code()]);
+                }
+            }
+            Err(Box::new(DeviceError::UnknownValue {
+                value_name: b_id.to_owned(),
+                hint: Hint::closest(b_id, value_names(std::slice::from_ref(bounds))),
+            }))
+        }
+        Bounds::Range(range) => {
+            let b_id = bound_ids.first().unwrap();
+            let val = range.step(b_id).ok_or_else(|| DeviceError::UnknownValue {
+                value_name: b_id.to_owned(),
+                hint: Hint::default(),
+            })?;
+            match range.code(val) {
+                Some(code) => Ok(vec![code]),
+                None => Err(Box::new(DeviceError::ValueOutOfBound {
+                    value_name: b_id.to_owned(),
+                })),
+            }
+        }
+        Bounds::NoteSeq(seq) => {
+            let mut bcode = Vec::with_capacity(bound_ids.len());
+            for (step, b_id) in bound_ids.iter().enumerate() {
+                match (b_id.as_str(), seq.rest, seq.tie) {
+                    (REST, Some(code), _) | (TIE, _, Some(code)) => {
+                        bcode.push(code);
+                        continue;
+                    }
+                    _ => {}
+                }
+                let note = MidiNote::from_str(b_id).map_err(|_| DeviceError::InvalidStep {
+                    note: b_id.to_owned(),
+                    step: step + 1,
+                })?;
+                match note.note.checked_add(seq.sysex_offset) {
+                    Some(code)
+                        if code < 0x80 && Some(code) != seq.rest && Some(code) != seq.tie =>
+                    {
+                        bcode.push(code)
+                    }
+                    _ => {
+                        return Err(Box::new(DeviceError::ValueOutOfBound {
+                            value_name: b_id.to_owned(),
+                        }))
+                    }
+                }
+            }
+            Ok(bcode)
+        }
+        Bounds::Text(text) => {
+            let value = bound_ids.join(" ");
+            if value.len() > text.max_len as usize
+                || !value.chars().all(|c| c == ' ' || c.is_ascii_graphic())
+            {
+                return Err(Box::new(DeviceError::ValueOutOfBound { value_name: value }));
+            }
+            let mut bcode = value.into_bytes();
+            if let Some(pad) = text.pad {
+                bcode.resize(text.max_len as usize, pad);
+            }
+            Ok(bcode)
+        }
+        Bounds::Wide(wide) => {
+            let b_id = bound_ids.first().unwrap();
+            let val = u32::from_str(b_id)?;
+            if val >= wide.lo && val <= wide.hi && val - wide.sysex_offset <= wide.max_raw() {
+                Ok(wide.encode(val - wide.sysex_offset))
+            } else {
+                Err(Box::new(DeviceError::ValueOutOfBound {
+                    value_name: b_id.to_owned(),
+                }))
+            }
+        }
+        Bounds::Dump(dump) => {
+            let b_id = bound_ids.first().unwrap();
+            let data = hex::decode(b_id).map_err(|_| DeviceError::UnknownValue {
+                value_name: b_id.to_owned(),
+                hint: Hint::default(),
+            })?;
+            if data.len() > dump.max_len as usize
+                || (!dump.packed && data.iter().any(|b| *b > 0x7f))
+            {
+                return Err(Box::new(DeviceError::ValueOutOfBound {
+                    value_name: b_id.to_owned(),
+                }));
+            }
+            Ok(dump.encode(&data))
+        }
+    }
+}
+
+/// A complete sysex message, with the payload's checksum if the device uses one
+fn sysex(vendor: &[u8], parts: &[&[u8]], checksum: Option<Checksum>) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(64);
+    msg.push(0xf0);
+    msg.extend_from_slice(vendor);
+    for p in parts {
+        msg.extend_from_slice(p);
+    }
+    if let Some(checksum) = checksum {
+        msg.push(checksum.compute(&msg[vendor.len() + 1..]));
+    }
+    msg.push(0xf7);
+    msg
+}
+
+#[cfg(test)]
+mod test {
+    use crate::devices::backend::{EchoFilter, MockBackend, SysexStream};
+    use crate::devices::nrpn::NrpnParser;
+    #[cfg(feature = "async")]
+    use crate::devices::task::DeviceTask;
+    use crate::devices::{
+        bound_codes, bound_str, bounds_match, check_constraints, decode_any, decode_message,
+        decode_reply, encode_values, is_relative, modal_codes, modal_str, relative_value, sysex,
+        unsupported_firmware, Correlator, Device, DeviceError, DeviceType, Identity, Joiner,
+        MidiNote, Overrides, IDENTITY_REPLY, IDENTITY_REQUEST, REST, TIE,
+    };
+    use crate::schema::{
+        self, Bounds, ByteOrder, Checksum, Form, Fragments, Param, Range, Text, Wide,
+    };
+    use linked_hash_map::LinkedHashMap;
+    use quickcheck::{quickcheck, TestResult};
+    use std::sync::{Arc, Mutex};
+
+    fn code(param: &str, value: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let brute = schema::device("MicroBrute").unwrap();
+        bound_codes(&brute.param(param).unwrap(), &[value.to_string()])
+    }
+
+    #[test]
+    fn test_sentinel_with_range() {
+        let brute = schema::device("MicroBrute").unwrap();
+        let chan = brute.param("MidiRecvChan").unwrap();
+        assert_eq!(bound_str(chan.bounds(), &[0x00]), Some("1".to_string()));
+        assert_eq!(bound_str(chan.bounds(), &[0x0f]), Some("16".to_string()));
+        assert_eq!(bound_str(chan.bounds(), &[0x10]), Some("All".to_string()));
+        assert_eq!(bound_str(chan.bounds(), &[0x11]), None);
+
+        assert_eq!(code("MidiRecvChan", "All").unwrap(), vec![0x10]);
+        assert_eq!(code("MidiRecvChan", "16").unwrap(), vec![0x0f]);
+        match code("MidiRecvChan", "17").unwrap_err().downcast_ref() {
+            Some(DeviceError::ValueOutOfBound { .. }) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+        match code("MidiRecvChan", "Most").unwrap_err().downcast_ref() {
+            Some(DeviceError::UnknownValue { .. }) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_values_take_precedence() {
+        let mut values = LinkedHashMap::new();
+        values.insert("All".to_string(), 0x10.into());
+        // range declared first and overlapping the sentinel value
+        let bounds = vec![
+            Bounds::Range(Range {
+                lo: 0,
+                hi: 127,
+                sysex_offset: 0,
+                twos_complement: false,
+                scale: None,
+                unit: None,
+                firmware: Default::default(),
+            }),
+            Bounds::Values(values),
+        ];
+        assert_eq!(bound_str(&bounds, &[0x10]), Some("All".to_string()));
+        assert_eq!(bound_str(&bounds, &[0x11]), Some("17".to_string()));
+    }
+
+    #[test]
+    fn test_decode_modal_reply() {
+        let beatstep = schema::device("BeatStep").unwrap();
+        let mut values = LinkedHashMap::new();
+        let replies: &[&[u8]] = &[
+            &[0x02, 0x00, 0x50, 0x0b, 0x03],
+            &[0x02, 0x00, 0x01, 0x70, 0x09],
+            &[0x02, 0x00, 0x02, 0x70, 0x09],
+            // not a field of the Note mode
+            &[0x02, 0x00, 0x04, 0x70, 0x00],
+            &[0x02, 0x00, 0x06, 0x70, 0x01],
+        ];
+        for reply in replies {
+            decode_reply(beatstep, reply, &mut values);
+        }
+        let ids = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        assert_eq!(values.get("MidiChannel"), Some(&ids("4")));
+        assert_eq!(
+            values.get("Pad/1"),
+            Some(&ids("Note Channel=10 Option=Gate"))
+        );
+        assert_eq!(values.len(), 2);
+    }
+
+    #[test]
+    fn test_zone_modes() {
+        let poly = DeviceType::PolyBrute.descriptor();
+        let ids = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        assert_eq!(
+            poly.encode("Zone/2:Midi", &ids("Channel=3 Transpose=-12"))
+                .unwrap(),
+            vec![
+                vec![0xf0, 0x00, 0x20, 0x6b, 0x7f, 0x42, 0x02, 0x00, 0x60, 0x00, 0x01, 0x02, 0xf7],
+                vec![0xf0, 0x00, 0x20, 0x6b, 0x7f, 0x42, 0x02, 0x00, 0x60, 0x04, 0x01, 0x02, 0xf7],
+                vec![0xf0, 0x00, 0x20, 0x6b, 0x7f, 0x42, 0x02, 0x00, 0x60, 0x01, 0x01, 0x34, 0xf7],
+            ]
+        );
+        let mut values = LinkedHashMap::new();
+        for reply in &[
+            [0x02, 0x00, 0x60, 0x00, 0x00, 0x01],
+            [0x02, 0x00, 0x60, 0x01, 0x00, 0x40],
+        ] {
+            decode_reply(schema::device("PolyBrute").unwrap(), reply, &mut values);
+        }
+        assert_eq!(values.get("Zone/1"), Some(&ids("Local Transpose=0")));
+    }
+
+    #[test]
+    fn test_indexed_values() {
+        let impact = DeviceType::DrumBruteImpact.descriptor();
+        assert_eq!(
+            impact
+                .encode("InstrumentNote/10", &["36".to_string()])
+                .unwrap(),
+            vec![vec![
+                0xf0, 0x00, 0x20, 0x6b, 0x09, 0x01, 0x00, 0x01, 0x50, 0x09, 36, 0xf7
+            ]]
+        );
+        assert!(impact
+            .encode("InstrumentNote/11", &["36".to_string()])
+            .is_err());
+        assert_eq!(impact.encode_query("InstrumentNote/1").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_preset_dump() {
+        let freak = DeviceType::MicroFreak.descriptor();
+        assert_eq!(
+            freak.encode("PresetB/2", &["00017f".to_string()]).unwrap(),
+            vec![vec![
+                0xf0, 0x00, 0x20, 0x6b, 0x07, 0x01, 0x00, 0x01, 0x52, 0x01, 0x01, 0x00, 0x01, 0x7f,
+                0xf7
+            ]]
+        );
+        assert!(freak.encode("PresetA/1", &["0080".to_string()]).is_err());
+        assert!(freak.encode("PresetA/1", &["xyz".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_fragmented_dump() {
+        let matrix = DeviceType::MatrixBrute.descriptor();
+        let dump = "01".repeat(40);
+        let messages = matrix
+            .encode("PresetA/3", std::slice::from_ref(&dump))
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0][..13],
+            [0xf0, 0x00, 0x20, 0x6b, 0x0b, 0x01, 0x00, 0x01, 0x52, 0x00, 0x02, 0x01, 0x01]
+        );
+        assert_eq!(messages[1].len(), 12 + 8 + 1);
+        let mut joiner = Joiner::new(schema::device("MatrixBrute").unwrap().fragments.unwrap());
+        let payload = |msg: &[u8]| msg[5..msg.len() - 1].to_vec();
+        assert_eq!(joiner.push(&payload(&messages[0])), None);
+        let mut values = LinkedHashMap::new();
+        let joined = joiner.push(&payload(&messages[1])).unwrap();
+        decode_reply(schema::device("MatrixBrute").unwrap(), &joined, &mut values);
+        assert_eq!(values.get("PresetA/3"), Some(&vec![dump]));
+    }
+
+    #[test]
+    fn test_value_aliases() {
+        let brute = schema::device("MicroBrute").unwrap();
+        let param = brute.param("lfokeyretrig").unwrap();
+        assert_eq!(
+            bound_codes(&param, &["on".to_string()]).unwrap(),
+            vec![0x01]
+        );
+        assert_eq!(
+            bound_codes(&param, &["Yes".to_string()]).unwrap(),
+            vec![0x01]
+        );
+        assert_eq!(
+            bound_codes(&param, &["no".to_string()]).unwrap(),
+            vec![0x00]
+        );
+        assert!(bound_codes(&param, &["maybe".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_hints() {
+        let brute = schema::device("MicroBrute").unwrap();
+        let err = brute.param("LfoKeyRetrg").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unknown parameter LfoKeyRetrg, did you mean LfoKeyRetrig?"
+        );
+        let err = brute.param("Zorglub").err().unwrap();
+        assert_eq!(err.to_string(), "unknown parameter Zorglub");
+        let param = brute.param("LfoKeyRetrig").unwrap();
+        let err = bound_codes(&param, &["of".to_string()]).err().unwrap();
+        assert_eq!(err.to_string(), "unknown value of, did you mean Off or On?");
+        let err = schema::device("MicroBrut").err().unwrap();
+        assert!(err.to_string().ends_with("did you mean MicroBrute?"));
+    }
+
+    #[test]
+    fn test_signed_range() {
+        let range = |sysex_offset, twos_complement| {
+            vec![Bounds::Range(Range {
+                lo: -12,
+                hi: 12,
+                sysex_offset,
+                twos_complement,
+                scale: None,
+                unit: None,
+                firmware: Default::default(),
+            })]
+        };
+        let ids = |s: &str| vec![s.to_string()];
+        let offset = range(-12, false);
+        assert_eq!(
+            bounds_match(&offset, "Transpose", &ids("-5")).unwrap().1,
+            vec![7]
+        );
+        assert_eq!(
+            bounds_match(&offset, "Transpose", &ids("+12")).unwrap().1,
+            vec![0x18]
+        );
+        assert_eq!(bound_str(&offset, &[0]), Some("-12".to_string()));
+        assert_eq!(bound_str(&offset, &[0x19]), None);
+        let twos = range(0, true);
+        assert_eq!(
+            bounds_match(&twos, "Transpose", &ids("-1")).unwrap().1,
+            vec![0x7f]
+        );
+        assert_eq!(bound_str(&twos, &[0x74]), Some("-12".to_string()));
+        assert!(bounds_match(&twos, "Transpose", &ids("-13")).is_err());
+    }
+
+    #[test]
+    fn test_scaled_range() {
+        let bounds = vec![Bounds::Range(Range {
+            lo: 1,
+            hi: 40,
+            sysex_offset: 1,
+            twos_complement: false,
+            scale: Some(2.5),
+            unit: Some("ms".to_string()),
+            firmware: Default::default(),
+        })];
+        let ids = |s: &str| vec![s.to_string()];
+        assert_eq!(bound_str(&bounds, &[17]), Some("45ms".to_string()));
+        assert_eq!(bound_str(&bounds, &[0]), Some("2.5ms".to_string()));
+        assert_eq!(
+            bounds_match(&bounds, "Gate", &ids("45ms")).unwrap().1,
+            vec![17]
+        );
+        assert_eq!(
+            bounds_match(&bounds, "Gate", &ids("45")).unwrap().1,
+            vec![17]
+        );
+        assert_eq!(
+            bounds_match(&bounds, "Gate", &ids("44")).unwrap().1,
+            vec![17]
+        );
+        assert!(bounds_match(&bounds, "Gate", &ids("103ms")).is_err());
+        assert!(bounds_match(&bounds, "Gate", &ids("45s")).is_err());
+    }
+
+    #[test]
+    fn test_text() {
+        let text = |pad| {
+            vec![Bounds::Text(Text {
+                max_len: 8,
+                pad,
+                firmware: Default::default(),
+            })]
+        };
+        let ids = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        let padded = text(Some(b' '));
+        assert_eq!(
+            bounds_match(&padded, "Name", &ids("My Bass")).unwrap().1,
+            b"My Bass ".to_vec()
+        );
+        assert_eq!(bound_str(&padded, b"My Bass "), Some("My Bass".to_string()));
+        assert!(bounds_match(&padded, "Name", &ids("My Long Bass")).is_err());
+        let unpadded = text(None);
+        assert_eq!(
+            bounds_match(&unpadded, "Name", &ids("Lead")).unwrap().1,
+            b"Lead".to_vec()
+        );
+        assert_eq!(bound_str(&unpadded, b"Lead\0\0"), Some("Lead".to_string()));
+    }
+
+    #[test]
+    fn test_wide() {
+        let wide = |order| {
+            vec![Bounds::Wide(Wide {
+                lo: 0,
+                hi: 16383,
+                sysex_offset: 0,
+                bytes: 2,
+                order,
+                firmware: Default::default(),
+            })]
+        };
+        let ids = |s: &str| vec![s.to_string()];
+        let msb = wide(ByteOrder::MsbFirst);
+        assert_eq!(
+            bounds_match(&msb, "Fine", &ids("8192")).unwrap().1,
+            vec![0x40, 0x00]
+        );
+        assert_eq!(bound_str(&msb, &[0x40, 0x01]), Some("8193".to_string()));
+        assert_eq!(bound_str(&msb, &[0x40]), None);
+        assert!(bounds_match(&msb, "Fine", &ids("16384")).is_err());
+        let lsb = wide(ByteOrder::LsbFirst);
+        assert_eq!(
+            bounds_match(&lsb, "Fine", &ids("129")).unwrap().1,
+            vec![0x01, 0x01]
+        );
+        assert_eq!(bound_str(&lsb, &[0x7f, 0x7f]), Some("16383".to_string()));
+    }
+
+    #[test]
+    fn test_defaults_in_bounds() {
+        let brute = schema::device("MicroBrute").unwrap();
+        assert_eq!(brute.defaults().len(), 14);
+        for (param, values) in brute.defaults() {
+            assert!(bound_codes(&brute.param(&param).unwrap(), &values).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_relative_value() {
+        let brute = schema::device("MicroBrute").unwrap();
+        let step = |param: &str, current: &str, change: &str| {
+            let param = brute.param(param).unwrap();
+            relative_value(&param, &[current.to_string()], change).ok()
+        };
+        assert_eq!(step("BendRange", "2", "+2"), Some(vec!["4".to_string()]));
+        assert_eq!(step("BendRange", "2", "-1"), Some(vec!["1".to_string()]));
+        assert_eq!(step("BendRange", "12", "+1"), None);
+        assert_eq!(
+            step("MidiRecvChan", "16", "+1"),
+            Some(vec!["All".to_string()])
+        );
+        assert_eq!(
+            step("LfoKeyRetrig", "On", "toggle"),
+            Some(vec!["Off".to_string()])
+        );
+        assert_eq!(step("Gate", "Short", "toggle"), None);
+        let fine: schema::Device = serde_yaml::from_str(
+            "name: Fine\nvendor: Test\nmanufacturer: [0x7d]\nport_prefix: Fine\nsysex: [0x42]\nparameters:\n  Fine:\n    sysex: [0x01]\n    bounds: [{type: Wide, lo: 0, hi: 16383, sysex_offset: 0, bytes: 2}]",
+        )
+        .unwrap();
+        let fine = fine.param("Fine").unwrap();
+        let step =
+            |current: &str, change| relative_value(&fine, &[current.to_string()], change).ok();
+        assert_eq!(step("8192", "+1"), Some(vec!["8193".to_string()]));
+        assert_eq!(step("16383", "+1"), None);
+        assert_eq!(step("0", "-1"), None);
+        let chan = brute.param("MidiSendChan").unwrap();
+        assert!(is_relative(&chan, "-1") && is_relative(&chan, "+12"));
+        assert!(is_relative(&chan, "toggle"));
+        assert!(!is_relative(&chan, "12") && !is_relative(&chan, "1/8"));
+    }
+
+    #[test]
+    fn test_device_units() {
+        assert_eq!(DeviceType::from_name("MicroBrute").unwrap().1, 1);
+        assert_eq!(DeviceType::from_name("MicroBrute/2").unwrap().1, 2);
+        assert!(DeviceType::from_name("MicroBrute/0").is_err());
+        assert!(DeviceType::from_name("MicroBrute/x").is_err());
+        assert!(DeviceType::from_name("MicroBrut/1").is_err());
+        assert_eq!(DeviceType::from_name("mb/2").unwrap().1, 2);
+    }
+
+    #[test]
+    fn test_identity() {
+        let id = Identity::parse(&[
+            0x00, 0x20, 0x6b, 0x04, 0x01, 0x06, 0x02, 0x02, 0x00, 0x05, 0x01,
+        ])
+        .unwrap();
+        assert_eq!(id.manufacturer, vec![0x00, 0x20, 0x6b]);
+        assert_eq!(id.family, 0x84);
+        assert_eq!(id.model, 0x106);
+        assert_eq!(id.firmware_version(), "2.0.5.1");
+        assert!(id.is(schema::device("MicroBrute").unwrap()));
+        assert!(!id.is(schema::device("KeyStep37").unwrap()));
+        assert!(!id.is(schema::device("BassStation2").unwrap()));
+        assert_eq!(Identity::parse(&[0x00, 0x20, 0x6b, 0x04]), None);
+    }
+
+    /// A MicroBrute simulated by a mock backend, answering the first query of KeyNotePriority
+    fn mock_brute() -> (MockBackend, Box<dyn Device>) {
+        let backend = MockBackend::new(&["MicroBrute"]);
+        let mut identity = vec![0xf0];
+        identity.extend_from_slice(IDENTITY_REPLY);
+        identity.extend_from_slice(&[
+            0x00, 0x20, 0x6b, 0x04, 0x01, 0x06, 0x02, 0x02, 0x00, 0x05, 0x01, 0xf7,
+        ]);
+        backend.reply(IDENTITY_REQUEST, vec![identity]);
+        // message id 1 is the first after identification, the reply is HighNote
+        backend.reply(
+            &[0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0x01, 0x01, 0x0c],
+            vec![vec![
+                0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0x01, 0x01, 0x0b, 0x02, 0xf7,
+            ]],
+        );
+        let port = backend.port("MicroBrute");
+        let brute = DeviceType::MicroBrute
+            .descriptor()
+            .connect(Arc::new(backend.clone()), &port, &Overrides::default())
+            .unwrap();
+        (backend, brute)
+    }
+
+    #[test]
+    fn test_sysex_stream() {
+        let mut stream = SysexStream::default();
+        assert!(stream.push(&[0xf0, 0x00, 0x20]).is_empty());
+        // a clock tick within the message is passed on first
+        assert_eq!(stream.push(&[0x6b, 0xf8, 0x05]), vec![vec![0xf8]]);
+        assert_eq!(
+            stream.push(&[0x01, 0xf7, 0xf0, 0x7e, 0xf7]),
+            vec![
+                vec![0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0xf7],
+                vec![0xf0, 0x7e, 0xf7]
+            ]
+        );
+        assert_eq!(
+            stream.push(&[0x90, 0x3c, 0x40]),
+            vec![vec![0x90, 0x3c, 0x40]]
+        );
+    }
+
+    #[test]
+    fn test_mock_backend() {
+        let (backend, mut brute) = mock_brute();
+        assert_eq!(brute.identity().unwrap().firmware_version(), "2.0.5.1");
+        let sent = backend.sent().len();
+        let names = DeviceType::MicroBrute
+            .schema()
+            .expand(&["keynotepriority".to_string()])
+            .unwrap();
+        let values = brute.query_retrying(&names, 2).unwrap();
+        assert_eq!(backend.sent().len(), sent + 1);
+        assert_eq!(
+            values.get("KeyNotePriority"),
+            Some(&vec!["HighNote".to_string()])
+        );
+        brute
+            .update("KeyNotePriority", &["LowNote".to_string()])
+            .unwrap();
+        assert_eq!(
+            backend.sent().last(),
+            Some(&vec![
+                0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0x02, 0x01, 0x0b, 0x01, 0xf7
+            ])
+        );
+    }
+
+    #[test]
+    fn test_echo_filter() {
+        let mock = MockBackend::new(&["Echo"]);
+        mock.reply(
+            &[0xf0, 0x01],
+            vec![vec![0xf0, 0x01, 0xf7], vec![0xf0, 0x02, 0xf7]],
+        );
+        let backend = EchoFilter::wrap(Arc::new(mock.clone()), true);
+        let received = Arc::new(Mutex::new(vec![]));
+        let listened = received.clone();
+        let _listener = backend
+            .listen(
+                "Echo",
+                Box::new(move |_, msg| listened.lock().unwrap().push(msg.to_vec())),
+            )
+            .unwrap();
+        let mut sender = backend.connect_output(&mock.port("Echo")).unwrap();
+        sender.send(&[0xf0, 0x01, 0xf7]).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![vec![0xf0, 0x02, 0xf7]]);
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let (backend, mut brute) = mock_brute();
+        backend.rename("MicroBrute", "MicroBrute MIDI 2");
+        brute
+            .update("KeyNotePriority", &["LowNote".to_string()])
+            .unwrap();
+        assert_eq!(backend.sent().len(), 2);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_device_task() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _entered = runtime.enter();
+        let task = DeviceTask::spawn("MicroBrute", mock_brute().1);
+        let query = task.query(vec!["KeyNotePriority".to_string()], 0);
+        let values = runtime.block_on(query).unwrap();
+        assert_eq!(
+            values.get("KeyNotePriority"),
+            Some(&vec!["HighNote".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_decode_any() {
+        let decoded = decode_any(&[
+            0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0x01, 0x01, 0x0b, 0x02, 0xf7,
+        ]);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].0.to_string(), "MicroBrute");
+        let mut values = LinkedHashMap::new();
+        values.insert("KeyNotePriority".to_string(), vec!["HighNote".to_string()]);
+        assert_eq!(decoded[0].1, Ok(values));
+        let decoded = decode_any(&[0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0x01, 0x01, 0x7f, 0xf7]);
+        assert_eq!(decoded[0].1, Err("no parameter matches byte 8".to_string()));
+        assert!(decode_any(&[0xf0, 0x7d, 0x01, 0xf7]).is_empty());
+    }
+
+    #[test]
+    fn test_correlator() {
+        let correlator = Correlator::default();
+        correlator.expect(1, "MidiRecvChan".to_string());
+        correlator.expect(2, "Seq/1".to_string());
+        correlator.expect(3, "Seq/1".to_string());
+        correlator.expect(4, "KeyNotePriority".to_string());
+        assert!(correlator.accept(Some(1), Some("MidiRecvChan".to_string())));
+        assert!(!correlator.accept(Some(1), Some("MidiRecvChan".to_string())));
+        assert!(!correlator.accept(Some(9), Some("MidiRecvChan".to_string())));
+        assert!(!correlator.accept(Some(2), Some("MidiSendChan".to_string())));
+        assert!(correlator.accept(Some(3), Some("Seq/1".to_string())));
+        assert_eq!(correlator.missing(), vec!["KeyNotePriority".to_string()]);
+        correlator.expect(4, "MidiSendChan".to_string());
+        assert_eq!(
+            correlator.missing(),
+            vec!["KeyNotePriority".to_string(), "MidiSendChan".to_string()]
+        );
+    }
+
+    #[cfg(feature = "usb")]
+    #[test]
+    fn test_usb_packets() {
+        use crate::devices::usb::{packets, Unpacker};
+        let message = [0xf0, 0x00, 0x20, 0x6b, 0x05, 0x01, 0x00, 0xf7];
+        let packed = packets(&message);
+        assert_eq!(
+            packed,
+            vec![4, 0xf0, 0x00, 0x20, 4, 0x6b, 0x05, 0x01, 6, 0x00, 0xf7, 0]
+        );
+        let mut unpacker = Unpacker::default();
+        assert!(unpacker.push(&packed[..8]).is_empty());
+        assert_eq!(unpacker.push(&packed[8..]), vec![message.to_vec()]);
+        assert_eq!(packets(&[0xb0, 99, 3]), vec![0x0b, 0xb0, 99, 3]);
+    }
+
+    #[test]
+    fn test_nrpn() {
+        let mopho = DeviceType::Mopho.descriptor();
+        let messages = mopho.encode("MidiChannel", &["2".to_string()]).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                vec![0xb0, 99, 0x03],
+                vec![0xb0, 98, 0x02],
+                vec![0xb0, 6, 0x02]
+            ]
+        );
+        let mut parser = NrpnParser::new(schema::device("Mopho").unwrap());
+        let values: Vec<_> = messages.iter().filter_map(|msg| parser.push(msg)).collect();
+        assert_eq!(
+            values,
+            vec![("MidiChannel".to_string(), vec!["2".to_string()])]
+        );
+        assert!(parser.push(&[0xb1, 6, 0x02]).is_none());
+    }
+
+    #[test]
+    fn test_modal() {
+        let dev: schema::Device = serde_yaml::from_str(
+            r#"
+name: Pads
+vendor: Test
+manufacturer: [0x7d]
+port_prefix: Pads
+sysex: [0x42]
+parameters:
+  Pad:
+    sysex: [0x01]
+    index:
+      lo: 1
+      hi: 16
+      sysex_offset: 0
+    modes:
+      Off:
+        sysex: [0x00]
+      Note:
+        sysex: [0x09]
+        fields:
+          Channel:
+            sysex: [0x02]
+            bounds:
+              - type: Range
+                lo: 1
+                hi: 16
+                sysex_offset: 1
+          Note:
+            sysex: [0x03]
+            bounds:
+              - type: NoteSeq
+                max_len: 1
+                sysex_offset: 0
+"#,
+        )
+        .unwrap();
+        let ids = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        let pad = dev.param("Pad/5:Note").unwrap();
+        assert_eq!(pad.to_string(), "Pad/5:Note");
+        assert_eq!(
+            modal_codes(&pad, &ids("Channel=10 Note=C3")).unwrap(),
+            vec![vec![0x01, 5, 0x09], vec![0x02, 5, 9], vec![0x03, 5, 48]]
+        );
+        assert_eq!(dev.param("pad/5:note").unwrap().to_string(), "Pad/5:Note");
+        assert!(dev.param("Pad:Note").is_err());
+        assert!(dev.param("Pad/5:Drum").is_err());
+        assert!(dev.param("Pad/5:").is_err());
+        let pad = dev.param("Pad/5").unwrap();
+        assert_eq!(modal_codes(&pad, &ids("Off")).unwrap().len(), 1);
+        assert!(modal_codes(&pad, &ids("Note Velocity=3")).is_err());
+        assert!(modal_codes(&pad, &ids("Note Channel=17")).is_err());
+        assert_eq!(
+            modal_str(&pad, &[0x09], &[(vec![0x02], vec![9])]),
+            Some(ids("Note Channel=10"))
+        );
+    }
+
+    #[test]
+    fn test_encode_values() {
+        let mut values = LinkedHashMap::new();
+        values.insert(
+            "Encoder/1".to_string(),
+            vec!["CC".to_string(), "Channel=2".to_string()],
+        );
+        values.insert("Pad/3".to_string(), vec!["Off".to_string()]);
+        let encoded = encode_values(&DeviceType::BeatStep, &values).unwrap();
+        assert_eq!(encoded.len(), 2);
+        assert_eq!(encoded["Encoder/1"].len(), 2);
+        values.insert("Encoder/2".to_string(), vec!["Drum".to_string()]);
+        assert!(encode_values(&DeviceType::BeatStep, &values).is_err());
+    }
+
+    #[test]
+    fn test_firmware() {
+        let dev: schema::Device = serde_yaml::from_str(
+            r#"
+name: Old
+vendor: Test
+manufacturer: [0x7d]
+port_prefix: Old
+sysex: [0x42]
+parameters:
+  Tune:
+    max_firmware: '1.2'
+    sysex: [0x01]
+    bounds:
+      - type: Range
+        lo: 0
+        hi: 9
+        sysex_offset: 0
+  Swing:
+    sysex: [0x02]
+    bounds:
+      - type: Values
+        Off: 0x00
+      - type: Range
+        lo: 50
+        hi: 75
+        sysex_offset: 0
+        min_firmware: '2.0.5'
+"#,
+        )
+        .unwrap();
+        let identity = |firmware| Identity {
+            manufacturer: vec![0x42],
+            family: 0,
+            model: 0,
+            firmware,
+        };
+        let (v1, v2) = (identity([1, 2, 0, 0]), identity([2, 0, 5, 1]));
+        let ids = |s: &str| vec![s.to_string()];
+        let tune = dev.param("Tune").unwrap();
+        assert_eq!(unsupported_firmware(&tune, &[], Some(&v1)), None);
+        assert!(unsupported_firmware(&tune, &[], Some(&v2)).is_some());
+        assert_eq!(unsupported_firmware(&tune, &[], None), None);
+        let swing = dev.param("Swing").unwrap();
+        assert_eq!(unsupported_firmware(&swing, &ids("Off"), Some(&v1)), None);
+        assert!(unsupported_firmware(&swing, &ids("60"), Some(&v1)).is_some());
+        assert_eq!(unsupported_firmware(&swing, &ids("60"), Some(&v2)), None);
+    }
+
+    #[test]
+    fn test_decode_reply() {
+        let brute = schema::device("MicroBrute").unwrap();
+        let mut values = LinkedHashMap::new();
+        decode_reply(brute, &[0x01, 0x00, 0x01, 0x0b, 0x01], &mut values);
+        let mut seq = vec![0x01, 0x01, 0x23, 0x3a, 0x02, 0x00, 0x20, 0x48, 0x7f, 0x4a];
+        seq.resize(39, 0);
+        decode_reply(brute, &seq, &mut values);
+        decode_reply(brute, &[0x01, 0x02, 0x01, 0x0b], &mut values);
+        let ids = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        assert_eq!(values.get("KeyNotePriority"), Some(&ids("LowNote")));
+        assert_eq!(values.get("Seq/3"), Some(&ids("C3 _ D3")));
+        assert_eq!(values.len(), 2);
+        // notes below C0 have no name
+        let mut low = vec![0x01, 0x01, 0x23, 0x3a, 0x04, 0x00, 0x20, 0x1a, 0x24];
+        low.resize(39, 0);
+        decode_reply(brute, &low, &mut values);
+        assert_eq!(values.get("Seq/5"), Some(&ids("?26 C0")));
+        let seq5 = brute.param("Seq/5").unwrap();
+        assert_eq!(bound_str(seq5.bounds(), &[0x1a]), Some("?26".to_string()));
+        assert_eq!(
+            bound_str(seq5.bounds(), &[0x48, 0x7f, 0x4a]),
+            Some("C3,_,D3".to_string())
+        );
+        // queried sequences can be set back as they are, ties need a device supporting them
+        let seq3 = brute.param("Seq/3").unwrap();
+        assert_eq!(
+            bound_codes(&seq3, &ids("C3 _ D3")).unwrap(),
+            vec![0x48, 0x7f, 0x4a]
+        );
+        assert!(bound_codes(&seq3, &ids("C3 - D3")).is_err());
+
+        // blocks are placed by their offset, whatever order they arrive in
+        let block = |offset, notes: &[u8]| {
+            let mut seq = vec![0x01, 0x03, 0x23, 0x3a, 0x00, offset, 0x20];
+            seq.extend_from_slice(notes);
+            seq.resize(39, 0);
+            seq
+        };
+        let mut values = LinkedHashMap::new();
+        decode_reply(brute, &block(0x20, &[0x4a; 0x20]), &mut values);
+        decode_reply(brute, &block(0x00, &[0x48; 0x20]), &mut values);
+        decode_reply(brute, &block(0x20, &[0x4a; 0x20]), &mut values);
+        let notes = &values["Seq/1"];
+        assert_eq!((notes.len(), &*notes[0], &*notes[0x20]), (0x40, "C3", "D3"));
+    }
+
+    #[test]
+    fn test_constraints() {
+        let dev: schema::Device = serde_yaml::from_str(
+            r#"
+name: Seq
+vendor: Test
+manufacturer: [0x7d]
+port_prefix: Seq
+sysex: [0x42]
+parameters:
+  Sync:
+    sysex: [0x01]
+    bounds:
+      - type: Values
+        Internal: 0x00
+        External: 0x01
+  Step:
+    sysex: [0x02]
+    bounds:
+      - type: Range
+        lo: 1
+        hi: 32
+        sysex_offset: 0
+    constraints:
+      - param: Sync
+        when: [External]
+        bounds:
+          - type: Range
+            lo: 1
+            hi: 4
+            sysex_offset: 0
+"#,
+        )
+        .unwrap();
+        let step = dev.param("Step").unwrap();
+        let ids = |s: &str| vec![s.to_string()];
+        let mut current = LinkedHashMap::new();
+        assert!(check_constraints(&step, &ids("16"), &current).is_ok());
+        current.insert("Sync".to_string(), ids("Internal"));
+        assert!(check_constraints(&step, &ids("16"), &current).is_ok());
+        current.insert("Sync".to_string(), ids("External"));
+        assert!(check_constraints(&step, &ids("4"), &current).is_ok());
+        let err = check_constraints(&step, &ids("16"), &current).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Step 16 is not valid while Sync is External"
+        );
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Roland DT1 setting address 40 00 7f to 00, from the GS documentation
+        let msg = sysex(
+            &[0x41, 0x10, 0x42, 0x12],
+            &[&[0x40, 0x00, 0x7f], &[0x00]],
+            Some(Checksum::Roland),
+        );
+        assert_eq!(
+            msg,
+            vec![0xf0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41, 0xf7]
+        );
+        let payload = &msg[5..msg.len() - 1];
+        assert_eq!(Checksum::Roland.verify(payload), Some(&msg[5..9]));
+        assert_eq!(
+            Checksum::Roland.verify(&[0x40, 0x00, 0x7f, 0x00, 0x42]),
+            None
+        );
+
+        let dev: schema::Device = serde_yaml::from_str(
+            r#"
+name: Gs
+vendor: Test
+manufacturer: [0x41]
+port_prefix: Gs
+sysex: [0x10, 0x42, 0x12]
+checksum: roland
+reply:
+  code_pos: 2
+parameters:
+  Level:
+    sysex: [0x40, 0x00, 0x7f]
+    bounds:
+      - type: Range
+        lo: 0
+        hi: 127
+        sysex_offset: 0
+"#,
+        )
+        .unwrap();
+        let values = decode_message(&dev, &msg);
+        assert_eq!(values.get("Level"), Some(&vec!["0".to_string()]));
+        let mut corrupt = msg.clone();
+        corrupt[9] = 0x42;
+        assert!(decode_message(&dev, &corrupt).is_empty());
+    }
+
+    #[test]
+    fn test_join_fragments() {
+        let mut joiner = Joiner::new(Fragments {
+            marker_pos: 2,
+            more: 0x01,
+            last: 0x00,
+            data_pos: 3,
+            max_data: None,
+        });
+        assert_eq!(joiner.push(&[0x10, 0x20, 0x01, 0xa, 0xb]), None);
+        assert_eq!(joiner.push(&[0x10, 0x20, 0x01, 0xc]), None);
+        assert_eq!(
+            joiner.push(&[0x10, 0x20, 0x00, 0xd]),
+            Some(vec![0x10, 0x20, 0x00, 0xa, 0xb, 0xc, 0xd])
+        );
+        // unfragmented messages go straight through
+        assert_eq!(
+            joiner.push(&[0x10, 0x20, 0x00, 0xe]),
+            Some(vec![0x10, 0x20, 0x00, 0xe])
+        );
+        // a message whose last fragment was lost is dropped when another one starts
+        assert_eq!(joiner.push(&[0x10, 0x20, 0x01, 0xa]), None);
+        assert_eq!(
+            joiner.push(&[0x10, 0x21, 0x00, 0xf]),
+            Some(vec![0x10, 0x21, 0x00, 0xf])
+        );
+    }
+
+    #[test]
+    fn test_split_fragments() {
+        let fragments = Fragments {
+            marker_pos: 2,
+            more: 0x01,
+            last: 0x00,
+            data_pos: 3,
+            max_data: None,
+        };
+        let split = fragments
+            .split(&[0x10, 0x20, 0x00, 0xa, 0xb, 0xc], 2)
+            .unwrap();
+        assert!(fragments.split(&[0x10, 0x20], 2).is_none());
+        assert_eq!(
+            split,
+            vec![
+                vec![0x10, 0x20, 0x01, 0xa, 0xb],
+                vec![0x10, 0x20, 0x00, 0xc]
+            ]
+        );
+        let mut joiner = Joiner::new(fragments);
+        assert_eq!(joiner.push(&split[0]), None);
+        assert_eq!(
+            joiner.push(&split[1]),
+            Some(vec![0x10, 0x20, 0x00, 0xa, 0xb, 0xc])
+        );
+    }
+
+    /// Values exercising each end of some bounds
+    fn samples(bounds: &Bounds) -> Vec<Vec<String>> {
+        let one = |v: String| vec![v];
+        match bounds {
+            Bounds::Values(values) => values.keys().cloned().map(one).collect(),
+            Bounds::Range(range) => {
+                vec![one(range.display(range.lo)), one(range.display(range.hi))]
+            }
+            Bounds::NoteSeq(_) => vec![vec!["C2".to_string(), "G4".to_string()]],
+            Bounds::Wide(wide) => vec![one(wide.lo.to_string()), one(wide.hi.to_string())],
+            Bounds::Text(_) => vec![one("Lead".to_string())],
+            Bounds::Dump(_) => vec![one("00017f".to_string())],
+        }
+    }
+
+    /// Reply to the query of a parameter, carrying the codes of a value
+    fn reply_payload(device: &schema::Device, param: &Param, codes: Vec<u8>) -> Vec<u8> {
+        let layout = device.reply.unwrap();
+        let reply = param.schema.sysex.get(Form::Reply);
+        let start = layout
+            .sysex_pos
+            .unwrap_or_else(|| (layout.code_pos + 1).saturating_sub(reply.len()));
+        let mut payload = vec![0; start];
+        payload.extend_from_slice(reply);
+        payload.extend(param.index);
+        payload.resize(payload.len() + param.schema.reply_skip, 0);
+        if let Some(Bounds::NoteSeq(seq)) = param.bounds().first() {
+            if seq.block_len.is_some() {
+                // first block offset and length
+                payload.extend_from_slice(&[0x00, codes.len() as u8]);
+            }
+        }
+        payload.extend(codes);
+        payload
+    }
+
+    /// A valid value within some bounds, made from arbitrary picks
+    fn pick_value(bounds: &Bounds, picks: &[u16]) -> Vec<String> {
+        let pick = |i: usize| picks.get(i).copied().unwrap_or(0);
+        match bounds {
+            Bounds::Values(values) => {
+                let name = values.keys().nth(pick(0) as usize % values.len());
+                vec![name.unwrap().clone()]
+            }
+            Bounds::Range(range) => {
+                let step = pick(0) % (range.hi - range.lo + 1) as u16;
+                vec![range.display(range.lo + step as i16)]
+            }
+            Bounds::NoteSeq(seq) => {
+                let len = 1 + pick(0) as usize % seq.max_len as usize;
+                // from C1 to C6, then rests and ties
+                (1..=len)
+                    .map(|i| match pick(i) % 63 {
+                        61 if seq.rest.is_some() => REST.to_string(),
+                        62 if seq.tie.is_some() => TIE.to_string(),
+                        note => MidiNote {
+                            note: 24 + (note % 61) as u8,
+                        }
+                        .to_string(),
+                    })
+                    .collect()
+            }
+            Bounds::Wide(wide) => {
+                let step = u32::from(pick(0)) % (wide.hi - wide.lo + 1);
+                vec![(wide.lo + step).to_string()]
+            }
+            Bounds::Text(_) | Bounds::Dump(_) => samples(bounds).remove(0),
+        }
+    }
+
+    /// Random values of random bundled parameters read back as they were written
+    #[test]
+    fn test_random_round_trip() {
+        fn round_trip(device_pick: usize, param_pick: usize, picks: Vec<u16>) -> TestResult {
+            let devices: Vec<_> = schema::DEVICES
+                .values()
+                .filter(|device| device.reply.is_some())
+                .collect();
+            let device = devices[device_pick % devices.len()];
+            let params = device.params();
+            let param = &params[param_pick % params.len()];
+            let bounds = match param.bounds().first() {
+                Some(bounds) if param.schema.modes.is_none() => bounds.clone(),
+                _ => return TestResult::discard(),
+            };
+            let value = pick_value(&bounds, &picks);
+            let codes = match bound_codes(param, &value) {
+                Ok(codes) => codes,
+                Err(err) => return TestResult::error(format!("{} {:?}: {}", param, value, err)),
+            };
+            let mut values = LinkedHashMap::new();
+            decode_reply(device, &reply_payload(device, param, codes), &mut values);
+            TestResult::from_bool(values.get(&param.to_string()) == Some(&value))
+        }
+        quickcheck(round_trip as fn(usize, usize, Vec<u16>) -> TestResult);
+    }
+
+    /// Parameter names a few edits away from valid ones are rejected, or name a parameter
+    /// that reads back the same
+    #[test]
+    fn test_near_param_names() {
+        fn near_name(param_pick: usize, edits: Vec<(u8, u8)>) -> bool {
+            static CHARS: &[u8] = b"/:0123456789aeSqx";
+            let brute = schema::device("MicroBrute").unwrap();
+            let params = brute.params();
+            let mut name = params[param_pick % params.len()].to_string().into_bytes();
+            for (pos, pick) in edits {
+                let pos = pos as usize % (name.len() + 1);
+                let c = CHARS[pick as usize % CHARS.len()];
+                match pick % 3 {
+                    0 => name.insert(pos, c),
+                    1 if pos < name.len() => name[pos] = c,
+                    _ if pos < name.len() => {
+                        name.remove(pos);
+                    }
+                    _ => {}
+                }
+            }
+            let name = String::from_utf8(name).unwrap();
+            match brute.param(&name) {
+                Ok(param) => {
+                    brute.param(&param.to_string()).unwrap().to_string() == param.to_string()
+                }
+                Err(_) => true,
+            }
+        }
+        quickcheck(near_name as fn(usize, Vec<(u8, u8)>) -> bool);
+    }
+
+    /// Every value sample of every bundled parameter reads back as it was written
+    #[test]
+    fn test_round_trip() {
+        for device in schema::DEVICES.values() {
+            if device.reply.is_none() {
+                continue;
+            }
+            for param in device.params() {
+                let index: Vec<u8> = param.index.into_iter().collect();
+                let mut cases = vec![];
+                if param.schema.modes.is_some() {
+                    let prefix = param.schema.sysex.get(Form::Update).len() + index.len();
+                    for (mode_name, _) in param.modes() {
+                        let value = vec![mode_name.to_string()];
+                        let bodies = modal_codes(&param, &value).unwrap();
+                        cases.push((value, bodies[0][prefix..].to_vec()));
+                    }
+                } else {
+                    for value in param.bounds().iter().flat_map(samples) {
+                        let codes = bound_codes(&param, &value)
+                            .unwrap_or_else(|e| panic!("{} {:?}: {}", param, value, e));
+                        cases.push((value, codes));
+                    }
+                }
+                for (value, codes) in cases {
+                    let payload = reply_payload(device, &param, codes);
+                    let mut values = LinkedHashMap::new();
+                    decode_reply(device, &payload, &mut values);
+                    assert_eq!(
+                        values.get(&param.to_string()),
+                        Some(&value),
+                        "{} {}",
+                        device.name,
+                        param
+                    );
+                }
+            }
+        }
     }
-    msg.push(0xf7);
-    msg
 }
@@ -0,0 +1,300 @@
+//! Devices configured with NRPN control changes rather than sysex, described entirely by their
+//! schema. A parameter is selected by its number with CC 99 (MSB) and CC 98 (LSB), then set with
+//! CC 6, and CC 38 for the second byte of two byte values.
+
+use crate::devices::backend::{MidiBackend, MidirBackend};
+use crate::devices::{self, MidiPort};
+use crate::devices::{Connection, Descriptor, Device, Overrides, SysexQuery};
+use crate::devices::{DeviceError, Hint, Identity};
+use crate::schema::{self, Bounds, Param};
+
+use devices::Result;
+use linked_hash_map::LinkedHashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const NRPN_MSB: u8 = 99;
+const NRPN_LSB: u8 = 98;
+const DATA_MSB: u8 = 6;
+const DATA_LSB: u8 = 38;
+
+/// Control change status byte on the device's channel
+fn status(schema: &schema::Device) -> u8 {
+    0xb0 | (schema.midi_channel.unwrap_or(1).max(1) - 1) & 0x0f
+}
+
+/// NRPN number of a parameter, plus its index, as MSB and LSB
+fn number(param: &Param) -> Result<[u8; 2]> {
+    let nrpn = param
+        .schema
+        .nrpn
+        .ok_or_else(|| DeviceError::UnknownParameter {
+            param_name: param.to_string(),
+            hint: Hint::default(),
+        })?;
+    let number = nrpn + u16::from(param.index.unwrap_or(0));
+    Ok([(number >> 7) as u8 & 0x7f, number as u8 & 0x7f])
+}
+
+/// Number of data bytes of a parameter's values
+fn data_len(param: &Param) -> usize {
+    match param.bounds().first() {
+        Some(Bounds::Wide(wide)) => wide.bytes as usize,
+        _ => 1,
+    }
+}
+
+#[derive(Debug)]
+pub struct NrpnDescriptor {
+    /// Name of the device schema
+    pub name: &'static str,
+}
+
+impl NrpnDescriptor {
+    fn schema(&self) -> &'static schema::Device {
+        schema::device(self.name).expect("NRPN device schema")
+    }
+}
+
+impl Descriptor for NrpnDescriptor {
+    fn ports(&self) -> Vec<MidiPort> {
+        MidirBackend
+            .output_ports()
+            .into_iter()
+            .filter(|port| self.schema().matches_port(&port.name))
+            .collect()
+    }
+
+    fn connect(
+        &self,
+        backend: Arc<dyn MidiBackend>,
+        port: &MidiPort,
+        overrides: &Overrides,
+    ) -> Result<Box<dyn Device>> {
+        let mut connection = Connection::open(self.schema(), backend, port, overrides)?;
+        match connection.identify() {
+            Ok(identity) => connection.identity = Some(identity),
+            Err(err) => match err.downcast_ref::<DeviceError>() {
+                Some(DeviceError::NoInputPort { .. }) => connection.has_input = false,
+                // many NRPN devices do not answer identity requests
+                Some(DeviceError::NoIdentificationReply) => {}
+                _ => return Err(err),
+            },
+        }
+        Ok(Box::new(NrpnDevice { connection }))
+    }
+
+    fn encode(&self, param: &str, value_ids: &[String]) -> Result<Vec<Vec<u8>>> {
+        encode(self.schema(), param, value_ids)
+    }
+
+    fn encode_query(&self, param: &str) -> Result<Vec<Vec<u8>>> {
+        encode_query(self.schema(), param)
+    }
+
+    /// NRPN values span several messages, they are decoded by `NrpnParser`
+    fn decode(&self, _message: &[u8]) -> LinkedHashMap<String, Vec<String>> {
+        LinkedHashMap::new()
+    }
+}
+
+pub struct NrpnDevice {
+    connection: Connection,
+}
+
+impl NrpnDevice {
+    fn require_echo(&self) -> Result<()> {
+        if !self.connection.schema.nrpn_echo {
+            Err(Box::new(DeviceError::QueryUnsupported {
+                device_name: self.connection.schema.name.clone(),
+            }))
+        } else {
+            self.connection.require_input()
+        }
+    }
+
+    /// Decode the values echoed by the device on an input port, passing them to `on_value`
+    fn listen<F>(&self, port_name: &str, on_value: F) -> Result<SysexQuery>
+    where
+        F: Fn(&str, &[String]) + Send + 'static,
+    {
+        let mut parser = NrpnParser::new(self.connection.schema);
+        let (tx, replies) = mpsc::channel();
+        let values = Arc::new(Mutex::new(LinkedHashMap::new()));
+        let result_map = values.clone();
+        let listener = self.connection.backend.listen(
+            port_name,
+            Box::new(move |ts, message| {
+                debug!("← {} {}", ts, hex::encode(message));
+                if let Some((param, value)) = parser.push(message) {
+                    on_value(&param, &value);
+                    result_map.lock().unwrap().insert(param, value);
+                    let _ = tx.send(());
+                }
+            }),
+        )?;
+        Ok(SysexQuery {
+            listener,
+            values,
+            replies,
+        })
+    }
+}
+
+impl Device for NrpnDevice {
+    fn query(&mut self, params: &[String]) -> Result<LinkedHashMap<String, Vec<String>>> {
+        self.require_echo()?;
+        let values = self.listen(&self.connection.in_port_name, |_, _| {})?;
+        let mut sent = 0;
+        for param_str in params {
+            let param = self.connection.schema.param(param_str)?;
+            if let Some(firmwares) =
+                devices::unsupported_firmware(&param, &[], self.connection.identity.as_ref())
+            {
+                warn!("skipping {}, it needs {}", param, firmwares);
+                continue;
+            }
+            for msg in encode_query(self.connection.schema, param_str)? {
+                self.connection.send(&msg)?;
+            }
+            sent += 1;
+        }
+        Ok(values.close_wait(sent, self.connection.timeout))
+    }
+
+    fn update(&mut self, param_str: &str, value_ids: &[String]) -> Result<()> {
+        let param = self.connection.schema.param(param_str)?;
+        if let Some(firmwares) =
+            devices::unsupported_firmware(&param, value_ids, self.connection.identity.as_ref())
+        {
+            warn!("{} needs {}, the device may ignore it", param, firmwares);
+        }
+        for msg in encode(self.connection.schema, param_str, value_ids)? {
+            self.connection.send(&msg)?;
+        }
+        Ok(())
+    }
+
+    fn identity(&self) -> Option<&Identity> {
+        self.connection.identity.as_ref()
+    }
+
+    fn ping(&mut self, count: usize, interval: Duration) -> Result<usize> {
+        self.require_echo()?;
+        let param = self
+            .connection
+            .schema
+            .params()
+            .into_iter()
+            .find(|p| p.index.is_none())
+            .ok_or(DeviceError::EmptyParameter)?
+            .to_string();
+        let values = self.listen(&self.connection.in_port_name, |_, _| {})?;
+        for _ in 0..count {
+            for msg in encode_query(self.connection.schema, &param)? {
+                self.connection.send(&msg)?;
+            }
+            sleep(interval);
+        }
+        let received = devices::wait_replies(&values.replies, count, self.connection.timeout);
+        values.close();
+        Ok(received)
+    }
+
+    fn watch(&mut self) -> Result<()> {
+        self.connection.require_input()?;
+        devices::keep_listening(
+            self.connection.backend.as_ref(),
+            self.connection.schema,
+            &self.connection.in_port_name,
+            |port_name| {
+                self.listen(port_name, |param, value| {
+                    println!("{}: {}", param, value.join(" "))
+                })
+            },
+        )
+    }
+}
+
+/// Control changes selecting a parameter, which devices echoing NRPN answer with its value
+fn encode_query(schema: &schema::Device, param_str: &str) -> Result<Vec<Vec<u8>>> {
+    if !schema.nrpn_echo {
+        return Err(Box::new(DeviceError::QueryUnsupported {
+            device_name: schema.name.clone(),
+        }));
+    }
+    let param = schema.param(param_str)?;
+    let [msb, lsb] = number(&param)?;
+    let status = status(schema);
+    Ok(vec![
+        vec![status, NRPN_MSB, msb],
+        vec![status, NRPN_LSB, lsb],
+    ])
+}
+
+/// Control changes setting a parameter
+fn encode(schema: &schema::Device, param_str: &str, value_ids: &[String]) -> Result<Vec<Vec<u8>>> {
+    let param = schema.param(param_str)?;
+    let [msb, lsb] = number(&param)?;
+    let data = devices::bound_codes(&param, value_ids)?;
+    let status = status(schema);
+    let mut messages = vec![vec![status, NRPN_MSB, msb], vec![status, NRPN_LSB, lsb]];
+    for (control, byte) in [DATA_MSB, DATA_LSB].iter().zip(data) {
+        messages.push(vec![status, *control, byte]);
+    }
+    Ok(messages)
+}
+
+/// Follows the NRPN control changes sent by a device, to decode the values they carry
+pub struct NrpnParser {
+    schema: &'static schema::Device,
+    number: [u8; 2],
+    data_msb: Option<u8>,
+}
+
+impl NrpnParser {
+    pub fn new(schema: &'static schema::Device) -> Self {
+        NrpnParser {
+            schema,
+            number: [0x7f, 0x7f],
+            data_msb: None,
+        }
+    }
+
+    /// The parameter and value completed by a message, if any
+    pub fn push(&mut self, message: &[u8]) -> Option<(String, Vec<String>)> {
+        let (control, byte) = match message {
+            [head, control, byte] if *head == status(self.schema) => (*control, *byte),
+            _ => return None,
+        };
+        let data = match control {
+            NRPN_MSB => {
+                self.number[0] = byte;
+                return None;
+            }
+            NRPN_LSB => {
+                self.number[1] = byte;
+                return None;
+            }
+            DATA_MSB => {
+                self.data_msb = Some(byte);
+                vec![byte]
+            }
+            DATA_LSB => vec![self.data_msb?, byte],
+            _ => return None,
+        };
+        let selected = self.number;
+        let param = self
+            .schema
+            .params()
+            .into_iter()
+            .find(|p| number(p).ok() == Some(selected))?;
+        if data_len(&param) != data.len() {
+            return None;
+        }
+        let value = devices::bound_str(param.bounds(), &data)?;
+        Some((param.to_string(), vec![value]))
+    }
+}
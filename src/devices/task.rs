@@ -0,0 +1,80 @@
+//! Async access to devices, each served by a blocking task of its own that reads its replies.
+//! Queries to several devices run concurrently, and a query whose future is dropped before
+//! the device gets to it is never sent.
+
+use crate::devices::{Device, DeviceError, Result};
+
+use linked_hash_map::LinkedHashMap;
+use std::error::Error;
+use std::future::Future;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+
+/// Errors returned across tasks, device errors kept whole so their kind is not lost
+type TaskResult<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+struct Query {
+    params: Vec<String>,
+    retries: u32,
+    reply: oneshot::Sender<TaskResult<LinkedHashMap<String, Vec<String>>>>,
+}
+
+/// A connected device, moved to its own task. It is closed when dropped.
+pub struct DeviceTask {
+    device_name: String,
+    queries: mpsc::UnboundedSender<Query>,
+}
+
+impl DeviceTask {
+    /// Serve a device from a blocking task, which needs a running Tokio runtime
+    pub fn spawn(device_name: &str, mut device: Box<dyn Device>) -> Self {
+        let (queries, mut received) = mpsc::unbounded_channel::<Query>();
+        task::spawn_blocking(move || {
+            while let Some(query) = received.blocking_recv() {
+                if !query.reply.is_closed() {
+                    let values = device.query_retrying(&query.params, query.retries);
+                    let _ = query.reply.send(values.map_err(sendable));
+                }
+            }
+        });
+        DeviceTask {
+            device_name: device_name.to_string(),
+            queries,
+        }
+    }
+
+    /// Queue a query, whose values are awaited from the returned future
+    pub fn query(
+        &self,
+        params: Vec<String>,
+        retries: u32,
+    ) -> impl Future<Output = Result<LinkedHashMap<String, Vec<String>>>> + '_ {
+        let (reply, replied) = oneshot::channel();
+        let queued = self.queries.send(Query {
+            params,
+            retries,
+            reply,
+        });
+        async move {
+            queued.map_err(|_| self.closed())?;
+            match replied.await {
+                Ok(values) => values.map_err(|err| err as Box<dyn Error>),
+                Err(_) => Err(self.closed()),
+            }
+        }
+    }
+
+    /// The device's task ended, after a panic in the device
+    fn closed(&self) -> Box<dyn Error> {
+        Box::new(DeviceError::NoConnectedDevice {
+            device_name: self.device_name.clone(),
+        })
+    }
+}
+
+fn sendable(err: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+    match err.downcast::<DeviceError>() {
+        Ok(err) => err,
+        Err(err) => err.to_string().into(),
+    }
+}
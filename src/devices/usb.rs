@@ -0,0 +1,263 @@
+//! USB-MIDI transport for devices whose MIDI ports the system does not expose, talking to the
+//! device's bulk endpoints directly. Messages are those of the device's descriptor, so their
+//! message ids always start at 0, and replies split in fragments are not joined.
+
+use crate::devices::{Descriptor, Device, DeviceError, Identity, IDENTITY_REPLY, IDENTITY_REQUEST};
+use crate::devices::{DeviceType, Overrides, Result, Throttle};
+use crate::schema;
+
+use linked_hash_map::LinkedHashMap;
+use rusb::{DeviceHandle, Direction, GlobalContext, TransferType};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// USB interface class and subclass of MIDI streaming interfaces
+const AUDIO_CLASS: u8 = 0x01;
+const MIDI_STREAMING: u8 = 0x03;
+
+/// Code index number of packets starting or continuing a sysex message
+const SYSEX_START: u8 = 0x4;
+/// Code index numbers of packets ending a sysex message with one, two and three bytes
+const SYSEX_END: u8 = 0x5;
+const SYSEX_END_3: u8 = 0x7;
+
+/// USB-MIDI event packets of a message, on cable 0
+pub fn packets(message: &[u8]) -> Vec<u8> {
+    let mut packets = vec![];
+    if message.first() != Some(&0xf0) {
+        // channel messages fit in one packet, coded by their status
+        let mut packet = [message.first().map_or(0, |status| status >> 4), 0, 0, 0];
+        for (byte, value) in packet[1..].iter_mut().zip(message) {
+            *byte = *value;
+        }
+        return packet.to_vec();
+    }
+    let mut chunks = message.chunks(3).peekable();
+    while let Some(chunk) = chunks.next() {
+        let cin = if chunks.peek().is_some() {
+            SYSEX_START
+        } else {
+            SYSEX_END + chunk.len() as u8 - 1
+        };
+        packets.push(cin);
+        packets.extend_from_slice(chunk);
+        packets.resize(packets.len() + 3 - chunk.len(), 0);
+    }
+    packets
+}
+
+/// Reassembles sysex messages from USB-MIDI event packets
+#[derive(Default)]
+pub struct Unpacker {
+    pending: Vec<u8>,
+}
+
+impl Unpacker {
+    /// Complete sysex messages ended by some packets
+    pub fn push(&mut self, packets: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = vec![];
+        for packet in packets.chunks_exact(4) {
+            let len = match packet[0] & 0x0f {
+                SYSEX_START => {
+                    self.pending.extend_from_slice(&packet[1..]);
+                    continue;
+                }
+                cin @ SYSEX_END..=SYSEX_END_3 => (cin - SYSEX_END + 1) as usize,
+                _ => continue,
+            };
+            self.pending.extend_from_slice(&packet[1..=len]);
+            if self.pending.first() == Some(&0xf0) {
+                messages.push(self.pending.split_off(0));
+            } else {
+                self.pending.clear();
+            }
+        }
+        messages
+    }
+}
+
+/// Open the `unit`th USB device with the schema's vendor and product ids, if there is one
+pub fn connect(
+    dev_type: &DeviceType,
+    unit: usize,
+    overrides: &Overrides,
+) -> Result<Option<Box<dyn Device>>> {
+    let schema = dev_type.schema();
+    let (vendor_id, product_id) = match (schema.usb_vendor_id, schema.usb_product_id) {
+        (Some(vendor_id), Some(product_id)) => (vendor_id, product_id),
+        _ => return Ok(None),
+    };
+    let usb_devices = rusb::devices()?;
+    let mut matching = usb_devices.iter().filter(|device| {
+        device
+            .device_descriptor()
+            .is_ok_and(|desc| desc.vendor_id() == vendor_id && desc.product_id() == product_id)
+    });
+    let device = match matching.nth(unit - 1) {
+        Some(device) => device,
+        None => return Ok(None),
+    };
+    let config = device.active_config_descriptor()?;
+    let mut endpoints = None;
+    for interface in config.interfaces() {
+        for setting in interface.descriptors() {
+            if setting.class_code() != AUDIO_CLASS || setting.sub_class_code() != MIDI_STREAMING {
+                continue;
+            }
+            let bulk = |direction| {
+                setting
+                    .endpoint_descriptors()
+                    .find(|ep| {
+                        ep.transfer_type() == TransferType::Bulk && ep.direction() == direction
+                    })
+                    .map(|ep| ep.address())
+            };
+            if let (Some(ep_in), Some(ep_out)) = (bulk(Direction::In), bulk(Direction::Out)) {
+                endpoints = Some((setting.interface_number(), ep_in, ep_out));
+            }
+        }
+    }
+    let (interface, ep_in, ep_out) = endpoints.ok_or_else(|| DeviceError::NoConnectedDevice {
+        device_name: dev_type.to_string(),
+    })?;
+    let handle = device.open()?;
+    let _ = handle.set_auto_detach_kernel_driver(true);
+    handle.claim_interface(interface)?;
+    debug!("opened USB device {:04x}:{:04x}", vendor_id, product_id);
+    let mut usb = UsbDevice {
+        schema,
+        descriptor: dev_type.descriptor(),
+        handle,
+        ep_in,
+        ep_out,
+        timeout: overrides.timeout.unwrap_or_else(|| schema.reply_timeout()),
+        throttle: Throttle::new(overrides.send_delay.unwrap_or_else(|| schema.send_delay())),
+        identity: None,
+    };
+    usb.identity = usb.identify();
+    Ok(Some(Box::new(usb)))
+}
+
+pub struct UsbDevice {
+    schema: &'static schema::Device,
+    descriptor: Box<dyn Descriptor + Send>,
+    handle: DeviceHandle<GlobalContext>,
+    ep_in: u8,
+    ep_out: u8,
+    /// How long to wait for replies
+    timeout: Duration,
+    /// Spacing of the messages sent
+    throttle: Throttle,
+    identity: Option<Identity>,
+}
+
+impl UsbDevice {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        self.throttle.wait();
+        debug!("→ USB {}", hex::encode(message));
+        self.handle
+            .write_bulk(self.ep_out, &packets(message), self.timeout)?;
+        Ok(())
+    }
+
+    /// Sysex messages received until `expected` have arrived, or `timeout` has elapsed
+    fn receive(&mut self, expected: usize, timeout: Duration) -> Vec<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut unpacker = Unpacker::default();
+        let mut messages = vec![];
+        let mut buf = [0; 512];
+        while messages.len() < expected {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_millis(0) {
+                break;
+            }
+            match self.handle.read_bulk(self.ep_in, &mut buf, left) {
+                Ok(len) => {
+                    for message in unpacker.push(&buf[..len]) {
+                        debug!("← USB {}", hex::encode(&message));
+                        messages.push(message);
+                    }
+                }
+                Err(rusb::Error::Timeout) => break,
+                Err(err) => {
+                    debug!("USB read failed: {}", err);
+                    break;
+                }
+            }
+        }
+        messages
+    }
+
+    fn identify(&mut self) -> Option<Identity> {
+        self.send(IDENTITY_REQUEST).ok()?;
+        let timeout = self.timeout;
+        self.receive(1, timeout)
+            .into_iter()
+            .filter(|msg| msg.len() > IDENTITY_REPLY.len() + 2)
+            .find(|msg| msg[1..].starts_with(IDENTITY_REPLY))
+            .and_then(|msg| Identity::parse(&msg[IDENTITY_REPLY.len() + 1..msg.len() - 1]))
+    }
+
+    /// Values decoded from the replies to some queries
+    fn replies(&mut self, queries: Vec<Vec<u8>>) -> Result<LinkedHashMap<String, Vec<String>>> {
+        for msg in &queries {
+            self.send(msg)?;
+        }
+        let timeout = self.timeout;
+        let mut values = LinkedHashMap::new();
+        for msg in self.receive(queries.len(), timeout) {
+            values.extend(self.descriptor.decode(&msg));
+        }
+        Ok(values)
+    }
+}
+
+impl Device for UsbDevice {
+    fn query(&mut self, params: &[String]) -> Result<LinkedHashMap<String, Vec<String>>> {
+        let mut queries = vec![];
+        for param in params {
+            queries.extend(self.descriptor.encode_query(param)?);
+        }
+        self.replies(queries)
+    }
+
+    fn update(&mut self, param: &str, value_ids: &[String]) -> Result<()> {
+        for msg in self.descriptor.encode(param, value_ids)? {
+            self.send(&msg)?;
+        }
+        Ok(())
+    }
+
+    fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    fn ping(&mut self, count: usize, interval: Duration) -> Result<usize> {
+        let param = self
+            .schema
+            .params()
+            .into_iter()
+            .find(|p| p.index.is_none())
+            .ok_or(DeviceError::EmptyParameter)?
+            .to_string();
+        let query = self.descriptor.encode_query(&param)?;
+        let mut received = 0;
+        for _ in 0..count {
+            for msg in &query {
+                self.send(msg)?;
+            }
+            received += self.receive(query.len(), interval.max(self.timeout)).len();
+        }
+        Ok(received)
+    }
+
+    fn watch(&mut self) -> Result<()> {
+        loop {
+            for msg in self.receive(usize::MAX, Duration::from_secs(1)) {
+                for (param, value) in self.descriptor.decode(&msg) {
+                    println!("{}: {}", param, value.join(" "));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+use crate::devices::Result;
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of changes kept, older ones are forgotten
+const MAX_ENTRIES: usize = 100;
+
+/// Previous values of parameters changed by `set`, most recent last
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub device: String,
+    pub param: String,
+    /// Value before the change
+    pub values: Vec<String>,
+}
+
+pub fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("la_bruteforce").join("journal.yaml"))
+}
+
+/// Load the journal, or an empty journal if there is none
+pub fn load() -> Result<Journal> {
+    match path() {
+        Some(path) if path.exists() => Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?),
+        _ => Ok(Journal::default()),
+    }
+}
+
+impl Journal {
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = path() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(path, serde_yaml::to_string(self)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    /// Remove and return the most recent change
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::journal::{Entry, Journal, MAX_ENTRIES};
+
+    #[test]
+    fn test_push_pop() {
+        let mut journal = Journal::default();
+        for i in 0..MAX_ENTRIES + 2 {
+            journal.push(Entry {
+                device: "MicroBrute".to_string(),
+                param: "BendRange".to_string(),
+                values: vec![i.to_string()],
+            });
+        }
+        let last = journal.pop().unwrap();
+        assert_eq!(last.values, vec![(MAX_ENTRIES + 1).to_string()]);
+        let mut count = 1;
+        while journal.pop().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, MAX_ENTRIES);
+    }
+}
@@ -1,36 +1,105 @@
 extern crate strum;
 #[macro_use]
 extern crate strum_macros;
+#[macro_use]
+extern crate lazy_static;
 
+mod cache;
+mod config;
 mod devices;
+mod journal;
+#[cfg(feature = "codegen")]
+#[allow(dead_code)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+}
+mod output;
 mod schema;
+mod seq;
+mod smf;
+mod snapshot;
+mod syx;
 
-use midir::MidiOutput;
+use midir::{MidiInput, MidiOutput};
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use strum::IntoEnumIterator;
 
-use crate::devices::{DeviceError, DeviceType};
+use crate::devices::{DeviceError, DeviceType, ErrorKind, Identity, MidiPort, Overrides};
 
 #[derive(StructOpt, Debug)]
 #[structopt(
     name = "la_bruteforce",
     about = "La BruteForce is used to edit Arturia devices hidden parameters"
 )]
+struct Opt {
+    /// MIDI backend to use, must match the one this program was built with
+    #[structopt(long = "backend")]
+    backend: Option<String>,
+
+    /// Format of command results, `text` or `json`
+    #[structopt(long = "output", default_value = "text")]
+    output: Format,
+
+    /// How long to wait for missing device replies, e.g. `200ms`, overriding the device's default
+    #[structopt(long = "timeout", parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+
+    /// Output port of the device, overriding the one found by its name
+    #[structopt(long = "out-port")]
+    out_port: Option<String>,
+
+    /// Input port the device replies on, when its name differs from the output port's
+    #[structopt(long = "in-port")]
+    in_port: Option<String>,
+
+    /// Pause between consecutive messages to the device, e.g. `20ms`, overriding its default
+    #[structopt(long = "throttle", parse(try_from_str = parse_duration))]
+    throttle: Option<Duration>,
+
+    /// How many times to ask again for values that got no reply
+    #[structopt(long = "retries", default_value = "2")]
+    retries: u32,
+
+    /// Do not ask for confirmation before overwriting sequences or restoring backups
+    #[structopt(short = "y", long = "yes")]
+    yes: bool,
+
+    /// Log MIDI traffic to stderr, `-vv` to also log how replies are decoded
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+
+    /// Format of error messages, `text` or `json`
+    #[structopt(long = "errors", default_value = "text")]
+    errors: Format,
+
+    #[structopt(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(StructOpt, Debug)]
 enum Cmd {
     /// All active devices
     Ports,
 
+    /// Ask every port for the identity of its device, and match it to a known device
+    Scan,
+
     /// All known devices
     Devices,
 
     /// A single device's possible parameters
     Params {
-        /// Name of the device as listed
+        /// Name of the device as listed, of a device group, or `Device/*` for all its units
         device_name: String,
+        /// Also show the sysex codes, bounds and mode fields of each parameter
+        #[structopt(long)]
+        detailed: bool,
     },
 
+    /// A single parameter's possible values
     Bounds {
-        /// Name of the device as listed
+        /// Name of the device as listed, of a device group, or `Device/*` for all its units
         device_name: String,
         /// Name of the param as listed
         param_name: String,
@@ -39,96 +108,1631 @@ enum Cmd {
     #[structopt(name = "get")]
     /// Get a device's parameter value
     Get {
-        /// Name of the device as listed
+        /// Name of the device as listed, of a device group, or `Device/*` for all its units
         device_name: String,
-        /// Name of the param as listed
+        /// Name of the params as listed, `*` for all params, `Seq/*` for all indexes of `Seq`
         param_names: Vec<String>,
+        /// Answer from the values last read or written, without connecting to the device
+        #[structopt(long = "cached")]
+        cached: bool,
+        /// Show sequences as a grid of steps across and notes down
+        #[structopt(long = "roll")]
+        roll: bool,
     },
 
-    #[structopt(name = "set")]
-    /// Set a device's parameter value
+    #[structopt(name = "set", setting = AppSettings::AllowNegativeNumbers)]
+    /// Set a device's parameter values, e.g. `Gate Long Sync Internal` sets two parameters
     Set {
+        /// Name of the device as listed, of a device group, or `Device/*` for all its units
+        device_name: String,
+        /// Name of the param as listed
+        param_name: String,
+        /// New bound value of the param, or a change relative to the current value: `+2`, `-1` or `toggle`,
+        /// possibly followed by other params and their values
+        value_ids: Vec<String>,
+        /// Print the sysex messages instead of sending them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Copy the value of a parameter to another, e.g. a sequence to another slot
+    Copy {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the param to read, e.g. `Seq/1`
+        from_param: String,
+        /// Name of the param to write, e.g. `Seq/5`
+        to_param: String,
+    },
+
+    /// Device family, model and firmware version
+    Identify {
+        /// Name of the device as listed, of a device group, or `Device/*` for all its units
+        device_name: String,
+    },
+
+    /// Show how a parameter and its values are encoded, without sending anything
+    Explain {
         /// Name of the device as listed
         device_name: String,
         /// Name of the param as listed
         param_name: String,
-        /// New bound value of the param
+        /// Value to set, if omitted the value query is explained
         value_ids: Vec<String>,
     },
+
+    /// Revert the most recent change made by set
+    Undo,
+
+    /// Save all of a device's parameter values to a file
+    Backup {
+        /// Name of the device as listed
+        device_name: String,
+        /// File to write, stdout if not specified
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+
+    /// Apply parameter values saved by backup to a device
+    Restore {
+        /// Name of the device as listed
+        device_name: String,
+        /// File to read
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Print the sysex messages instead of sending them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Set all parameters of a device to their factory value
+    Reset {
+        /// Name of the device as listed
+        device_name: String,
+        /// Print the sysex messages instead of sending them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Show parameters whose current value differs from a backup file
+    Diff {
+        /// Name of the device as listed
+        device_name: String,
+        /// File to compare against
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// Print parameter changes made on a device as they happen, until interrupted
+    Watch {
+        /// Name of the device as listed
+        device_name: String,
+    },
+
+    /// Identify devices as they are connected, restoring the profile configured for each
+    Daemon {
+        /// How often to look for new ports, e.g. `500ms`
+        #[structopt(long = "interval", default_value = "1s", parse(try_from_str = parse_duration))]
+        interval: Duration,
+    },
+
+    /// Print all sysex messages received on a MIDI input port, until interrupted
+    Sniff {
+        /// Name (or name prefix) of the input port as listed
+        port_name: String,
+    },
+
+    /// Send the sysex messages of a .syx file to a MIDI output port
+    Send {
+        /// Name (or name prefix) of the output port as listed
+        port_name: String,
+        /// File to send
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Pause between messages, e.g. `20ms`
+        #[structopt(long = "delay", default_value = "20ms", parse(try_from_str = parse_duration))]
+        delay: Duration,
+    },
+
+    /// Record sysex messages from a MIDI input port to a .syx file
+    Receive {
+        /// Name (or name prefix) of the input port as listed
+        port_name: String,
+        /// File to write
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+        /// Hex encoded sysex message to send before recording, e.g. a dump request
+        #[structopt(long = "trigger")]
+        trigger: Option<String>,
+        /// Stop recording after this long without receiving a message
+        #[structopt(long = "duration", default_value = "2s", parse(try_from_str = parse_duration))]
+        duration: Duration,
+    },
+
+    /// Decode sysex messages captured elsewhere, as the parameter values they carry
+    Decode {
+        /// Hex encoded sysex messages, or .syx files
+        inputs: Vec<String>,
+    },
+
+    /// MIDI backend in use and its known limitations
+    Backend,
+
+    /// Sequence tools
+    Seq {
+        #[structopt(subcommand)]
+        cmd: SeqCmd,
+    },
+
+    /// Device schema tools
+    Schema {
+        #[structopt(subcommand)]
+        cmd: SchemaCmd,
+    },
+
+    /// Query a device at increasing rates to find when it starts dropping replies
+    Stress {
+        /// Name of the device as listed
+        device_name: String,
+        /// Maximum test duration, e.g. `60s` or `500ms`
+        #[structopt(long = "duration", default_value = "60s", parse(try_from_str = parse_duration))]
+        duration: Duration,
+    },
 }
 
-use crate::devices::Bounds;
+#[derive(StructOpt, Debug)]
+enum SeqCmd {
+    /// Set a sequence to the notes of a MIDI file, from its first track with notes
+    Import {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/3`
+        param_name: String,
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Steps in a quarter note
+        #[structopt(long = "steps-per-beat", default_value = "4")]
+        steps_per_beat: u16,
+        /// Print the sysex messages instead of sending them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
+    /// Set one step of a sequence, leaving the others as they are
+    SetStep {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+        /// Step to set, from 1
+        step: usize,
+        /// Note of the step, e.g. `E3`, or `rest`
+        note: String,
+    },
+
+    /// Insert steps in a sequence, before a step
+    Insert {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+        /// Step to insert before, from 1
+        step: usize,
+        /// Notes of the steps inserted, e.g. `E3`, or `rest`
+        notes: Vec<String>,
+    },
+
+    /// Remove steps from a sequence
+    Delete {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+        /// First step removed, from 1
+        step: usize,
+        /// Number of steps removed
+        #[structopt(default_value = "1")]
+        count: usize,
+    },
+
+    /// Move the steps of a sequence later, after rests, or earlier if negative
+    #[structopt(setting = AppSettings::AllowNegativeNumbers)]
+    Shift {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+        /// Steps to move by
+        offset: i32,
+    },
+
+    /// Play a sequence backwards
+    Reverse {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+    },
+
+    /// Move the steps of a sequence later, those past the end coming back at the start, or
+    /// earlier if negative
+    #[structopt(setting = AppSettings::AllowNegativeNumbers)]
+    Rotate {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+        /// Steps to move by
+        offset: i32,
+    },
+
+    /// Copy a sequence to another device, or another sequence of the same device
+    Copy {
+        /// Name of the device to read, as listed
+        from_device: String,
+        /// Name of the sequence param to read, e.g. `Seq/1`
+        from_param: String,
+        /// Name of the device to write, as listed
+        to_device: String,
+        /// Name of the sequence param to write
+        to_param: String,
+    },
+
+    /// Move the notes of a sequence to the nearest notes of a scale
+    Quantize {
+        /// Name of the device as listed
+        device_name: String,
+        /// Name of the sequence param, e.g. `Seq/1`
+        param_name: String,
+        /// Root note and mode, e.g. `D-dorian`, `F#-minor` or `C-major-pentatonic`
+        #[structopt(long = "scale")]
+        scale: seq::Scale,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum SchemaCmd {
+    /// Print all device schemas as loaded in memory
+    DumpResolved,
+
+    /// Check a device schema file for mistakes, or all bundled schemas if none is given
+    Lint {
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+
+    /// Print a JSON Schema of the device schema format, for editor completion and validation
+    ExportJsonschema,
+}
+
+use crate::config::Config;
+use crate::devices::backend::{MidiBackend, MidirBackend};
+#[cfg(feature = "async")]
+use crate::devices::task::DeviceTask;
+use crate::devices::Device;
 use crate::devices::CLIENT_NAME;
+use crate::output::{
+    BackendInfo, ChangeInfo, DecodedInfo, DecodedValues, DiffInfo, ExplainInfo, FieldDetail,
+    Format, IdentityInfo, ModeDetail, ParamDetail, ParamInfo, PortInfo, Report, RestoredInfo,
+    RollInfo, ScanInfo, SetInfo,
+};
+use crate::schema::{Bounds, Form, NoteSeq};
+use crate::snapshot::Snapshot;
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tracing::Level;
 
-fn main() -> devices::Result<()> {
-    let cmd = Cmd::from_args();
+fn main() {
+    let opt = Opt::from_args();
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_target(false)
+        .with_max_level(match opt.verbose {
+            0 => Level::WARN,
+            1 => Level::DEBUG,
+            _ => Level::TRACE,
+        })
+        .init();
+    let errors = opt.errors;
+    if let Err(err) = run(opt) {
+        let kind = ErrorKind::of(err.as_ref());
+        errors.print_error(kind, err.as_ref());
+        process::exit(kind as i32);
+    }
+}
 
-    match cmd {
-        Cmd::Ports => {
-            let midi_client = MidiOutput::new(CLIENT_NAME)?;
-            devices::output_ports(&midi_client)
-                .iter()
-                .for_each(|port| println!("{}", port.name))
-        }
-        Cmd::Devices => DeviceType::iter().for_each(|dev| println!("{}", dev)),
-        Cmd::Params { device_name } => {
-            let dev = DeviceType::from_str(&device_name)?;
-            for param in dev.descriptor().globals() {
-                println!("{}", param);
-            }
-        }
+fn run(opt: Opt) -> devices::Result<()> {
+    let config = config::load()?;
+
+    if let Some(backend) = &opt.backend {
+        devices::check_backend(backend)?;
+    }
+
+    let ctx = Context {
+        config,
+        format: opt.output,
+        timeout: opt.timeout,
+        out_port: opt.out_port,
+        in_port: opt.in_port,
+        throttle: opt.throttle,
+        retries: opt.retries,
+        yes: opt.yes,
+    };
+    let format = ctx.format;
+    match opt.cmd {
+        Cmd::Ports => format.print(&ports()?)?,
+        Cmd::Scan => format.print(&scan(&ctx)?)?,
+        Cmd::Devices => format.print(&Report::List(
+            DeviceType::iter().map(|dev| dev.to_string()).collect(),
+        ))?,
+        Cmd::Params {
+            device_name,
+            detailed,
+        } => for_each_device(&ctx, &device_name, |dev| {
+            format.print(&params(dev, detailed)?)
+        })?,
         Cmd::Bounds {
             device_name,
             param_name,
+        } => for_each_device(&ctx, &device_name, |dev| {
+            format.print(&bounds(dev, ctx.config.param_name(&param_name))?)
+        })?,
+        Cmd::Set {
+            device_name,
+            param_name,
+            value_ids,
+            dry_run,
         } => {
-            let dev = DeviceType::from_str(&device_name)?;
-            match dev.descriptor().bounds(&param_name)? {
-                Bounds::Discrete(values) => {
-                    for bound in values {
-                        println!("{}", bound.1)
-                    }
+            let mut args = vec![param_name];
+            args.extend(value_ids);
+            let (device_name, args) = default_device(&ctx, device_name, args);
+            for_each_device(&ctx, &device_name, |dev| {
+                format.print(&set(&ctx, dev, &args, dry_run)?)
+            })?
+        }
+        Cmd::Get {
+            device_name,
+            param_names,
+            cached,
+            roll,
+        } => {
+            let (device_name, param_names) = default_device(&ctx, device_name, param_names);
+            #[cfg(feature = "async")]
+            {
+                if !cached && is_group(&ctx, &device_name) {
+                    return get_group(&ctx, &device_name, &param_names, roll);
                 }
-                Bounds::Range(_offset, (lo, hi)) => println!("[{}..{}]", lo, hi),
-                Bounds::NoteSeq(_) => println!("note1 note2 note3 ..."),
             }
+            for_each_device(&ctx, &device_name, |dev| {
+                match get(&ctx, dev, &param_names, cached)? {
+                    Report::Values(values) if roll => format.print(&rolls(dev, values)?),
+                    report => format.print(&report),
+                }
+            })?
         }
-        Cmd::Set {
+        Cmd::Copy {
+            device_name,
+            from_param,
+            to_param,
+        } => format.print(&copy(
+            &ctx,
+            &device_name,
+            &from_param,
+            &device_name,
+            &to_param,
+        )?)?,
+        Cmd::Identify { device_name } => for_each_device(&ctx, &device_name, |dev| {
+            format.print(&identify(&ctx, dev)?)
+        })?,
+        Cmd::Explain {
             device_name,
             param_name,
             value_ids,
+        } => format.print(&explain(
+            &device_name,
+            ctx.config.param_name(&param_name),
+            &value_ids,
+        )?)?,
+        Cmd::Undo => format.print(&undo(&ctx)?)?,
+        Cmd::Backup { device_name, file } => backup(&ctx, &device_name, file.as_deref())?,
+        Cmd::Restore {
+            device_name,
+            input,
+            dry_run,
+        } => restore(&ctx, &device_name, &input, dry_run)?,
+        Cmd::Reset {
+            device_name,
+            dry_run,
+        } => reset(&ctx, &device_name, dry_run)?,
+        Cmd::Diff { device_name, input } => format.print(&diff(&ctx, &device_name, &input)?)?,
+        Cmd::Watch { device_name } => connect(&ctx, &device_name)?.watch()?,
+        Cmd::Daemon { interval } => daemon(&ctx, interval)?,
+        Cmd::Sniff { port_name } => devices::sniff(&port_name)?,
+        Cmd::Send {
+            port_name,
+            input,
+            delay,
+        } => devices::send_raw(&port_name, &syx::parse(&fs::read(input)?)?, delay)?,
+        Cmd::Receive {
+            port_name,
+            file,
+            trigger,
+            duration,
         } => {
-            let dev = DeviceType::from_str(&device_name)?.descriptor();
-            let midi_client = MidiOutput::new(CLIENT_NAME)?;
-            if let Some(port) = dev.ports().get(0) {
-                let mut sysex = dev.connect(midi_client, port)?;
-                sysex.update(&param_name, &value_ids)?;
-            } else {
-                return Err(Box::new(DeviceError::NoConnectedDevice { device_name }));
-            }
+            let trigger = match trigger {
+                Some(hex) => Some(hex::decode(hex)?),
+                None => None,
+            };
+            let messages = devices::receive_raw(&port_name, trigger.as_deref(), duration)?;
+            fs::write(file, messages.concat())?;
+            let mut received = LinkedHashMap::new();
+            received.insert("received".to_string(), vec![messages.len().to_string()]);
+            format.print(&Report::Values(received))?
         }
-        Cmd::Get {
+        Cmd::Decode { inputs } => format.print(&decode(&inputs)?)?,
+        Cmd::Backend => format.print(&backend_info())?,
+        Cmd::Schema { cmd } => match cmd {
+            SchemaCmd::DumpResolved => schema::dump_resolved()?,
+            SchemaCmd::Lint { file } => lint(file.as_deref())?,
+            SchemaCmd::ExportJsonschema => schema::export_json_schema()?,
+        },
+        Cmd::Seq { cmd } => format.print(&match cmd {
+            SeqCmd::Import {
+                device_name,
+                param_name,
+                file,
+                steps_per_beat,
+                dry_run,
+            } => seq_import(
+                &ctx,
+                &device_name,
+                &param_name,
+                &file,
+                steps_per_beat,
+                dry_run,
+            )?,
+            SeqCmd::SetStep {
+                device_name,
+                param_name,
+                step,
+                note,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, max_len| {
+                seq::set_step(steps, step, &note, max_len)
+            })?,
+            SeqCmd::Insert {
+                device_name,
+                param_name,
+                step,
+                notes,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, max_len| {
+                seq::insert(steps, step, &notes, max_len)
+            })?,
+            SeqCmd::Delete {
+                device_name,
+                param_name,
+                step,
+                count,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, _| {
+                seq::delete(steps, step, count)
+            })?,
+            SeqCmd::Shift {
+                device_name,
+                param_name,
+                offset,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, max_len| {
+                seq::shift(steps, offset, max_len)
+            })?,
+            SeqCmd::Reverse {
+                device_name,
+                param_name,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, _| {
+                seq::reverse(steps);
+                Ok(())
+            })?,
+            SeqCmd::Rotate {
+                device_name,
+                param_name,
+                offset,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, _| {
+                seq::rotate(steps, offset);
+                Ok(())
+            })?,
+            SeqCmd::Copy {
+                from_device,
+                from_param,
+                to_device,
+                to_param,
+            } => seq_copy(&ctx, &from_device, &from_param, &to_device, &to_param)?,
+            SeqCmd::Quantize {
+                device_name,
+                param_name,
+                scale,
+            } => edit_seq(&ctx, &device_name, &param_name, |steps, _| {
+                seq::to_scale(steps, &scale)
+            })?,
+        })?,
+        Cmd::Stress {
             device_name,
-            mut param_names,
-        } => {
-            let dev = DeviceType::from_str(&device_name)?.descriptor();
-            let midi_client = MidiOutput::new(CLIENT_NAME)?;
-            let port = dev
+            duration,
+        } => stress(&ctx, &device_name, duration)?,
+    }
+
+    Ok(())
+}
+
+/// Options and settings shared by all commands
+struct Context {
+    config: Config,
+    format: Format,
+    /// Reply timeout overriding the device schema's
+    timeout: Option<Duration>,
+    /// Port names overriding those found for the device
+    out_port: Option<String>,
+    in_port: Option<String>,
+    /// Pause between messages overriding the device schema's
+    throttle: Option<Duration>,
+    /// Times unanswered queries are sent again
+    retries: u32,
+    /// Skip confirmation of destructive operations
+    yes: bool,
+}
+
+/// All input and output ports paired by name, with the device connected to each
+fn ports() -> devices::Result<Report> {
+    let midi_out = MidiOutput::new(CLIENT_NAME)?;
+    let midi_in = MidiInput::new(CLIENT_NAME)?;
+    let mut units = HashMap::new();
+    for dev_type in DeviceType::iter() {
+        for (idx, port) in dev_type.ports().into_iter().enumerate() {
+            units.insert(port.name, format!("{}/{}", dev_type, idx + 1));
+        }
+    }
+    let mut ports: Vec<PortInfo> = vec![];
+    for port in devices::output_ports(&midi_out) {
+        ports.push(PortInfo {
+            device: units.get(&port.name).cloned(),
+            output: Some(devices::can_open_output(&port)),
+            input: None,
+            name: port.name,
+        });
+    }
+    for port in devices::input_ports(&midi_in) {
+        let usable = devices::can_open_input(&port);
+        match ports.iter_mut().find(|p| p.name == port.name) {
+            Some(paired) => paired.input = Some(usable),
+            None => ports.push(PortInfo {
+                device: DeviceType::iter()
+                    .find(|dev| dev.schema().matches_port(&port.name))
+                    .map(|dev| dev.to_string()),
+                output: None,
+                input: Some(usable),
+                name: port.name,
+            }),
+        }
+    }
+    Ok(Report::Ports(ports))
+}
+
+/// The values of each sysex message, for each device that could have sent it
+fn decode(inputs: &[String]) -> devices::Result<Report> {
+    let mut decoded = vec![];
+    for input in inputs {
+        let bytes = if Path::new(input).is_file() {
+            fs::read(input)?
+        } else {
+            hex::decode(input.replace(' ', ""))?
+        };
+        for message in syx::parse(&bytes)? {
+            let devices = devices::decode_any(&message)
+                .into_iter()
+                .map(|(dev_type, values)| {
+                    let (values, error) = match values {
+                        Ok(values) => (values, None),
+                        Err(reason) => (LinkedHashMap::new(), Some(reason)),
+                    };
+                    DecodedValues {
+                        device: dev_type.to_string(),
+                        values,
+                        error,
+                    }
+                })
+                .collect();
+            decoded.push(DecodedInfo {
+                message: hex::encode(&message),
+                devices,
+            });
+        }
+    }
+    Ok(Report::Decoded(decoded))
+}
+
+/// How long `scan` waits for identity replies, unless overridden by `--timeout`
+const SCAN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Identity of the device on each output port, and the known device it matches. Devices
+/// are matched by their identity codes, or by their manufacturer and port name for schemas
+/// without identity codes.
+fn scan(ctx: &Context) -> devices::Result<Report> {
+    let mut scanned = vec![];
+    for (port, identity) in devices::scan(ctx.timeout.unwrap_or(SCAN_TIMEOUT))? {
+        let identity = match identity {
+            Some(identity) => identity,
+            None => {
+                scanned.push(ScanInfo {
+                    port: port.name,
+                    identity: None,
+                    device: None,
+                });
+                continue;
+            }
+        };
+        let device = identified_device(&identity, &port.name);
+        let vendor = DeviceType::iter()
+            .map(|dev| dev.schema())
+            .find(|schema| schema.manufacturer == identity.manufacturer)
+            .map(|schema| schema.vendor.clone());
+        scanned.push(ScanInfo {
+            port: port.name,
+            identity: Some(IdentityInfo {
+                manufacturer: hex::encode(&identity.manufacturer),
+                vendor,
+                family: format!("{:04x}", identity.family),
+                model: format!("{:04x}", identity.model),
+                firmware: identity.firmware_version(),
+            }),
+            device: device.map(|dev| dev.to_string()),
+        });
+    }
+    Ok(Report::Scan(scanned))
+}
+
+/// Known device matching an identity reply, told apart by its port name when its schema
+/// has no identity codes
+fn identified_device(identity: &Identity, port_name: &str) -> Option<DeviceType> {
+    DeviceType::iter()
+        .find(|dev| dev.schema().identity.is_some() && identity.is(dev.schema()))
+        .or_else(|| {
+            DeviceType::iter()
+                .find(|dev| identity.is(dev.schema()) && dev.schema().matches_port(port_name))
+        })
+}
+
+/// Identify the devices on ports that appear, restoring the profiles configured for them.
+/// Ports present when starting are left alone.
+fn daemon(ctx: &Context, interval: Duration) -> devices::Result<()> {
+    let midi_client = MidiOutput::new(CLIENT_NAME)?;
+    let mut present: Vec<String> = devices::output_ports(&midi_client)
+        .into_iter()
+        .map(|port| port.name)
+        .collect();
+    loop {
+        sleep(interval);
+        let ports = devices::output_ports(&midi_client);
+        let arrived: Vec<MidiPort> = ports
+            .iter()
+            .filter(|port| !present.contains(&port.name))
+            .cloned()
+            .collect();
+        present = ports.into_iter().map(|port| port.name).collect();
+        if arrived.is_empty() {
+            continue;
+        }
+        for (port, identity) in devices::scan_ports(arrived, ctx.timeout.unwrap_or(SCAN_TIMEOUT)) {
+            let device = identity
+                .and_then(|identity| identified_device(&identity, &port.name))
+                .or_else(|| DeviceType::iter().find(|dev| dev.schema().matches_port(&port.name)));
+            let dev_type = match device {
+                Some(dev_type) => dev_type,
+                None => {
+                    println!("{}: unknown device", port.name);
+                    continue;
+                }
+            };
+            let unit = dev_type
                 .ports()
-                .get(0)
-                .cloned()
-                .ok_or(DeviceError::NoOutputPort {
-                    port_name: device_name,
-                })?;
-            let mut sysex = dev.connect(midi_client, &port)?;
-            if param_names.is_empty() {
-                param_names = dev.globals().iter().map(|p| p.to_string()).collect();
+                .iter()
+                .position(|p| p.name == port.name)
+                .map_or(1, |i| i + 1);
+            let device_name = match unit {
+                1 => dev_type.to_string(),
+                unit => format!("{}/{}", dev_type, unit),
+            };
+            println!("{}: {} connected", port.name, device_name);
+            if let Some(profile) = ctx.config.profiles.get(&dev_type.to_string()) {
+                match load_snapshot(&dev_type, profile)
+                    .and_then(|snapshot| apply(ctx, &device_name, &snapshot.values, false))
+                {
+                    Ok(()) => println!("{}: restored {}", device_name, profile.display()),
+                    Err(err) => eprintln!(
+                        "warning: could not restore {} to {}: {}",
+                        profile.display(),
+                        device_name,
+                        err
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Commands can omit the device name if a default device is configured, in which case
+/// the first argument is the first of the command's other arguments
+fn default_device(ctx: &Context, first: String, mut args: Vec<String>) -> (String, Vec<String>) {
+    match &ctx.config.device {
+        Some(device) if !is_group(ctx, &first) && DeviceType::from_name(&first).is_err() => {
+            args.insert(0, first);
+            (device.clone(), args)
+        }
+        _ => (first, args),
+    }
+}
+
+/// Suffix of a device name designating all its connected units, as in `MicroBrute/*`
+const ALL_UNITS: &str = "/*";
+
+/// Whether a name designates several devices: a group, or every unit of a device
+fn is_group(ctx: &Context, name: &str) -> bool {
+    ctx.config.groups.contains_key(name) || name.ends_with(ALL_UNITS)
+}
+
+/// The devices designated by a name: the members of a group, every connected unit of a device,
+/// or else the named device
+fn device_names(ctx: &Context, name: &str) -> devices::Result<Vec<String>> {
+    let mut names = vec![];
+    for member in ctx.config.devices(name) {
+        match member.strip_suffix(ALL_UNITS) {
+            Some(device_name) => {
+                let dev_type = DeviceType::from_name(device_name)?.0;
+                let units = dev_type.ports().len();
+                if units == 0 {
+                    return Err(Box::new(DeviceError::NoConnectedDevice {
+                        device_name: device_name.to_string(),
+                    }));
+                }
+                names.extend((1..=units).map(|unit| format!("{}/{}", dev_type, unit)));
+            }
+            None => names.push(member),
+        }
+    }
+    Ok(names)
+}
+
+/// Apply an operation to a single device or to every member of a device group,
+/// printing a section header for each member of a group.
+fn for_each_device<F>(ctx: &Context, name: &str, mut op: F) -> devices::Result<()>
+where
+    F: FnMut(&str) -> devices::Result<()>,
+{
+    let devices = device_names(ctx, name)?;
+    if devices.len() == 1 && devices[0] == name {
+        return op(name);
+    }
+    let mut result = Ok(());
+    for device_name in devices {
+        ctx.format.section(&device_name);
+        if let Err(err) = op(&device_name) {
+            eprintln!("{}: {}", device_name, err);
+            result = Err(err);
+        }
+    }
+    result
+}
+
+fn params(device_name: &str, detailed: bool) -> devices::Result<Report> {
+    let dev = DeviceType::from_name(device_name)?.0;
+    let params = dev
+        .schema()
+        .parameters
+        .iter()
+        .map(|(name, param)| ParamInfo {
+            name: name.to_string(),
+            index: param.index.as_ref().map(|range| (range.lo, range.hi)),
+            modes: param
+                .modes
+                .iter()
+                .flatten()
+                .map(|(mode_name, mode)| (mode_name.clone(), mode.fields.keys().cloned().collect()))
+                .collect(),
+            detail: if detailed {
+                Some(param_detail(param))
+            } else {
+                None
+            },
+        })
+        .collect();
+    Ok(Report::Params(params))
+}
+
+fn param_detail(param: &schema::Parameter) -> ParamDetail {
+    let modes = param
+        .modes
+        .iter()
+        .flatten()
+        .map(|(mode_name, mode)| {
+            let fields = mode
+                .fields
+                .iter()
+                .map(|(field_name, field)| {
+                    let field = FieldDetail {
+                        sysex: hex::encode(field.sysex.get(Form::Update)),
+                        bounds: field.bounds.clone(),
+                    };
+                    (field_name.clone(), field)
+                })
+                .collect();
+            let mode = ModeDetail {
+                sysex: hex::encode(&mode.sysex),
+                fields,
+            };
+            (mode_name.clone(), mode)
+        })
+        .collect();
+    ParamDetail {
+        update: hex::encode(param.sysex.get(Form::Update)),
+        query: hex::encode(param.sysex.get(Form::Query)),
+        bounds: param.bounds.clone().unwrap_or_default(),
+        modes,
+    }
+}
+
+fn bounds(device_name: &str, param_name: &str) -> devices::Result<Report> {
+    let dev = DeviceType::from_name(device_name)?.0;
+    let param = dev.schema().param(param_name)?;
+    let mut examples: Vec<String> = param
+        .bounds()
+        .iter()
+        .map(|bounds| format!("la_bruteforce set {} {} {}", dev, param, bounds.example()))
+        .collect();
+    let mut modes = LinkedHashMap::new();
+    for (mode_name, mode) in param.modes() {
+        if param.mode.is_some_and(|m| m != mode_name) {
+            continue;
+        }
+        let mut fields = LinkedHashMap::new();
+        let mut example = format!("la_bruteforce set {} {} {}", dev, param, mode_name);
+        if param.mode.is_some() {
+            example = format!("la_bruteforce set {} {}", dev, param);
+        }
+        for (field_name, field) in &mode.fields {
+            fields.insert(field_name.clone(), field.bounds.clone());
+            if let Some(bounds) = field.bounds.first() {
+                example += &format!(" {}={}", field_name, bounds.example());
+            }
+        }
+        modes.insert(mode_name.to_string(), fields);
+        examples.push(example);
+    }
+    Ok(Report::Bounds {
+        bounds: param.bounds().to_vec(),
+        modes,
+        examples,
+    })
+}
+
+fn explain(device_name: &str, param_name: &str, value_ids: &[String]) -> devices::Result<Report> {
+    let dev_type = DeviceType::from_name(device_name)?.0;
+    let schema = dev_type.schema();
+    let param = schema.param(param_name)?;
+    let mut explain = ExplainInfo {
+        vendor: schema.vendor.clone(),
+        device: schema.name.clone(),
+        device_sysex: hex::encode(&schema.sysex),
+        param: param.to_string(),
+        param_sysex: hex::encode(param.schema.sysex.get(Form::Update)),
+        index: param.index.map(|idx| format!("{:02x}", idx)),
+        mode: None,
+        fields: vec![],
+        value: None,
+        bounds: None,
+        sysex: vec![],
+    };
+    let messages = if value_ids.is_empty() {
+        dev_type.descriptor().encode_query(param_name)?
+    } else if param.schema.modes.is_some() {
+        let (mode_name, mode, fields) = devices::modal_values(&param, value_ids)?;
+        explain.mode = Some((mode_name.to_string(), hex::encode(&mode.sysex)));
+        for (field_name, value) in fields {
+            if let Some(field) = mode.fields.get(field_name) {
+                explain.fields.push((
+                    format!("{}={}", field_name, value),
+                    hex::encode(field.sysex.get(Form::Update)),
+                ));
+            }
+        }
+        dev_type.descriptor().encode(param_name, value_ids)?
+    } else {
+        let (bounds, bcode) = devices::bound_match(&param, value_ids)?;
+        explain.value = Some((value_ids.join(" "), hex::encode(bcode)));
+        explain.bounds = Some(bounds.to_string());
+        dev_type.descriptor().encode(param_name, value_ids)?
+    };
+    explain.sysex = messages.iter().map(hex::encode).collect();
+    Ok(Report::Explain(explain))
+}
+
+/// The MIDI backend, its limitations and the number of ports it finds
+fn backend_info() -> Report {
+    Report::Backend(BackendInfo {
+        backend: devices::backend_name().to_string(),
+        limitations: devices::backend_limitations()
+            .iter()
+            .map(|limitation| limitation.to_string())
+            .collect(),
+        output_ports: MidiOutput::new(CLIENT_NAME)
+            .map(|midi_out| devices::output_ports(&midi_out).len())
+            .map_err(|err| err.to_string()),
+        input_ports: MidiInput::new(CLIENT_NAME)
+            .map(|midi_in| devices::input_ports(&midi_in).len())
+            .map_err(|err| err.to_string()),
+    })
+}
+
+fn lint(file: Option<&Path>) -> devices::Result<()> {
+    let files = match file {
+        Some(file) => vec![(file.display().to_string(), fs::read_to_string(file)?)],
+        None => schema::bundled()
+            .iter()
+            .map(|(name, body)| (name.to_string(), body.to_string()))
+            .collect(),
+    };
+    let mut count = 0;
+    for (name, body) in files {
+        for issue in schema::lint(&body) {
+            println!("{}: {}", name, issue);
+            count += 1;
+        }
+    }
+    if count > 0 {
+        return Err(Box::new(DeviceError::SchemaInvalid { count }));
+    }
+    Ok(())
+}
+
+fn connect(ctx: &Context, device_name: &str) -> devices::Result<Box<dyn Device>> {
+    let (dev_type, unit) = DeviceType::from_name(device_name)?;
+    let backend = Arc::new(MidirBackend);
+    let port = match ctx
+        .out_port
+        .as_deref()
+        .or_else(|| ctx.config.port(device_name))
+    {
+        Some(port_name) => devices::find_port(backend.output_ports(), port_name),
+        None => dev_type.ports().get(unit - 1).cloned(),
+    };
+    let overrides = Overrides {
+        in_port: ctx.in_port.clone(),
+        timeout: ctx.timeout,
+        send_delay: ctx.throttle,
+    };
+    if let Some(port) = port {
+        dev_type.descriptor().connect(backend, &port, &overrides)
+    } else if let Some(port_name) = &ctx.out_port {
+        Err(Box::new(DeviceError::NoOutputPort {
+            port_name: port_name.clone(),
+        }))
+    } else {
+        #[cfg(feature = "usb")]
+        {
+            if let Some(dev) = devices::usb::connect(&dev_type, unit, &overrides)? {
+                return Ok(dev);
+            }
+        }
+        Err(Box::new(DeviceError::NoConnectedDevice {
+            device_name: device_name.to_string(),
+        }))
+    }
+}
+
+/// Split `set` arguments into parameters and their values. A parameter name following a value
+/// starts the next parameter.
+fn assignments<'a>(
+    ctx: &'a Context,
+    schema: &schema::Device,
+    args: &'a [String],
+) -> Vec<(&'a str, &'a [String])> {
+    let mut starts = vec![0];
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        let last = *starts.last().unwrap_or(&0);
+        if i > last + 1 && schema.param(ctx.config.param_name(arg)).is_ok() {
+            starts.push(i);
+        }
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &start)| {
+            let end = starts.get(n + 1).copied().unwrap_or(args.len());
+            (ctx.config.param_name(&args[start]), &args[start + 1..end])
+        })
+        .collect()
+}
+
+fn set(
+    ctx: &Context,
+    device_name: &str,
+    args: &[String],
+    dry_run: bool,
+) -> devices::Result<Report> {
+    let schema = DeviceType::from_name(device_name)?.0.schema();
+    set_params(
+        ctx,
+        device_name,
+        None,
+        &assignments(ctx, schema, args),
+        dry_run,
+    )
+}
+
+/// Set some parameters of a device, each to some values or by a relative change, connecting
+/// to the device unless it already is
+fn set_params(
+    ctx: &Context,
+    device_name: &str,
+    connected: Option<&mut dyn Device>,
+    params: &[(&str, &[String])],
+    dry_run: bool,
+) -> devices::Result<Report> {
+    let (dev_type, unit) = DeviceType::from_name(device_name)?;
+    let schema = dev_type.schema();
+    let mut changes = vec![];
+    for &(param_name, value_ids) in params {
+        let param = schema.param(param_name)?;
+        let relative = match value_ids {
+            [value_id] if devices::is_relative(&param, value_id) => Some(value_id),
+            _ => None,
+        };
+        changes.push((param_name, param, value_ids, relative));
+    }
+    if dry_run {
+        let dev = dev_type.descriptor();
+        let mut messages = LinkedHashMap::new();
+        for (param_name, param, value_ids, relative) in &changes {
+            if let Some(value_id) = relative {
+                return Err(Box::new(DeviceError::CurrentValueRequired {
+                    value_name: value_id.to_string(),
+                }));
+            }
+            messages.insert(
+                param.to_string(),
+                sysex_hex(&dev.encode(param_name, value_ids)?),
+            );
+        }
+        return Ok(Report::Sysex(messages));
+    }
+    for (_, param, _, _) in &changes {
+        if param.schema.destructive {
+            confirm(ctx, &format!("Overwrite {} of {}?", param, device_name))?;
+        }
+    }
+    let mut connection;
+    let sysex = match connected {
+        Some(sysex) => sysex,
+        None => {
+            connection = connect(ctx, device_name)?;
+            connection.as_mut()
+        }
+    };
+    let mut names = vec![];
+    for (_, param, _, _) in &changes {
+        names.push(param.to_string());
+        names.extend(param.schema.constraints.iter().map(|c| c.param.clone()));
+    }
+    let mut current = match sysex.query_retrying(&names, ctx.retries) {
+        Ok(values) => values,
+        Err(err) if changes.iter().all(|(_, _, _, relative)| relative.is_none()) => {
+            eprintln!(
+                "warning: could not read the current values of {}, these changes can not be undone: {}",
+                device_name, err
+            );
+            LinkedHashMap::new()
+        }
+        Err(err) => return Err(err),
+    };
+    // check every change before sending any, so that a bad value does not leave the
+    // device half updated
+    let dev = dev_type.descriptor();
+    let mut planned = vec![];
+    for (param_name, param, value_ids, relative) in changes {
+        let value_ids = match relative {
+            Some(change) => {
+                let current = current
+                    .get(&param.to_string())
+                    .ok_or(DeviceError::NoValueReceived)?;
+                devices::relative_value(&param, current, change)?
             }
-            for pair in sysex.query(param_names.as_slice())? {
-                println!("{} {}", pair.0, pair.1.join(" "))
+            None => value_ids.to_vec(),
+        };
+        devices::check_constraints(&param, &value_ids, &current)?;
+        dev.encode(param_name, &value_ids)?;
+        // later changes are checked against the values set before them
+        let previous = current.insert(param.to_string(), value_ids.clone());
+        planned.push((param_name, param, value_ids, previous));
+    }
+    let mut journal = journal::load()?;
+    let mut set = vec![];
+    let send = || -> devices::Result<()> {
+        for (param_name, param, value_ids, previous) in planned {
+            sysex.update(param_name, &value_ids)?;
+            if let Some(values) = &previous {
+                journal.push(journal::Entry {
+                    device: device_name.to_string(),
+                    param: param.to_string(),
+                    values: values.clone(),
+                });
             }
+            let mut values = LinkedHashMap::new();
+            values.insert(param.to_string(), value_ids.clone());
+            cache::update(&dev_type, unit, sysex.identity(), &values)?;
+            set.push(SetInfo {
+                device: device_name.to_string(),
+                param: param.to_string(),
+                old: previous,
+                new: value_ids,
+            });
         }
+        Ok(())
+    };
+    // changes sent before a failure can still be undone
+    let sent = send();
+    journal.save()?;
+    sent.map(|()| Report::Set(set))
+}
+
+/// The bounds of a sequence parameter
+fn seq_bounds<'a>(param: &'a schema::Param) -> devices::Result<&'a NoteSeq> {
+    match param.bounds().first() {
+        Some(Bounds::NoteSeq(seq)) => Ok(seq),
+        _ => Err(Box::new(DeviceError::NotASequence {
+            param_name: param.to_string(),
+        })),
     }
+}
+
+fn read_value(
+    ctx: &Context,
+    sysex: &mut dyn Device,
+    param: &schema::Param,
+) -> devices::Result<Vec<String>> {
+    sysex
+        .query_retrying(&[param.to_string()], ctx.retries)?
+        .remove(&param.to_string())
+        .ok_or_else(|| Box::new(DeviceError::NoValueReceived) as Box<dyn std::error::Error>)
+}
+
+/// Copy a sequence to another device, or within one device
+fn seq_copy(
+    ctx: &Context,
+    from_device: &str,
+    from_param: &str,
+    to_device: &str,
+    to_param: &str,
+) -> devices::Result<Report> {
+    let from_schema = DeviceType::from_name(from_device)?.0.schema();
+    seq_bounds(&from_schema.param(ctx.config.param_name(from_param))?)?;
+    let to_schema = DeviceType::from_name(to_device)?.0.schema();
+    seq_bounds(&to_schema.param(ctx.config.param_name(to_param))?)?;
+    copy(ctx, from_device, from_param, to_device, to_param)
+}
+
+fn seq_import(
+    ctx: &Context,
+    device_name: &str,
+    param_name: &str,
+    file: &Path,
+    steps_per_beat: u16,
+    dry_run: bool,
+) -> devices::Result<Report> {
+    let (division, notes) = smf::notes(&fs::read(file)?)?;
+    let steps = seq::quantize(&notes, division, steps_per_beat)?;
+    let param_name = ctx.config.param_name(param_name);
+    set_params(ctx, device_name, None, &[(param_name, &steps)], dry_run)
+}
+
+/// Read a sequence, edit its steps, given its maximum length, and write it back
+fn edit_seq<F>(
+    ctx: &Context,
+    device_name: &str,
+    param_name: &str,
+    edit: F,
+) -> devices::Result<Report>
+where
+    F: FnOnce(&mut Vec<String>, usize) -> devices::Result<()>,
+{
+    let param_name = ctx.config.param_name(param_name);
+    let param = DeviceType::from_name(device_name)?
+        .0
+        .schema()
+        .param(param_name)?;
+    let max_len = seq_bounds(&param)?.max_len as usize;
+    let mut sysex = connect(ctx, device_name)?;
+    let mut steps = read_value(ctx, sysex.as_mut(), &param)?;
+    edit(&mut steps, max_len)?;
+    set_params(
+        ctx,
+        device_name,
+        Some(sysex.as_mut()),
+        &[(param_name, &steps)],
+        false,
+    )
+}
+
+fn identify(ctx: &Context, device_name: &str) -> devices::Result<Report> {
+    let sysex = connect(ctx, device_name)?;
+    let identity = sysex.identity().ok_or(DeviceError::NoIdentificationReply)?;
+    let mut values = LinkedHashMap::new();
+    values.insert(
+        "manufacturer".to_string(),
+        vec![hex::encode(&identity.manufacturer)],
+    );
+    values.insert(
+        "family".to_string(),
+        vec![format!("{:04x}", identity.family)],
+    );
+    values.insert("model".to_string(), vec![format!("{:04x}", identity.model)]);
+    values.insert("firmware".to_string(), vec![identity.firmware_version()]);
+    Ok(Report::Values(values))
+}
+
+/// Revert the most recent change made by `set`
+fn undo(ctx: &Context) -> devices::Result<Report> {
+    let mut journal = journal::load()?;
+    let entry = journal.pop().ok_or(DeviceError::NothingToUndo)?;
+    let mut sysex = connect(ctx, &entry.device)?;
+    sysex.update(&entry.param, &entry.values)?;
+    journal.save()?;
+    Ok(Report::Set(vec![SetInfo {
+        device: entry.device,
+        param: entry.param,
+        old: None,
+        new: entry.values,
+    }]))
+}
+
+/// Messages as hex
+fn sysex_hex(messages: &[Vec<u8>]) -> Vec<String> {
+    messages.iter().map(hex::encode).collect()
+}
+
+/// Values with the piano rolls of sequences
+fn rolls(device_name: &str, values: LinkedHashMap<String, Vec<String>>) -> devices::Result<Report> {
+    let schema = DeviceType::from_name(device_name)?.0.schema();
+    let mut rolls = vec![];
+    for (param, value) in values {
+        let roll = match schema.param(&param)?.bounds().first() {
+            Some(Bounds::NoteSeq(_)) => Some(seq::piano_roll(&value)),
+            _ => None,
+        };
+        rolls.push(RollInfo { param, value, roll });
+    }
+    Ok(Report::Rolls(rolls))
+}
+
+fn get(
+    ctx: &Context,
+    device_name: &str,
+    param_names: &[String],
+    cached: bool,
+) -> devices::Result<Report> {
+    let (dev_type, unit) = DeviceType::from_name(device_name)?;
+    let param_names: Vec<String> = param_names
+        .iter()
+        .map(|name| ctx.config.param_name(name).to_string())
+        .collect();
+    let param_names = dev_type.schema().expand(&param_names)?;
+    if cached {
+        let cache = cache::load(&dev_type, unit, None)?;
+        let mut values = LinkedHashMap::new();
+        for param in param_names {
+            match cache.get(&param) {
+                Some(value) => {
+                    values.insert(param, value.clone());
+                }
+                None => eprintln!("warning: no cached value for {}", param),
+            }
+        }
+        return Ok(Report::Values(values));
+    }
+    let mut sysex = connect(ctx, device_name)?;
+    let values = sysex.query_retrying(param_names.as_slice(), ctx.retries)?;
+    cache::update(&dev_type, unit, sysex.identity(), &values)?;
+    Ok(Report::Values(values))
+}
+
+/// Query every member of a group at once, printing their values in group order
+#[cfg(feature = "async")]
+fn get_group(
+    ctx: &Context,
+    group: &str,
+    param_names: &[String],
+    roll: bool,
+) -> devices::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let _entered = runtime.enter();
+    let param_names: Vec<String> = param_names
+        .iter()
+        .map(|name| ctx.config.param_name(name).to_string())
+        .collect();
+    let mut members = vec![];
+    for device_name in device_names(ctx, group)? {
+        let connected = DeviceType::from_name(&device_name).and_then(|(dev_type, unit)| {
+            let params = dev_type.schema().expand(&param_names)?;
+            let device = connect(ctx, &device_name)?;
+            let identity = device.identity().cloned();
+            let task = DeviceTask::spawn(&device_name, device);
+            Ok((dev_type, unit, identity, params, task))
+        });
+        members.push((device_name, connected));
+    }
+    let queries: Vec<_> = members
+        .iter()
+        .map(|(_, connected)| {
+            connected
+                .as_ref()
+                .ok()
+                .map(|(_, _, _, params, task)| task.query(params.clone(), ctx.retries))
+        })
+        .collect();
+    let mut result = Ok(());
+    for ((device_name, connected), query) in members.iter().zip(queries) {
+        ctx.format.section(device_name);
+        let values = match (connected, query) {
+            (Ok((dev_type, unit, identity, _, _)), Some(query)) => {
+                runtime.block_on(query).and_then(|values| {
+                    cache::update(dev_type, *unit, identity.as_ref(), &values).map(|_| values)
+                })
+            }
+            (Err(err), _) => Err(err.to_string().into()),
+            _ => continue,
+        };
+        match values {
+            Ok(values) if roll => ctx.format.print(&rolls(device_name, values)?)?,
+            Ok(values) => ctx.format.print(&Report::Values(values))?,
+            Err(err) => {
+                eprintln!("{}: {}", device_name, err);
+                result = Err(err);
+            }
+        }
+    }
+    result
+}
 
+/// Copy the value of a parameter to another, of the same device or of another one connected
+/// at the same time. A sequence is fitted to the bounds of the target.
+fn copy(
+    ctx: &Context,
+    from_device: &str,
+    from_param: &str,
+    to_device: &str,
+    to_param: &str,
+) -> devices::Result<Report> {
+    let from_schema = DeviceType::from_name(from_device)?.0.schema();
+    let from = from_schema.param(ctx.config.param_name(from_param))?;
+    let to_param = ctx.config.param_name(to_param);
+    let to_schema = DeviceType::from_name(to_device)?.0.schema();
+    let to = to_schema.param(to_param)?;
+    let mut source = connect(ctx, from_device)?;
+    let mut target = if to_device == from_device {
+        None
+    } else {
+        Some(connect(ctx, to_device)?)
+    };
+    let mut value_ids = read_value(ctx, source.as_mut(), &from)?;
+    if let Some(Bounds::NoteSeq(to_seq)) = to.bounds().first() {
+        let dropped = seq::fit(&mut value_ids, to_seq);
+        if dropped > 0 {
+            eprintln!(
+                "warning: {} of {} holds at most {} steps, the last {} are dropped",
+                to, to_device, to_seq.max_len, dropped
+            );
+        }
+    }
+    let target = match target.as_mut() {
+        Some(target) => target.as_mut(),
+        None => source.as_mut(),
+    };
+    set_params(
+        ctx,
+        to_device,
+        Some(target),
+        &[(to_param, &value_ids)],
+        false,
+    )
+}
+
+fn backup(ctx: &Context, device_name: &str, file: Option<&Path>) -> devices::Result<()> {
+    let (dev_type, unit) = DeviceType::from_name(device_name)?;
+    let mut sysex = connect(ctx, device_name)?;
+    let values = sysex.query_retrying(&dev_type.schema().globals(), ctx.retries)?;
+    cache::update(&dev_type, unit, sysex.identity(), &values)?;
+    Snapshot::new(dev_type.to_string(), values).save(file)
+}
+
+fn load_snapshot(dev_type: &DeviceType, input: &Path) -> devices::Result<Snapshot> {
+    let snapshot = Snapshot::load(input)?;
+    if snapshot.device != dev_type.to_string() {
+        return Err(Box::new(DeviceError::WrongDevice {
+            expected: dev_type.to_string(),
+            found: snapshot.device,
+        }));
+    }
+    Ok(snapshot)
+}
+
+fn restore(ctx: &Context, device_name: &str, input: &Path, dry_run: bool) -> devices::Result<()> {
+    let dev_type = DeviceType::from_name(device_name)?.0;
+    let snapshot = load_snapshot(&dev_type, input)?;
+    if !dry_run {
+        confirm(
+            ctx,
+            &format!(
+                "Overwrite {} parameters of {} with {}?",
+                snapshot.values.len(),
+                device_name,
+                input.display()
+            ),
+        )?;
+    }
+    apply(ctx, device_name, &snapshot.values, dry_run)
+}
+
+fn reset(ctx: &Context, device_name: &str, dry_run: bool) -> devices::Result<()> {
+    let dev_type = DeviceType::from_name(device_name)?.0;
+    let defaults = dev_type.schema().defaults();
+    if !dry_run {
+        confirm(
+            ctx,
+            &format!(
+                "Reset {} parameters of {} to factory values?",
+                defaults.len(),
+                device_name
+            ),
+        )?;
+    }
+    apply(ctx, device_name, &defaults, dry_run)
+}
+
+/// Ask a yes / no question on the terminal before a destructive operation, failing unless
+/// answered yes. Always passes with `--yes`.
+fn confirm(ctx: &Context, question: &str) -> devices::Result<()> {
+    if ctx.yes {
+        return Ok(());
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err(Box::new(DeviceError::Cancelled))
+    }
+}
+
+/// Set many parameters, reporting each one's outcome
+fn apply(
+    ctx: &Context,
+    device_name: &str,
+    values: &LinkedHashMap<String, Vec<String>>,
+    dry_run: bool,
+) -> devices::Result<()> {
+    let dev_type = DeviceType::from_name(device_name)?.0;
+
+    // validate everything before touching the device
+    let encoded = devices::encode_values(&dev_type, values)?;
+
+    if dry_run {
+        let messages = encoded
+            .iter()
+            .map(|(param_name, messages)| (param_name.clone(), sysex_hex(messages)))
+            .collect();
+        return ctx.format.print(&Report::Sysex(messages));
+    }
+
+    let mut sysex = connect(ctx, device_name)?;
+    let mut restored = vec![];
+    for (param_name, value_ids) in values {
+        restored.push(RestoredInfo {
+            param: param_name.clone(),
+            error: sysex
+                .update(param_name, value_ids)
+                .err()
+                .map(|err| err.to_string()),
+        });
+    }
+    let failed = restored
+        .iter()
+        .filter(|param| param.error.is_some())
+        .count();
+    ctx.format.print(&Report::Restored(restored))?;
+    if failed > 0 {
+        return Err(Box::new(DeviceError::RestoreFailed { failed }));
+    }
+    Ok(())
+}
+
+fn diff(ctx: &Context, device_name: &str, input: &Path) -> devices::Result<Report> {
+    let dev_type = DeviceType::from_name(device_name)?.0;
+    let snapshot = load_snapshot(&dev_type, input)?;
+    let schema = dev_type.schema();
+    let mut sysex = connect(ctx, device_name)?;
+    let param_names: Vec<String> = snapshot.values.keys().cloned().collect();
+    let current = sysex.query_retrying(&param_names, ctx.retries)?;
+    let mut diff = DiffInfo {
+        changes: vec![],
+        changed: 0,
+        identical: 0,
+        unreadable: 0,
+    };
+    for (param_name, old) in snapshot.values {
+        let new = current.get(&param_name);
+        match new {
+            Some(new) if *new == old => {
+                diff.identical += 1;
+                continue;
+            }
+            Some(_) => diff.changed += 1,
+            None => diff.unreadable += 1,
+        }
+        let category = schema
+            .param(&param_name)
+            .map_or_else(|_| param_name.clone(), |param| param.category().to_string());
+        diff.changes.push(ChangeInfo {
+            param: param_name,
+            category,
+            old,
+            new: new.cloned(),
+        });
+    }
+    Ok(Report::Diff(diff))
+}
+
+fn parse_duration(s: &str) -> devices::Result<Duration> {
+    if let Some(millis) = s.strip_suffix("ms") {
+        Ok(Duration::from_millis(u64::from_str(millis)?))
+    } else if let Some(secs) = s.strip_suffix('s') {
+        Ok(Duration::from_secs(u64::from_str(secs)?))
+    } else {
+        Ok(Duration::from_secs(u64::from_str(s)?))
+    }
+}
+
+/// Number of queries sent at each rate
+const STRESS_BATCH: usize = 32;
+
+fn stress(ctx: &Context, device_name: &str, duration: Duration) -> devices::Result<()> {
+    let mut sysex = connect(ctx, device_name)?;
+    let start = Instant::now();
+    let mut interval = Duration::from_millis(50);
+    let mut last_good: Option<Duration> = None;
+    while start.elapsed() < duration {
+        let received = sysex.ping(STRESS_BATCH, interval)?;
+        println!(
+            "{:>6} msg/s: {}/{} replies",
+            1_000_000 / interval.as_micros().max(1),
+            received,
+            STRESS_BATCH
+        );
+        if received < STRESS_BATCH {
+            match last_good {
+                Some(good) => println!(
+                    "suggested schema setting: send_delay_ms: {}",
+                    good.as_micros().div_ceil(1000)
+                ),
+                None => println!("replies dropped at the slowest rate, check the connection"),
+            }
+            return Ok(());
+        }
+        last_good = Some(interval);
+        interval = interval * 3 / 4;
+    }
+    println!("no replies dropped, device needs no throttling");
     Ok(())
 }
@@ -0,0 +1,539 @@
+use serde::Serialize;
+
+use crate::devices::{ErrorKind, Result};
+use crate::schema::Bounds;
+use linked_hash_map::LinkedHashMap;
+use std::error::Error;
+use std::io::IsTerminal;
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// Result of a command, printed as text or JSON
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Report {
+    /// Plain names, such as devices
+    List(Vec<String>),
+
+    /// MIDI ports, with the device unit connected to each
+    Ports(Vec<PortInfo>),
+
+    /// Identity replies of the devices on each port
+    Scan(Vec<ScanInfo>),
+
+    /// Parameters of a device
+    Params(Vec<ParamInfo>),
+
+    /// Bounds of a parameter, with example set commands
+    Bounds {
+        bounds: Vec<Bounds>,
+        /// Fields of each mode of a modal parameter, and their bounds
+        #[serde(skip_serializing_if = "LinkedHashMap::is_empty")]
+        modes: LinkedHashMap<String, LinkedHashMap<String, Vec<Bounds>>>,
+        examples: Vec<String>,
+    },
+
+    /// Parameter values
+    Values(LinkedHashMap<String, Vec<String>>),
+
+    /// Differences between a device's values and a backup file
+    Diff(DiffInfo),
+
+    /// Parameter values, sequences with their piano roll
+    Rolls(Vec<RollInfo>),
+
+    /// Sysex messages that would be sent for each parameter, in hex
+    Sysex(LinkedHashMap<String, Vec<String>>),
+
+    /// Parameters set on a device
+    Set(Vec<SetInfo>),
+
+    /// Parameters written by a restore, and the error of those that failed
+    Restored(Vec<RestoredInfo>),
+
+    /// Values carried by sysex messages, for each device that could have sent them
+    Decoded(Vec<DecodedInfo>),
+
+    /// How a parameter and its values are encoded
+    Explain(ExplainInfo),
+
+    /// MIDI backend and the ports it finds
+    Backend(BackendInfo),
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortInfo {
+    pub name: String,
+    /// Device name and unit number, e.g. `MicroBrute/2`
+    pub device: Option<String>,
+    /// Whether the port has an output side, and if it could be opened
+    pub output: Option<bool>,
+    /// Whether the port has an input side, and if it could be opened
+    pub input: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScanInfo {
+    pub port: String,
+    /// Identity reply of the device on the port, if it replied
+    pub identity: Option<IdentityInfo>,
+    /// Known device matching the identity
+    pub device: Option<String>,
+}
+
+/// Contents of an identity reply, codes in hex
+#[derive(Debug, Serialize)]
+pub struct IdentityInfo {
+    pub manufacturer: String,
+    /// Vendor of the known devices with this manufacturer id
+    pub vendor: Option<String>,
+    pub family: String,
+    pub model: String,
+    pub firmware: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParamInfo {
+    pub name: String,
+    /// Lowest and highest index of an indexed parameter
+    pub index: Option<(i16, i16)>,
+    /// Mode names and their field names
+    pub modes: Vec<(String, Vec<String>)>,
+    /// Sysex codes and bounds, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ParamDetail>,
+}
+
+/// Reference description of a parameter, as found in its schema
+#[derive(Debug, Serialize)]
+pub struct ParamDetail {
+    /// Sysex codes of updates and queries, in hex
+    pub update: String,
+    pub query: String,
+    pub bounds: Vec<Bounds>,
+    pub modes: LinkedHashMap<String, ModeDetail>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModeDetail {
+    pub sysex: String,
+    pub fields: LinkedHashMap<String, FieldDetail>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldDetail {
+    pub sysex: String,
+    pub bounds: Vec<Bounds>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RollInfo {
+    pub param: String,
+    pub value: Vec<String>,
+    /// Lines of the grid of a sequence, highest note first
+    pub roll: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffInfo {
+    /// Parameters that differ or could not be read, in the backup file's order
+    pub changes: Vec<ChangeInfo>,
+    pub changed: usize,
+    pub identical: usize,
+    pub unreadable: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangeInfo {
+    pub param: String,
+    pub category: String,
+    /// Value in the backup file
+    pub old: Vec<String>,
+    /// Value on the device, none if it could not be read
+    pub new: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetInfo {
+    pub device: String,
+    pub param: String,
+    /// Value before the change, if it could be read
+    pub old: Option<Vec<String>>,
+    pub new: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoredInfo {
+    pub param: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodedInfo {
+    /// The message, in hex
+    pub message: String,
+    pub devices: Vec<DecodedValues>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodedValues {
+    pub device: String,
+    pub values: LinkedHashMap<String, Vec<String>>,
+    /// Why the message could not be decoded for the device
+    pub error: Option<String>,
+}
+
+/// Sysex codes in hex, of each part of a parameter's messages
+#[derive(Debug, Serialize)]
+pub struct ExplainInfo {
+    pub vendor: String,
+    pub device: String,
+    pub device_sysex: String,
+    pub param: String,
+    pub param_sysex: String,
+    pub index: Option<String>,
+    /// Mode and field assignments of a modal parameter, with their sysex
+    pub mode: Option<(String, String)>,
+    pub fields: Vec<(String, String)>,
+    /// Values and their code, with the bounds they match, none for a query
+    pub value: Option<(String, String)>,
+    pub bounds: Option<String>,
+    pub sysex: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackendInfo {
+    pub backend: String,
+    pub limitations: Vec<String>,
+    /// Number of ports found, or why they could not be listed
+    pub output_ports: std::result::Result<usize, String>,
+    pub input_ports: std::result::Result<usize, String>,
+}
+
+impl Format {
+    pub fn print(self, report: &Report) -> Result<()> {
+        match self {
+            Format::Json => println!("{}", serde_json::to_string(report)?),
+            Format::Text => print_text(report),
+        }
+        Ok(())
+    }
+
+    /// Print an error to stderr
+    pub fn print_error(self, kind: ErrorKind, err: &dyn Error) {
+        match self {
+            Format::Json => eprintln!(
+                "{}",
+                serde_json::json!({
+                    "error": kind,
+                    "code": kind as i32,
+                    "message": err.to_string(),
+                })
+            ),
+            Format::Text => eprintln!("error: {}", err),
+        }
+    }
+
+    /// Print the header of a device's results, when a command applies to a group of devices
+    pub fn section(self, device_name: &str) {
+        if self == Format::Text {
+            println!("[{}]", device_name);
+        }
+    }
+}
+
+fn print_text(report: &Report) {
+    match report {
+        Report::List(items) => {
+            for item in items {
+                println!("{}", item);
+            }
+        }
+        Report::Ports(ports) => {
+            for port in ports {
+                let side = |open: Option<bool>, name| match open {
+                    Some(true) => name,
+                    Some(false) => "--",
+                    None => "  ",
+                };
+                print!(
+                    "{} {} {}",
+                    side(port.input, "in"),
+                    side(port.output, "out"),
+                    port.name
+                );
+                if let Some(device) = &port.device {
+                    print!(" ({})", device);
+                }
+                if port.input == Some(false) || port.output == Some(false) {
+                    print!(" [busy]");
+                }
+                println!();
+            }
+        }
+        Report::Scan(scanned) => {
+            for scan in scanned {
+                match &scan.identity {
+                    Some(id) => {
+                        print!(
+                            "{}: manufacturer {}",
+                            scan.port,
+                            id.vendor.as_ref().unwrap_or(&id.manufacturer)
+                        );
+                        print!(
+                            " family {} model {} firmware {}",
+                            id.family, id.model, id.firmware
+                        );
+                        match &scan.device {
+                            Some(device) => println!(" ({})", device),
+                            None => println!(" (unknown device)"),
+                        }
+                    }
+                    None => println!("{}: no reply", scan.port),
+                }
+            }
+        }
+        Report::Params(params) => {
+            for param in params {
+                let name = match param.index {
+                    Some((lo, hi)) => format!("{}/{}..{}", param.name, lo, hi),
+                    None => param.name.clone(),
+                };
+                if let Some(detail) = &param.detail {
+                    print_detail(&name, detail);
+                    continue;
+                }
+                if param.modes.is_empty() {
+                    println!("{}", name);
+                }
+                for (mode, fields) in &param.modes {
+                    println!("{}:{}", name, mode);
+                    for field in fields {
+                        println!("  {}", field);
+                    }
+                }
+            }
+        }
+        Report::Bounds {
+            bounds,
+            modes,
+            examples,
+        } => {
+            for bounds in bounds {
+                match bounds {
+                    Bounds::Values(values) => {
+                        for (name, code) in values {
+                            if code.aliases().is_empty() {
+                                println!("{}", name)
+                            } else {
+                                println!("{} ({})", name, code.aliases().join(", "))
+                            }
+                        }
+                    }
+                    Bounds::Range(range) => {
+                        println!("[{}..{}]", range.display(range.lo), range.display(range.hi))
+                    }
+                    Bounds::NoteSeq(seq) => {
+                        let mut steps = vec![format!("max {} notes", seq.max_len)];
+                        if seq.rest.is_some() {
+                            steps.push("_ for a rest".to_string());
+                        }
+                        if seq.tie.is_some() {
+                            steps.push("- to hold the previous note".to_string());
+                        }
+                        println!("note1 note2 note3 ... ({})", steps.join(", "))
+                    }
+                    Bounds::Wide(wide) => println!("[{}..{}]", wide.lo, wide.hi),
+                    Bounds::Text(text) => println!("text (max {} characters)", text.max_len),
+                    Bounds::Dump(dump) => println!("hex data (max {} bytes)", dump.max_len),
+                }
+            }
+            for (mode, fields) in modes {
+                println!("{}", mode);
+                for (field, bounds) in fields {
+                    let bounds: Vec<String> = bounds.iter().map(Bounds::to_string).collect();
+                    println!("  {}: {}", field, bounds.join(" | "));
+                }
+            }
+            for example in examples {
+                println!("example: {}", example);
+            }
+        }
+        Report::Values(values) => {
+            for (param, value) in values {
+                println!("{} {}", param, value.join(" "))
+            }
+        }
+        Report::Diff(diff) => print_diff(diff),
+        Report::Sysex(messages) => {
+            for (param, messages) in messages {
+                println!("{}", param);
+                for message in messages {
+                    println!("{}", message);
+                }
+            }
+        }
+        Report::Set(changes) => {
+            for change in changes {
+                match &change.old {
+                    Some(old) => println!(
+                        "{} {} {} → {}",
+                        change.device,
+                        change.param,
+                        old.join(" "),
+                        change.new.join(" ")
+                    ),
+                    None => println!(
+                        "{} {} {}",
+                        change.device,
+                        change.param,
+                        change.new.join(" ")
+                    ),
+                }
+            }
+        }
+        Report::Restored(restored) => {
+            for param in restored {
+                match &param.error {
+                    None => println!("{} ok", param.param),
+                    Some(err) => println!("{} failed: {}", param.param, err),
+                }
+            }
+        }
+        Report::Decoded(decoded) => {
+            for message in decoded {
+                println!("{}", message.message);
+                if message.devices.is_empty() {
+                    println!("  unknown device");
+                }
+                for device in &message.devices {
+                    if let Some(err) = &device.error {
+                        println!("  {}: {}", device.device, err);
+                    }
+                    for (param, value) in &device.values {
+                        println!("  {} {} {}", device.device, param, value.join(" "));
+                    }
+                }
+            }
+        }
+        Report::Explain(explain) => print_explain(explain),
+        Report::Backend(backend) => {
+            println!("backend: {} (midir 0.5)", backend.backend);
+            for limitation in &backend.limitations {
+                println!("limitation: {}", limitation);
+            }
+            let count = |ports: &std::result::Result<usize, String>| match ports {
+                Ok(count) => count.to_string(),
+                Err(err) => format!("unavailable ({})", err),
+            };
+            println!("output ports: {}", count(&backend.output_ports));
+            println!("input ports: {}", count(&backend.input_ports));
+        }
+        Report::Rolls(rolls) => {
+            for roll in rolls {
+                match &roll.roll {
+                    Some(lines) => {
+                        println!("{}", roll.param);
+                        for line in lines {
+                            println!("{}", line);
+                        }
+                    }
+                    None => println!("{} {}", roll.param, roll.value.join(" ")),
+                }
+            }
+        }
+    }
+}
+
+const RED: u8 = 31;
+const GREEN: u8 = 32;
+
+/// Text in an ANSI color, if printed to a terminal and `NO_COLOR` is not set
+fn paint(text: &str, color: u8) -> String {
+    if std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none() {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Changes as removed (backup) and added (device) lines, under the header of each category
+fn print_diff(diff: &DiffInfo) {
+    let mut categories: LinkedHashMap<&str, Vec<&ChangeInfo>> = LinkedHashMap::new();
+    for change in &diff.changes {
+        categories
+            .entry(change.category.as_str())
+            .or_default()
+            .push(change);
+    }
+    for (category, changes) in categories {
+        println!("[{}]", category);
+        for change in changes {
+            let old = format!("- {} {}", change.param, change.old.join(" "));
+            println!("{}", paint(&old, RED));
+            match &change.new {
+                Some(new) => {
+                    let new = format!("+ {} {}", change.param, new.join(" "));
+                    println!("{}", paint(&new, GREEN));
+                }
+                None => println!("? {} unreadable", change.param),
+            }
+        }
+    }
+    println!(
+        "{} changed, {} identical, {} unreadable",
+        diff.changed, diff.identical, diff.unreadable
+    );
+}
+
+/// Encoding of a parameter, a part per line
+fn print_explain(explain: &ExplainInfo) {
+    println!("vendor  {}", explain.vendor);
+    println!("device  {} {}", explain.device, explain.device_sysex);
+    match &explain.index {
+        Some(idx) => println!(
+            "param   {} {} index {}",
+            explain.param, explain.param_sysex, idx
+        ),
+        None => println!("param   {} {}", explain.param, explain.param_sysex),
+    }
+    if explain.value.is_none() && explain.mode.is_none() {
+        println!("query");
+    }
+    if let Some((mode, sysex)) = &explain.mode {
+        println!("mode    {} {}", mode, sysex);
+    }
+    for (field, sysex) in &explain.fields {
+        println!("field   {} {}", field, sysex);
+    }
+    if let Some((value, code)) = &explain.value {
+        println!("value   {} {}", value, code);
+    }
+    if let Some(bounds) = &explain.bounds {
+        println!("bounds  {}", bounds);
+    }
+    for msg in &explain.sysex {
+        println!("sysex   {}", msg);
+    }
+}
+
+/// Parameter reference as a tree: sysex codes, then bounds or modes and their fields
+fn print_detail(name: &str, detail: &ParamDetail) {
+    println!("{}", name);
+    println!("  sysex update {} query {}", detail.update, detail.query);
+    for bounds in &detail.bounds {
+        println!("  {}", bounds);
+    }
+    for (mode_name, mode) in &detail.modes {
+        println!("  mode {} {}", mode_name, mode.sysex);
+        for (field_name, field) in &mode.fields {
+            let bounds: Vec<String> = field.bounds.iter().map(Bounds::to_string).collect();
+            println!("    {} {}: {}", field_name, field.sysex, bounds.join(" | "));
+        }
+    }
+}
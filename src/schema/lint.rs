@@ -0,0 +1,364 @@
+//! Consistency checks of device schema files, beyond what deserialization catches.
+
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+
+use crate::devices;
+use crate::schema::{parse, Bounds, Device, Firmwares, Form, Parameter, Protocol, Range, Wide};
+use std::fmt;
+
+/// Problems found in a schema file, empty if it is valid
+pub fn lint(body: &str) -> Vec<String> {
+    let device = match parse(body) {
+        Ok(device) => device,
+        Err(err) => return vec![err.to_string()],
+    };
+    let mut issues = duplicate_names(body);
+    check_device(&device, &mut issues);
+    issues
+}
+
+fn check_device(device: &Device, issues: &mut Vec<String>) {
+    match device.manufacturer[..] {
+        [id] if id != 0 && id <= 0x7f => {}
+        [0, a, b] if a <= 0x7f && b <= 0x7f => {}
+        _ => issues.push(format!(
+            "manufacturer {} is not one byte, or three starting with 0",
+            hex::encode(&device.manufacturer)
+        )),
+    }
+    if let Some(fragments) = device.fragments {
+        if fragments.data_pos <= fragments.marker_pos {
+            issues.push("fragments: data_pos is not after marker_pos".to_string());
+        }
+        if fragments.max_data == Some(0) {
+            issues.push("fragments: max_data is 0".to_string());
+        }
+    }
+    let params: Vec<_> = device.parameters.iter().collect();
+    for (i, (name, param)) in params.iter().enumerate() {
+        let reply = param.sysex.get(Form::Reply);
+        if device.protocol == Protocol::Nrpn {
+            check_nrpn(name, param, &params[..i], issues);
+        } else if let Some((other, _)) = params[..i].iter().find(|(_, p)| {
+            let other = p.sysex.get(Form::Reply);
+            (other.starts_with(reply) || reply.starts_with(other))
+                && !disjoint_indexes(&param.index, &p.index)
+        }) {
+            issues.push(format!(
+                "{}: sysex {} overlaps {}, it can not be told apart in replies",
+                name,
+                hex::encode(reply),
+                other
+            ));
+        }
+        check_firmware(name, &param.firmware, issues);
+        if let Some(index) = &param.index {
+            check_range(name, "index", index, issues);
+        }
+        match (&param.bounds, &param.modes) {
+            (None, None) => issues.push(format!("{}: no bounds", name)),
+            (Some(bounds), _) if bounds.is_empty() => issues.push(format!("{}: no bounds", name)),
+            _ => {}
+        }
+        for bounds in param.bounds.iter().flatten() {
+            check_bounds(name, bounds, issues);
+        }
+        for (mode_name, mode) in param.modes.iter().flatten() {
+            for (field, field_schema) in &mode.fields {
+                for bounds in &field_schema.bounds {
+                    check_bounds(&format!("{}:{}/{}", name, mode_name, field), bounds, issues);
+                }
+            }
+        }
+    }
+    for (name, param) in &device.parameters {
+        for constraint in &param.constraints {
+            let name = format!("{}: constraint on {}", name, constraint.param);
+            match device.param(&constraint.param) {
+                Ok(other) => {
+                    for value in &constraint.when {
+                        if let Err(err) = devices::bound_codes(&other, std::slice::from_ref(value))
+                        {
+                            issues.push(format!("{}: {}", name, err));
+                        }
+                    }
+                }
+                Err(err) => issues.push(format!("{}: {}", name, err)),
+            }
+            for bounds in &constraint.bounds {
+                check_bounds(&name, bounds, issues);
+            }
+        }
+    }
+    for param in device.params() {
+        if let Some(default) = &param.schema.default {
+            if let Err(err) = devices::bound_codes(&param, default) {
+                issues.push(format!("{}: default {}", param, err));
+            }
+        }
+    }
+}
+
+/// NRPN parameters need a number of their own, and values of one or two data bytes
+fn check_nrpn(
+    name: &str,
+    param: &Parameter,
+    previous: &[(&String, &Parameter)],
+    issues: &mut Vec<String>,
+) {
+    let nrpn = match param.nrpn {
+        Some(nrpn) => nrpn,
+        None => return issues.push(format!("{}: no nrpn number", name)),
+    };
+    // numbers taken by each index code, added to the parameter's number
+    let numbers = |p: &Parameter| {
+        let base = i32::from(p.nrpn.unwrap_or(0));
+        let codes = p.index.as_ref().map_or((0, 0), |index| {
+            (
+                index.code(index.lo).unwrap_or(0),
+                index.code(index.hi).unwrap_or(0),
+            )
+        });
+        base + i32::from(codes.0)..=base + i32::from(codes.1)
+    };
+    let own = numbers(param);
+    if *own.end() > 0x3fff {
+        issues.push(format!("{}: nrpn {} is above 16383", name, own.end()));
+    }
+    if let Some((other, _)) = previous.iter().find(|(_, p)| {
+        p.nrpn.is_some() && {
+            let other = numbers(p);
+            own.start() <= other.end() && other.start() <= own.end()
+        }
+    }) {
+        issues.push(format!("{}: nrpn {} overlaps {}", name, nrpn, other));
+    }
+    if param.modes.is_some() {
+        issues.push(format!("{}: modes are not supported over NRPN", name));
+    }
+    for bounds in param.bounds.iter().flatten() {
+        match bounds {
+            Bounds::Values(_) | Bounds::Range(_) => {}
+            Bounds::Wide(wide) if wide.bytes <= 2 => {}
+            _ => issues.push(format!("{}: {} can not be sent over NRPN", name, bounds)),
+        }
+    }
+}
+
+/// Whether two parameters are indexed by distinct codes, telling their replies apart
+fn disjoint_indexes(a: &Option<Range>, b: &Option<Range>) -> bool {
+    let codes = |range: &Range| -> Vec<u8> {
+        (range.lo..=range.hi)
+            .filter_map(|value| range.code(value))
+            .collect()
+    };
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let b = codes(b);
+            codes(a).iter().all(|code| !b.contains(code))
+        }
+        _ => false,
+    }
+}
+
+fn check_firmware(name: &str, firmware: &Firmwares, issues: &mut Vec<String>) {
+    if let (Some(min), Some(max)) = (firmware.min_firmware, firmware.max_firmware) {
+        if min > max {
+            issues.push(format!(
+                "{}: min firmware {} is above max {}",
+                name, min, max
+            ));
+        }
+    }
+}
+
+fn check_bounds(name: &str, bounds: &Bounds, issues: &mut Vec<String>) {
+    check_firmware(name, &bounds.firmware(), issues);
+    match bounds {
+        Bounds::Values(values) => {
+            let codes: Vec<(&String, u8)> = values.iter().map(|(v, c)| (v, c.code())).collect();
+            for (i, (value, code)) in codes.iter().enumerate() {
+                if let Some((first, _)) = codes[..i].iter().find(|(_, c)| c == code) {
+                    issues.push(format!(
+                        "{}: value {} has the same code as {}, it is never read back",
+                        name, value, first
+                    ));
+                }
+                if *code > 0x7f {
+                    issues.push(format!("{}: value {} code is not 7 bit", name, value));
+                }
+            }
+            let names: Vec<&String> = values
+                .iter()
+                .flat_map(|(value, code)| std::iter::once(value).chain(code.aliases()))
+                .collect();
+            for (i, value) in names.iter().enumerate() {
+                if names[..i].iter().any(|n| n.eq_ignore_ascii_case(value)) {
+                    issues.push(format!("{}: value name {} is ambiguous", name, value));
+                }
+            }
+        }
+        Bounds::Range(range) => check_range(name, "range", range, issues),
+        Bounds::NoteSeq(seq) if seq.max_len == 0 => {
+            issues.push(format!("{}: notes max_len is 0", name))
+        }
+        Bounds::NoteSeq(seq) if seq.block_len == Some(0) => {
+            issues.push(format!("{}: notes block_len is 0", name))
+        }
+        Bounds::NoteSeq(seq) if seq.tie.is_some() && seq.tie == seq.rest => {
+            issues.push(format!("{}: notes rest and tie have the same code", name))
+        }
+        Bounds::NoteSeq(_) => {}
+        Bounds::Wide(wide) => check_wide(name, wide, issues),
+        Bounds::Text(text) if text.max_len == 0 => {
+            issues.push(format!("{}: text max_len is 0", name))
+        }
+        Bounds::Text(_) => {}
+        Bounds::Dump(dump) if dump.max_len == 0 => {
+            issues.push(format!("{}: dump max_len is 0", name))
+        }
+        Bounds::Dump(_) => {}
+    }
+}
+
+fn check_wide(name: &str, wide: &Wide, issues: &mut Vec<String>) {
+    if wide.lo > wide.hi {
+        issues.push(format!(
+            "{}: range low {} is above high {}",
+            name, wide.lo, wide.hi
+        ));
+    } else if wide.lo < wide.sysex_offset {
+        issues.push(format!(
+            "{}: range low {} is below sysex offset {}",
+            name, wide.lo, wide.sysex_offset
+        ));
+    } else if wide.hi - wide.sysex_offset > wide.max_raw() {
+        issues.push(format!(
+            "{}: range high {} does not fit in {} bytes",
+            name, wide.hi, wide.bytes
+        ));
+    }
+}
+
+fn check_range(name: &str, what: &str, range: &Range, issues: &mut Vec<String>) {
+    if range.lo > range.hi {
+        issues.push(format!(
+            "{}: {} low {} is above high {}",
+            name, what, range.lo, range.hi
+        ));
+    } else if range.code(range.lo).is_none() {
+        issues.push(format!(
+            "{}: {} low {} does not fit in a data byte with sysex offset {}",
+            name, what, range.lo, range.sysex_offset
+        ));
+    } else if range.code(range.hi).is_none() {
+        issues.push(format!(
+            "{}: {} high {} does not fit in a data byte with sysex offset {}",
+            name, what, range.hi, range.sysex_offset
+        ));
+    }
+}
+
+/// Parameters declared more than once, which deserialization silently merges
+fn duplicate_names(body: &str) -> Vec<String> {
+    #[derive(Deserialize)]
+    struct Names {
+        #[serde(default)]
+        parameters: Keys,
+    }
+    match serde_yaml::from_str::<Names>(body) {
+        Ok(names) => {
+            let keys = (names.parameters).0;
+            keys.iter()
+                .enumerate()
+                .filter(|(i, key)| keys[..*i].contains(key))
+                .map(|(_, key)| format!("{}: declared more than once", key))
+                .collect()
+        }
+        Err(_) => vec![],
+    }
+}
+
+/// Keys of a mapping in declaration order, including duplicates
+#[derive(Default)]
+struct Keys(Vec<String>);
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = Keys;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a mapping")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Keys, M::Error> {
+                let mut keys = vec![];
+                while let Some((key, IgnoredAny)) = map.next_entry::<String, IgnoredAny>()? {
+                    keys.push(key);
+                }
+                Ok(Keys(keys))
+            }
+        }
+
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::schema::lint::lint;
+    use crate::schema::BUNDLED;
+
+    #[test]
+    fn test_bundled_clean() {
+        for (file, body) in BUNDLED {
+            assert_eq!(lint(body), Vec::<String>::new(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn test_issues() {
+        let issues = lint(
+            r#"
+name: Bad
+vendor: Nobody
+manufacturer: [0x7d]
+port_prefix: Bad
+sysex: [0x01]
+parameters:
+  A:
+    sysex: [0x01, 0x02]
+    constraints:
+      - param: B
+        when: [W]
+        bounds: []
+    bounds:
+      - type: Range
+        lo: 10
+        hi: 2
+        sysex_offset: 0
+        min_firmware: '2'
+        max_firmware: '1.9'
+  B:
+    sysex: [0x01, 0x02]
+    default: ['Y']
+    bounds:
+      - type: Values
+        X: 0x01
+        Z:
+          code: 0x01
+          aliases: [x]
+  C:
+    sysex: [0x01, 0x03]
+  C:
+    sysex: [0x01, 0x04]
+"#,
+        );
+        assert_eq!(issues.len(), 9, "{:#?}", issues);
+        assert!(lint("name: [").len() == 1);
+    }
+}
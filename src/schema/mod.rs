@@ -1,15 +1,161 @@
-
 pub type Sysex = Vec<u8>;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 
-use crate::devices::{DeviceError, Result};
-use std::convert::TryFrom;
+use crate::devices::{DeviceError, Hint, Result};
+use linked_hash_map::LinkedHashMap;
+use regex::Regex;
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+mod lint;
+pub use lint::lint;
+
+lazy_static! {
+    /// All bundled device schemas, by device name
+    pub static ref DEVICES: LinkedHashMap<String, Device> = load_devices();
+}
+
+/// Reply timeout of devices whose schema does not specify one
+const DEFAULT_REPLY_TIMEOUT_MS: u64 = 500;
+
+/// Bundled schema files and their contents
+static BUNDLED: &[(&str, &str)] = &[
+    ("MicroBrute.yaml", include_str!("MicroBrute.yaml")),
+    ("BeatStep.yaml", include_str!("BeatStep.yaml")),
+    ("BeatStepPro.yaml", include_str!("BeatStepPro.yaml")),
+    ("KeyStep37.yaml", include_str!("KeyStep37.yaml")),
+    ("KeyStepPro.yaml", include_str!("KeyStepPro.yaml")),
+    ("MiniBrute2.yaml", include_str!("MiniBrute2.yaml")),
+    ("MiniBrute2S.yaml", include_str!("MiniBrute2S.yaml")),
+    ("DrumBrute.yaml", include_str!("DrumBrute.yaml")),
+    ("DrumBruteImpact.yaml", include_str!("DrumBruteImpact.yaml")),
+    ("MicroFreak.yaml", include_str!("MicroFreak.yaml")),
+    ("MatrixBrute.yaml", include_str!("MatrixBrute.yaml")),
+    ("PolyBrute.yaml", include_str!("PolyBrute.yaml")),
+    ("MiniLabMk2.yaml", include_str!("MiniLabMk2.yaml")),
+    ("KeyLabMk2.yaml", include_str!("KeyLabMk2.yaml")),
+    ("KeyLabEssential.yaml", include_str!("KeyLabEssential.yaml")),
+    ("BassStation2.yaml", include_str!("BassStation2.yaml")),
+    ("Minilogue.yaml", include_str!("Minilogue.yaml")),
+    ("Monologue.yaml", include_str!("Monologue.yaml")),
+    ("Mopho.yaml", include_str!("Mopho.yaml")),
+];
+
+/// Bundled files of parameters shared by several devices, see `Device::include`
+static SHARED: &[(&str, &str)] = &[
+    (
+        "ArturiaSequencer.yaml",
+        include_str!("ArturiaSequencer.yaml"),
+    ),
+    ("ArturiaControls.yaml", include_str!("ArturiaControls.yaml")),
+    (
+        "ArturiaArpeggiator.yaml",
+        include_str!("ArturiaArpeggiator.yaml"),
+    ),
+    ("ArturiaKeyLab.yaml", include_str!("ArturiaKeyLab.yaml")),
+];
 
-//lazy_static!{
-//    static ref
-//}
+/// Nesting limit of `extends` and `include`, which also stops recursive includes
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Bundled schema files and their contents
+pub fn bundled() -> &'static [(&'static str, &'static str)] {
+    BUNDLED
+}
+
+fn load_devices() -> LinkedHashMap<String, Device> {
+    let mut devices = LinkedHashMap::new();
+    for (file, body) in BUNDLED {
+        let mut dev = parse(body).expect("bundled device schema");
+        dev.source = file.to_string();
+        devices.insert(dev.name.clone(), dev);
+    }
+    devices
+}
+
+/// Print every loaded device schema as resolved in memory. Each entry that comes from another
+/// file than the entry containing it, through `extends` or `include`, is followed by that file.
+pub fn dump_resolved() -> Result<()> {
+    for dev in DEVICES.values() {
+        println!("# source: {}", dev.source);
+        print_node(
+            &serde_yaml::to_value(dev)?,
+            &mut vec![],
+            &dev.source,
+            &dev.origins,
+        );
+        println!();
+    }
+    Ok(())
+}
+
+/// Print the entries of a mapping, indented by their depth, with their origin if it differs
+/// from their parent's
+fn print_node(value: &Value, path: &mut Vec<String>, parent_origin: &str, origins: &Origins) {
+    let mapping = match value {
+        Value::Mapping(mapping) => mapping,
+        _ => return,
+    };
+    for (key, value) in mapping {
+        path.push(key_str(key));
+        let origin = origins.get(path).map_or(parent_origin, String::as_str);
+        let note = if origin == parent_origin {
+            String::new()
+        } else {
+            format!("  # {}", origin)
+        };
+        let indent = "  ".repeat(path.len() - 1);
+        if let Value::Mapping(_) = value {
+            println!("{}{}:{}", indent, path[path.len() - 1], note);
+            print_node(value, path, origin, origins);
+        } else {
+            let value = serde_json::to_string(value).unwrap_or_default();
+            println!("{}{}: {}{}", indent, path[path.len() - 1], value, note);
+        }
+        path.pop();
+    }
+}
+
+/// Print a JSON Schema of device schema files, for editors to complete and validate them
+pub fn export_json_schema() -> Result<()> {
+    let schema = schemars::schema_for!(Device);
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
+
+/// Find a device by name, ignoring case, or by one of its aliases
+pub fn device(name: &str) -> Result<&'static Device> {
+    DEVICES
+        .get(name)
+        .or_else(|| {
+            DEVICES.values().find(|dev| {
+                dev.name.eq_ignore_ascii_case(name)
+                    || dev
+                        .aliases
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(name))
+            })
+        })
+        .ok_or_else(|| {
+            Box::new(DeviceError::UnknownDevice {
+                device_name: name.to_string(),
+                hint: Hint::closest(
+                    name,
+                    DEVICES.values().flat_map(|dev| {
+                        std::iter::once(dev.name.as_str())
+                            .chain(dev.aliases.iter().map(String::as_str))
+                    }),
+                ),
+            }) as Box<dyn ::std::error::Error>
+        })
+}
 
 #[derive(Debug, EnumString, IntoStaticStr, EnumIter, Display)]
 pub enum DeviceType {
@@ -17,82 +163,1185 @@ pub enum DeviceType {
     BeatStep,
 }
 
-//impl From<DeviceType> for Device {
-//    fn from(dev: DeviceType) -> Self {
-//
-//    }
-//}
+fn parse(body: &str) -> Result<Device> {
+    parse_with(body, &|file| {
+        BUNDLED
+            .iter()
+            .chain(SHARED)
+            .find(|(name, _)| *name == file)
+            .map(|(_, body)| *body)
+    })
+}
 
-impl TryFrom<&str> for Device {
-    type Error = Box<dyn ::std::error::Error>;
+/// Files that entries of a resolved schema come from, by the path of keys to the entry, for
+/// entries coming from another file than the entry containing them
+pub type Origins = BTreeMap<Vec<String>, String>;
 
-    fn try_from(name: &str) -> Result<Device> {
-        match name {
-            "MicroBrute" => parse(include_str!("MicroBrute.yaml")),
-            _ => Err(Box::new(DeviceError::UnknownDevice {
-                device_name: name.to_string(),
+fn key_str(key: &Value) -> String {
+    key.as_str().map_or_else(
+        || serde_json::to_string(key).unwrap_or_default(),
+        str::to_string,
+    )
+}
+
+/// Parse a device schema, looking up the files it extends or includes with `resolve`
+fn parse_with(body: &str, resolve: &dyn Fn(&str) -> Option<&'static str>) -> Result<Device> {
+    let mut origins = Origins::new();
+    let doc = resolve_includes(serde_yaml::from_str(body)?, resolve, 0, &mut origins)?;
+    let mut dev: Device = serde_yaml::from_value(doc)?;
+    dev.origins = origins;
+    Ok(dev)
+}
+
+/// Complete a schema document with the document named by `extends` and the parameters of
+/// those named by `include`. The document's own entries come first and take precedence.
+/// The files entries are taken from are added to `origins`.
+fn resolve_includes(
+    mut doc: Value,
+    resolve: &dyn Fn(&str) -> Option<&'static str>,
+    depth: usize,
+    origins: &mut Origins,
+) -> Result<Value> {
+    let own = match doc.as_mapping_mut() {
+        Some(own) => own,
+        None => return Ok(doc),
+    };
+    let extends = own.remove(&Value::from("extends"));
+    let include = own.remove(&Value::from("include"));
+    let load = |file: &Value| -> Result<(String, Value, Origins)> {
+        let file = file.as_str().unwrap_or_default();
+        match resolve(file) {
+            Some(body) if depth < MAX_INCLUDE_DEPTH => {
+                let mut origins = Origins::new();
+                let doc = resolve_includes(
+                    serde_yaml::from_str(body)?,
+                    resolve,
+                    depth + 1,
+                    &mut origins,
+                )?;
+                Ok((file.to_string(), doc, origins))
+            }
+            _ => Err(Box::new(DeviceError::SchemaInclude {
+                file: file.to_string(),
             })),
         }
+    };
+    if let Some(file) = extends {
+        let (file, mut base, base_origins) = load(&file)?;
+        if let Some(base) = base.as_mapping_mut() {
+            base.remove(&Value::from("name"));
+            base.remove(&Value::from("aliases"));
+        }
+        let from = Origin {
+            file: &file,
+            origins: &base_origins,
+        };
+        fill(&mut doc, base, &mut vec![], &from, origins);
     }
+    for file in include
+        .as_ref()
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+    {
+        let (file, shared_doc, shared_origins) = load(file)?;
+        let mut shared = Mapping::new();
+        if let Some(params) = shared_doc.get("parameters") {
+            shared.insert(Value::from("parameters"), params.clone());
+        }
+        let from = Origin {
+            file: &file,
+            origins: &shared_origins,
+        };
+        fill(
+            &mut doc,
+            Value::Mapping(shared),
+            &mut vec![],
+            &from,
+            origins,
+        );
+    }
+    Ok(doc)
 }
 
-fn parse(body: &str) -> Result<Device> {
-    Ok(serde_yaml::from_str(body)?)
+/// A file entries are taken from, and the origins of its own entries taken from other files
+struct Origin<'a> {
+    file: &'a str,
+    origins: &'a Origins,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Add the entries of `base` missing from `doc`, recursively through mappings, recording
+/// where the added entries come from
+fn fill(
+    doc: &mut Value,
+    base: Value,
+    path: &mut Vec<String>,
+    from: &Origin,
+    origins: &mut Origins,
+) {
+    if let (Value::Mapping(doc), Value::Mapping(base)) = (doc, base) {
+        for (key, value) in base {
+            path.push(key_str(&key));
+            match doc.get_mut(&key) {
+                Some(own) => fill(own, value, path, from, origins),
+                None => {
+                    origins.insert(path.clone(), from.file.to_string());
+                    for (nested, file) in from.origins {
+                        if nested.starts_with(path) {
+                            origins.insert(nested.clone(), file.clone());
+                        }
+                    }
+                    doc.insert(key, value);
+                }
+            }
+            path.pop();
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Device {
-    vendor: String,
-    port_prefix: String,
-    sysex: Sysex,
-    parameters: BTreeMap<String, Parameter>,
+    /// File this device was loaded from
+    #[serde(skip)]
+    pub source: String,
+    /// Files that entries come from, when other than the device's own file
+    #[serde(skip)]
+    pub origins: Origins,
+    /// Bundled schema this device is based on, except for its name and aliases
+    #[serde(default, skip_serializing)]
+    pub extends: Option<String>,
+    /// Bundled files of shared parameters, which this device's parameters can override
+    #[serde(default, skip_serializing)]
+    pub include: Vec<String>,
+    pub name: String,
+    /// Short names accepted on the command line, e.g. `mb`
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub vendor: String,
+    /// Manufacturer id starting every sysex message, one byte or three starting with 0
+    pub manufacturer: Sysex,
+    pub port_prefix: String,
+    /// Regular expression matching port names of the device that do not start with its
+    /// `port_prefix`, such as renamed or localized ones
+    #[schemars(with = "Option<String>")]
+    pub port_pattern: Option<PortPattern>,
+    /// Start of the device's input port names, when they differ from its output port's
+    pub input_port_prefix: Option<String>,
+    /// USB ids of the device, to reach it without a MIDI port in builds with the `usb` feature
+    pub usb_vendor_id: Option<u16>,
+    pub usb_product_id: Option<u16>,
+    pub sysex: Sysex,
+    /// Family and model codes of the device's identity reply
+    pub identity: Option<IdentityCodes>,
+    /// How parameters are set and read, sysex unless told otherwise
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// MIDI channel of control changes, from 1 to 16, for devices using the NRPN protocol
+    pub midi_channel: Option<u8>,
+    /// Whether the device answers the selection of an NRPN parameter with its value
+    #[serde(default)]
+    pub nrpn_echo: bool,
+    /// Whether the device sends the sysex it receives back out, echoes that are not replies
+    #[serde(default)]
+    pub echoes_input: bool,
+    pub fragments: Option<Fragments>,
+    pub checksum: Option<Checksum>,
+    pub reply: Option<Reply>,
+    /// How long to wait for replies that do not arrive, in milliseconds
+    pub reply_timeout_ms: Option<u64>,
+    /// Pause between consecutive messages, for devices that drop messages sent too fast
+    pub send_delay_ms: Option<u64>,
+    #[schemars(with = "BTreeMap<String, Parameter>")]
+    pub parameters: LinkedHashMap<String, Parameter>,
+}
+
+/// Kind of MIDI messages setting and reading a device's parameters
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// Sysex messages made of the device header and each parameter's sysex
+    #[default]
+    Sysex,
+    /// NRPN control changes: the parameter number with CC 99 and 98, then its value with
+    /// CC 6, and CC 38 for the second byte of two byte values
+    Nrpn,
+}
+
+/// Codes telling a device apart in its Universal Identity Reply
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct IdentityCodes {
+    pub family: u16,
+    pub model: u16,
+}
+
+/// Convention used by devices that split large replies across multiple sysex messages.
+/// Positions are relative to the payload following the device's sysex header.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct Fragments {
+    /// Position of the continuation marker
+    pub marker_pos: usize,
+    /// Marker value of fragments followed by more fragments
+    pub more: u8,
+    /// Marker value of the final fragment
+    pub last: u8,
+    /// Position of the fragment data, preceding bytes are repeated in every fragment
+    pub data_pos: usize,
+    /// Most data bytes the device accepts in one fragment, dumps sent to it are split
+    /// when set
+    pub max_data: Option<usize>,
+}
+
+/// Layout of replies to queries, relative to the payload following the device's sysex header
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct Reply {
+    /// Position of the byte telling the parameter, the last byte of its sysex.
+    /// It is followed by the parameter's index, if any, then by its value.
+    #[serde(default)]
+    pub code_pos: usize,
+    /// Position of the whole sysex of the parameter, for devices whose parameters are not
+    /// told apart by a single byte. Takes precedence over `code_pos`.
+    pub sysex_pos: Option<usize>,
+    /// Position of the message id that replies repeat from their query, to match each reply
+    /// to the query it answers
+    pub id_pos: Option<usize>,
+}
+
+impl Reply {
+    /// Position following a parameter's sysex in a reply payload, if the reply is about it
+    pub fn sysex_end(&self, payload: &[u8], sysex: &[u8]) -> Option<usize> {
+        match self.sysex_pos {
+            Some(pos) => payload
+                .get(pos..)?
+                .starts_with(sysex)
+                .then(|| pos + sysex.len()),
+            None => (payload.get(self.code_pos)? == sysex.last()?).then(|| self.code_pos + 1),
+        }
+    }
+}
+
+/// Checksum byte ending the payload of every message, before the end of sysex.
+/// It covers the whole payload following the device's sysex header.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Checksum {
+    /// Two's complement of the 7 bit sum of the payload, as used by Roland devices
+    Roland,
+}
+
+impl Checksum {
+    pub fn compute(self, payload: &[u8]) -> u8 {
+        match self {
+            Checksum::Roland => {
+                let sum = payload
+                    .iter()
+                    .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+                (0x80 - (sum & 0x7f)) & 0x7f
+            }
+        }
+    }
+
+    /// The payload without its checksum, if the checksum is correct
+    pub fn verify(self, payload: &[u8]) -> Option<&[u8]> {
+        let (checksum, payload) = payload.split_last()?;
+        if self.compute(payload) == *checksum {
+            Some(payload)
+        } else {
+            None
+        }
+    }
+}
+
+impl Fragments {
+    /// Split a payload into fragments carrying at most `max_data` bytes each, if it is long
+    /// enough to hold the marker and the bytes preceding the data
+    pub fn split(&self, payload: &[u8], max_data: usize) -> Option<Vec<Sysex>> {
+        if self.marker_pos >= self.data_pos || payload.len() < self.data_pos {
+            return None;
+        }
+        let (head, data) = payload.split_at(self.data_pos);
+        let chunks: Vec<&[u8]> = data.chunks(max_data.max(1)).collect();
+        let count = chunks.len();
+        Some(
+            chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let mut fragment = head.to_vec();
+                    fragment[self.marker_pos] = if i + 1 < count { self.more } else { self.last };
+                    fragment.extend_from_slice(chunk);
+                    fragment
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Device {
+    /// Resolve a parameter name as given on the command line, e.g. `Gate`, `Seq/3`,
+    /// or `Pad/5:Note` for a mode of a modal parameter
+    pub fn param(&self, s: &str) -> Result<Param<'_>> {
+        check_input(s, |c| c.is_ascii_alphanumeric() || c == '/' || c == ':')?;
+        let mut mode_parts = s.splitn(2, ':');
+        let path = mode_parts.next().unwrap_or_default();
+        let mut parts = path.splitn(2, '/');
+        let name = parts.next().unwrap_or_default();
+        if name.is_empty() {
+            return Err(Box::new(DeviceError::EmptyParameter));
+        }
+        let (name, schema) = self
+            .parameters
+            .iter()
+            .find(|(pname, _)| pname.eq_ignore_ascii_case(name))
+            .ok_or_else(|| DeviceError::UnknownParameter {
+                param_name: s.to_string(),
+                hint: Hint::closest(name, self.parameters.keys().map(String::as_str)),
+            })?;
+        let index = match (parts.next(), &schema.index) {
+            (Some(idx), Some(range)) => {
+                // idx starts from range.lo, internally starts from 0
+                Some(range.code(i16::from_str(idx)?).ok_or_else(|| {
+                    DeviceError::ValueOutOfBound {
+                        value_name: s.to_string(),
+                    }
+                })?)
+            }
+            (None, None) => None,
+            _ => {
+                return Err(Box::new(DeviceError::UnknownParameter {
+                    param_name: s.to_string(),
+                    hint: Hint::default(),
+                }))
+            }
+        };
+        let mode = match (mode_parts.next(), &schema.modes) {
+            (Some(mode), Some(modes)) => Some(
+                modes
+                    .keys()
+                    .find(|m| m.eq_ignore_ascii_case(mode))
+                    .ok_or_else(|| DeviceError::UnknownParameter {
+                        param_name: s.to_string(),
+                        hint: Hint::closest(mode, modes.keys().map(String::as_str)),
+                    })?
+                    .as_str(),
+            ),
+            (None, _) => None,
+            (Some(_), None) => {
+                return Err(Box::new(DeviceError::UnknownParameter {
+                    param_name: s.to_string(),
+                    hint: Hint::default(),
+                }))
+            }
+        };
+        Ok(Param {
+            name,
+            schema,
+            index,
+            mode,
+        })
+    }
+
+    /// Every addressable parameter, with indexed parameters expanded
+    pub fn params(&self) -> Vec<Param<'_>> {
+        self.parameters
+            .iter()
+            .flat_map(|(name, schema)| match &schema.index {
+                Some(range) => (range.lo..=range.hi)
+                    .filter_map(|idx| range.code(idx))
+                    .map(|idx| Param {
+                        mode: None,
+                        name,
+                        schema,
+                        index: Some(idx),
+                    })
+                    .collect(),
+                None => vec![Param {
+                    mode: None,
+                    name,
+                    schema,
+                    index: None,
+                }],
+            })
+            .collect()
+    }
+
+    pub fn globals(&self) -> Vec<String> {
+        self.params().iter().map(|p| p.to_string()).collect()
+    }
+
+    /// Sysex header of the device's messages, its manufacturer id followed by its sysex
+    pub fn header(&self) -> Vec<u8> {
+        [&self.manufacturer[..], &self.sysex].concat()
+    }
+
+    /// Whether a MIDI port belongs to this device, and not to another whose port names
+    /// start the same, like the BeatStep and the BeatStep Pro
+    pub fn matches_port(&self, port_name: &str) -> bool {
+        if let Some(pattern) = &self.port_pattern {
+            if pattern.0.is_match(port_name) {
+                return true;
+            }
+        }
+        port_name.starts_with(&self.port_prefix)
+            && !DEVICES.values().any(|other| {
+                other.port_prefix.len() > self.port_prefix.len()
+                    && port_name.starts_with(&other.port_prefix)
+            })
+    }
+
+    pub fn reply_timeout(&self) -> Duration {
+        Duration::from_millis(self.reply_timeout_ms.unwrap_or(DEFAULT_REPLY_TIMEOUT_MS))
+    }
+
+    pub fn send_delay(&self) -> Duration {
+        Duration::from_millis(self.send_delay_ms.unwrap_or(0))
+    }
+
+    /// Factory values of all parameters that have one
+    pub fn defaults(&self) -> LinkedHashMap<String, Vec<String>> {
+        self.params()
+            .iter()
+            .filter_map(|p| {
+                p.schema
+                    .default
+                    .as_ref()
+                    .map(|d| (p.to_string(), d.clone()))
+            })
+            .collect()
+    }
+
+    /// Expand wildcards in parameter names: `*` is every parameter, `Seq/*` every index of `Seq`
+    pub fn expand(&self, param_names: &[String]) -> Result<Vec<String>> {
+        if param_names.is_empty() {
+            return Ok(self.globals());
+        }
+        let mut expanded = vec![];
+        for name in param_names {
+            if name == "*" {
+                expanded.extend(self.globals());
+            } else if let Some(base) = name.strip_suffix("/*") {
+                let matching: Vec<String> = self
+                    .params()
+                    .iter()
+                    .filter(|p| p.name.eq_ignore_ascii_case(base) && p.index.is_some())
+                    .map(|p| p.to_string())
+                    .collect();
+                if matching.is_empty() {
+                    return Err(Box::new(DeviceError::UnknownParameter {
+                        param_name: name.to_string(),
+                        hint: Hint::default(),
+                    }));
+                }
+                expanded.extend(matching);
+            } else {
+                expanded.push(self.param(name)?.to_string());
+            }
+        }
+        Ok(expanded)
+    }
+}
+
+/// Longest parameter name or value accepted from the command line
+pub const MAX_INPUT_LEN: usize = 64;
+
+/// Reject empty, overlong or otherwise malformed command line input before interpreting it
+pub fn check_input<F>(s: &str, allowed: F) -> Result<()>
+where
+    F: Fn(char) -> bool,
+{
+    if s.is_empty() {
+        return Err(Box::new(DeviceError::EmptyInput));
+    }
+    if s.len() > MAX_INPUT_LEN {
+        return Err(Box::new(DeviceError::InputTooLong {
+            input: s.chars().take(MAX_INPUT_LEN).collect::<String>() + "...",
+            max_len: MAX_INPUT_LEN,
+        }));
+    }
+    if let Some(c) = s.chars().find(|c| !allowed(*c)) {
+        return Err(Box::new(DeviceError::InvalidCharacter {
+            input: s.escape_default().to_string(),
+            character: c.escape_default().to_string(),
+        }));
+    }
+    Ok(())
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// A resolved parameter reference, with its raw (sysex) index if the parameter is indexed
+#[derive(Debug, Clone, Copy)]
+pub struct Param<'a> {
+    pub name: &'a str,
+    pub schema: &'a Parameter,
+    pub index: Option<u8>,
+    /// Mode of a modal parameter, if given
+    pub mode: Option<&'a str>,
+}
+
+impl<'a> Param<'a> {
+    pub fn bounds(&self) -> &'a [Bounds] {
+        self.schema.bounds.as_ref().map_or(&[], |b| b.as_slice())
+    }
+
+    /// Modes of a modal parameter, empty for other parameters
+    pub fn modes(&self) -> Vec<(&'a str, &'a Mode)> {
+        self.schema
+            .modes
+            .iter()
+            .flatten()
+            .map(|(name, mode)| (name.as_str(), mode))
+            .collect()
+    }
+
+    pub fn mode_schema(&self) -> Option<&'a Mode> {
+        let mode = self.mode?;
+        self.schema.modes.as_ref()?.get(mode)
+    }
+
+    /// The parameter's category, or the first word of its name, e.g. `Lfo` for `LfoRate`
+    pub fn category(&self) -> &'a str {
+        if let Some(category) = &self.schema.category {
+            return category;
+        }
+        let end = self
+            .name
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| c.is_ascii_uppercase() || c.is_ascii_digit())
+            .map_or(self.name.len(), |(pos, _)| pos);
+        &self.name[..end]
+    }
+}
+
+impl<'a> Display for Param<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if let (Some(idx), Some(range)) = (self.index, &self.schema.index) {
+            match range.value(idx) {
+                Some(idx) => f.write_fmt(format_args!("/{}", idx))?,
+                None => f.write_fmt(format_args!("/?{}", idx))?,
+            }
+        }
+        if let Some(mode) = self.mode {
+            f.write_fmt(format_args!(":{}", mode))?;
+        }
+        Ok(())
+    }
+}
+
+/// A parameter type generated from a schema, see the `codegen` feature
+#[cfg(feature = "codegen")]
+#[allow(dead_code)]
+pub trait TypedParam: Sized {
+    /// Parameter name, without index
+    const NAME: &'static str;
+
+    /// Values as accepted by `Device::update`
+    fn value_ids(&self) -> Vec<String>;
+
+    /// Parse values as returned by `Device::query`
+    fn from_value_ids(ids: &[String]) -> Option<Self>;
+}
+
+/// A regular expression matching port names, compiled when the schema is loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortPattern(Regex);
+
+impl TryFrom<String> for PortPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> std::result::Result<Self, Self::Error> {
+        Regex::new(&pattern).map(PortPattern)
+    }
+}
+
+impl From<PortPattern> for String {
+    fn from(pattern: PortPattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl PartialEq for PortPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// A setting of a device, read and written by sysex
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Parameter {
-    sysex: Sysex,
-    index: Option<Range>,
-    bounds: Option<Vec<Bounds>>,
-    modes: Option<BTreeMap<String, Mode>>,
+    /// Factory value, applied by `reset`
+    pub default: Option<Vec<String>>,
+    /// Writes overwrite user data, such as a sequence, and need confirmation
+    #[serde(default)]
+    pub destructive: bool,
+    #[serde(flatten)]
+    pub firmware: Firmwares,
+    #[serde(default)]
+    pub sysex: ParamSysex,
+    /// NRPN number of the parameter on devices using the NRPN protocol, to which its index
+    /// is added
+    pub nrpn: Option<u16>,
+    /// Bytes between the index and the value in replies
+    #[serde(default)]
+    pub reply_skip: usize,
+    pub index: Option<Range>,
+    pub bounds: Option<Vec<Bounds>>,
+    pub modes: Option<BTreeMap<String, Mode>>,
+    /// Narrower bounds applying depending on other parameters
+    #[serde(default)]
+    pub constraints: Vec<Constraint>,
+    /// Group of related parameters, shown together by `diff`
+    pub category: Option<String>,
+}
+
+/// Bounds a parameter's values must also be in while another parameter has one of some values.
+/// Values are still encoded with the parameter's own bounds.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Constraint {
+    /// The other parameter
+    pub param: String,
+    /// Values of the other parameter for which the constraint applies
+    pub when: Vec<String>,
+    pub bounds: Vec<Bounds>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Sysex identifying a parameter, the same for all operations or specific to some of them
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ParamSysex {
+    Single(Sysex),
+    /// Operations without their own sysex use the default one
+    Split {
+        default: Sysex,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        update: Option<Sysex>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        query: Option<Sysex>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reply: Option<Sysex>,
+    },
+}
+
+impl Default for ParamSysex {
+    fn default() -> Self {
+        ParamSysex::Single(vec![])
+    }
+}
+
+/// Operation on a parameter, selecting its sysex
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    Update,
+    Query,
+    Reply,
+}
+
+impl ParamSysex {
+    pub fn get(&self, form: Form) -> &[u8] {
+        match self {
+            ParamSysex::Single(sysex) => sysex,
+            ParamSysex::Split {
+                default,
+                update,
+                query,
+                reply,
+            } => match form {
+                Form::Update => update.as_ref(),
+                Form::Query => query.as_ref(),
+                Form::Reply => reply.as_ref(),
+            }
+            .unwrap_or(default),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Mode {
-    sysex: Sysex,
-    fields: Fields,
+    pub sysex: Sysex,
+    #[serde(default)]
+    #[schemars(with = "BTreeMap<String, Field>")]
+    pub fields: Fields,
 }
 
-pub type Fields = BTreeMap<String, Vec<Bounds>>;
+pub type Fields = LinkedHashMap<String, Field>;
+
+/// A setting of a modal parameter that only exists in some modes
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Field {
+    pub sysex: ParamSysex,
+    pub bounds: Vec<Bounds>,
+}
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(tag = "type")]
 pub enum Bounds {
     /// Name / Value pair
-    Values(BTreeMap<String, u8>),
+    Values(#[schemars(with = "BTreeMap<String, ValueCode>")] LinkedHashMap<String, ValueCode>),
 
     /// Raw value offset and display value bounds (Low to High, inclusive)
     Range(Range),
 
     /// Sequence of notes with offset from std MIDI note value
     NoteSeq(NoteSeq),
+
+    /// Like `Range`, for values spread over several 7 bit data bytes, such as MSB/LSB pairs
+    Wide(Wide),
+
+    /// ASCII text, such as a preset or device name
+    Text(Text),
+
+    /// Opaque data bytes, such as a preset dump, shown in hex
+    Dump(Dump),
+}
+
+impl Display for Bounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Bounds::Values(values) => {
+                let names: Vec<&str> = values.keys().map(String::as_str).collect();
+                write!(f, "values {}", names.join(" "))
+            }
+            Bounds::Range(range) => write!(
+                f,
+                "range {}..{} (sysex offset {})",
+                range.display(range.lo),
+                range.display(range.hi),
+                range.sysex_offset
+            ),
+            Bounds::NoteSeq(seq) => write!(
+                f,
+                "notes (max {}, sysex offset {})",
+                seq.max_len, seq.sysex_offset
+            ),
+            Bounds::Wide(wide) => write!(
+                f,
+                "range {}..{} over {} bytes {} (sysex offset {})",
+                wide.lo, wide.hi, wide.bytes, wide.order, wide.sysex_offset
+            ),
+            Bounds::Text(text) => write!(f, "text (max {} characters)", text.max_len),
+            Bounds::Dump(dump) => write!(f, "dump (max {} bytes)", dump.max_len),
+        }
+    }
+}
+
+/// Code of a named value, with alternate names accepted on input
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum ValueCode {
+    Code(u8),
+    Aliased { code: u8, aliases: Vec<String> },
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+impl ValueCode {
+    pub fn code(&self) -> u8 {
+        match self {
+            ValueCode::Code(code) | ValueCode::Aliased { code, .. } => *code,
+        }
+    }
+
+    pub fn aliases(&self) -> &[String] {
+        match self {
+            ValueCode::Code(_) => &[],
+            ValueCode::Aliased { aliases, .. } => aliases,
+        }
+    }
+
+    /// Whether some input names this value, ignoring case
+    pub fn is_named(&self, name: &str, input: &str) -> bool {
+        name.eq_ignore_ascii_case(input)
+            || self
+                .aliases()
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(input))
+    }
+}
+
+impl From<u8> for ValueCode {
+    fn from(code: u8) -> Self {
+        ValueCode::Code(code)
+    }
+}
+
+/// Order in which multiple bounds of a parameter are tried, when encoding or decoding a value.
+/// Named values take precedence over ranges, so that sentinel values such as `All` always
+/// resolve to their name even if they fall within a range. Bounds of the same kind are
+/// tried in declaration order, and the first match wins.
+pub fn resolution_order(bounds: &[Bounds]) -> Vec<&Bounds> {
+    let mut ordered: Vec<&Bounds> = bounds.iter().collect();
+    ordered.sort_by_key(|b| match b {
+        Bounds::Values(_) => 0,
+        Bounds::Range(_) | Bounds::Wide(_) => 1,
+        Bounds::NoteSeq(_) | Bounds::Text(_) | Bounds::Dump(_) => 2,
+    });
+    ordered
+}
+
+impl Bounds {
+    /// Minimum and maximum number of values accepted on update
+    pub fn value_count(&self) -> (usize, usize) {
+        match self {
+            Bounds::NoteSeq(seq) => (0, seq.max_len as usize),
+            // words of a text are joined by spaces
+            Bounds::Text(text) => (1, text.max_len as usize),
+            _ => (1, 1),
+        }
+    }
+
+    /// A valid value for these bounds, as it would be typed on the command line
+    pub fn example(&self) -> String {
+        match self {
+            Bounds::Values(values) => values.keys().next().cloned().unwrap_or_default(),
+            Bounds::Range(range) => range.display(range.hi),
+            Bounds::NoteSeq(_) => "C3 D#3 G3".to_string(),
+            Bounds::Wide(wide) => wide.hi.to_string(),
+            Bounds::Text(_) => "'My Preset'".to_string(),
+            Bounds::Dump(_) => "00017f".to_string(),
+        }
+    }
+
+    /// Firmware versions these bounds are available in, named values are always available
+    pub fn firmware(&self) -> Firmwares {
+        match self {
+            Bounds::Values(_) => Firmwares::default(),
+            Bounds::Range(range) => range.firmware,
+            Bounds::NoteSeq(seq) => seq.firmware,
+            Bounds::Wide(wide) => wide.firmware,
+            Bounds::Text(text) => text.firmware,
+            Bounds::Dump(dump) => dump.firmware,
+        }
+    }
+}
+
+/// Up to `max_len` printable ASCII characters, one per data byte
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct Text {
+    pub max_len: u8,
+    /// Byte filling the text up to `max_len`, texts are sent at their own length without it
+    pub pad: Option<u8>,
+    #[serde(flatten)]
+    pub firmware: Firmwares,
+}
+
+/// Up to `max_len` raw 7 bit data bytes, written as one hex string. Only meant to be read
+/// back and written again as is, such as by backup and restore.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct Dump {
+    pub max_len: u16,
+    /// 8 bit data sent in groups of 7 bytes, each preceded by a byte of their high bits,
+    /// as Korg devices do. `max_len` counts the 8 bit bytes.
+    #[serde(default)]
+    pub packed: bool,
+    #[serde(flatten)]
+    pub firmware: Firmwares,
+}
+
+impl Dump {
+    /// Data bytes of some dumped data
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        if !self.packed {
+            return data.to_vec();
+        }
+        let mut bytes = Vec::with_capacity(data.len() + data.len().div_ceil(7));
+        for group in data.chunks(7) {
+            let high = group
+                .iter()
+                .enumerate()
+                .fold(0, |high, (i, byte)| high | (byte >> 7) << i);
+            bytes.push(high);
+            bytes.extend(group.iter().map(|byte| byte & 0x7f));
+        }
+        bytes
+    }
+
+    /// Dumped data of some data bytes
+    pub fn decode(&self, bytes: &[u8]) -> Vec<u8> {
+        if !self.packed {
+            return bytes.to_vec();
+        }
+        bytes
+            .chunks(8)
+            .flat_map(|group| {
+                let (high, low) = group.split_first().unwrap_or((&0, &[]));
+                low.iter()
+                    .enumerate()
+                    .map(move |(i, byte)| byte | ((high >> i) & 1) << 7)
+            })
+            .collect()
+    }
+}
+
+/// Numbers from `lo` to `hi`, sent less the sysex offset
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Range {
-    lo: u8,
-    hi: u8,
-    sysex_offset: u8,
+    pub lo: i16,
+    pub hi: i16,
+    pub sysex_offset: i16,
+    /// Values are sent as 7 bit two's complement, from -64 to 63, after the sysex offset
+    #[serde(default)]
+    pub twos_complement: bool,
+    /// Displayed value of each step from `lo` to `hi`, e.g. 2.5 for 2.5ms steps
+    pub scale: Option<f64>,
+    /// Suffix of displayed values, e.g. `ms`, optional on input
+    pub unit: Option<String>,
+    #[serde(flatten)]
+    pub firmware: Firmwares,
+}
+
+impl Range {
+    /// Data byte of a value, if it is within the range
+    pub fn code(&self, value: i16) -> Option<u8> {
+        if value < self.lo || value > self.hi {
+            return None;
+        }
+        let raw = value - self.sysex_offset;
+        let (min, max) = if self.twos_complement {
+            (-0x40, 0x3f)
+        } else {
+            (0, 0x7f)
+        };
+        if raw >= min && raw <= max {
+            Some(raw as u8 & 0x7f)
+        } else {
+            None
+        }
+    }
+
+    /// Value of a data byte, if it is within the range
+    pub fn value(&self, code: u8) -> Option<i16> {
+        let raw = if self.twos_complement && code & 0x40 != 0 {
+            i16::from(code & 0x7f) - 0x80
+        } else {
+            i16::from(code)
+        };
+        let value = raw + self.sysex_offset;
+        if value >= self.lo && value <= self.hi {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Displayed value of a step, scaled and with its unit
+    pub fn display(&self, step: i16) -> String {
+        let value = match self.scale {
+            Some(scale) => (f64::from(step) * scale).to_string(),
+            None => step.to_string(),
+        };
+        format!("{}{}", value, self.unit.as_deref().unwrap_or_default())
+    }
+
+    /// Step of a displayed value, rounded to the nearest one if the range is scaled
+    pub fn step(&self, value: &str) -> Option<i16> {
+        let value = match &self.unit {
+            Some(unit) => value.strip_suffix(unit.as_str()).unwrap_or(value),
+            None => value,
+        };
+        match self.scale {
+            Some(scale) => {
+                let step = (f64::from_str(value).ok()? / scale).round();
+                if step >= f64::from(i16::MIN) && step <= f64::from(i16::MAX) {
+                    Some(step as i16)
+                } else {
+                    None
+                }
+            }
+            None => i16::from_str(value).ok(),
+        }
+    }
+
+    /// Whether values can be negative, so that `-N` is not a relative change
+    pub fn is_signed(&self) -> bool {
+        self.lo < 0
+    }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+/// Up to `max_len` notes, sent as MIDI note numbers plus the sysex offset
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
 pub struct NoteSeq {
-    max_len: u8,
-    sysex_offset: u8,
+    pub max_len: u8,
+    pub sysex_offset: u8,
+    /// Code of a rest step, shown as `_`
+    pub rest: Option<u8>,
+    /// Code of a step holding the previous note, shown as `-`
+    pub tie: Option<u8>,
+    /// Notes per message, for sequences sent and read in blocks. The notes of each block
+    /// follow its offset and length, and are padded with zeroes.
+    pub block_len: Option<u8>,
+    #[serde(default)]
+    pub block_offset: BlockOffset,
+    #[serde(flatten)]
+    pub firmware: Firmwares,
+}
+
+/// How the offset of a block of notes is given
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockOffset {
+    /// Position of the first note of the block
+    #[default]
+    Notes,
+    /// Number of the block, counting from 0
+    Blocks,
+}
+
+impl NoteSeq {
+    /// Number of blocks holding the longest sequence, 1 if it is not sent in blocks
+    pub fn blocks(&self) -> usize {
+        match self.block_len {
+            Some(len) if len > 0 => (self.max_len as usize).div_ceil(len as usize),
+            _ => 1,
+        }
+    }
+
+    /// Offset code of a block
+    pub fn offset_code(&self, block: usize) -> u8 {
+        match self.block_offset {
+            BlockOffset::Notes => (block * self.block_len.unwrap_or(0) as usize) as u8,
+            BlockOffset::Blocks => block as u8,
+        }
+    }
+
+    /// Position of the first note of a block, from its offset code
+    pub fn offset_notes(&self, code: u8) -> usize {
+        match self.block_offset {
+            BlockOffset::Notes => code as usize,
+            BlockOffset::Blocks => code as usize * self.block_len.unwrap_or(0) as usize,
+        }
+    }
+}
+
+/// Numbers from `lo` to `hi`, sent less the sysex offset over several 7 bit bytes
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct Wide {
+    pub lo: u32,
+    pub hi: u32,
+    pub sysex_offset: u32,
+    /// Number of 7 bit data bytes holding the value
+    pub bytes: u8,
+    #[serde(default)]
+    pub order: ByteOrder,
+    #[serde(flatten)]
+    pub firmware: Firmwares,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy, Display, JsonSchema)]
+pub enum ByteOrder {
+    /// Most significant 7 bits first, as MIDI MSB/LSB pairs
+    #[strum(serialize = "msb first")]
+    #[default]
+    MsbFirst,
+    #[strum(serialize = "lsb first")]
+    LsbFirst,
+}
+
+impl Wide {
+    /// Data bytes of a raw value, which must fit in them
+    pub fn encode(&self, raw: u32) -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..self.bytes)
+            .map(|i| ((raw >> (7 * u32::from(i))) & 0x7f) as u8)
+            .collect();
+        if self.order == ByteOrder::MsbFirst {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    /// Raw value of the leading data bytes, if there are enough of them
+    pub fn decode(&self, bytes: &[u8]) -> Option<u32> {
+        let mut bytes = bytes.get(..self.bytes as usize)?.to_vec();
+        if self.order == ByteOrder::LsbFirst {
+            bytes.reverse();
+        }
+        Some(
+            bytes
+                .iter()
+                .fold(0, |raw, byte| (raw << 7) | u32::from(byte & 0x7f)),
+        )
+    }
+
+    /// Highest raw value that fits in the data bytes
+    pub fn max_raw(&self) -> u32 {
+        (1u64 << (7 * u32::from(self.bytes)).min(32)).saturating_sub(1) as u32
+    }
+}
+
+/// Firmware versions a parameter or bounds is available in, both inclusive
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+pub struct Firmwares {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub min_firmware: Option<Firmware>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<String>")]
+    pub max_firmware: Option<Firmware>,
+}
+
+impl Firmwares {
+    pub fn contains(&self, firmware: Firmware) -> bool {
+        self.min_firmware.is_none_or(|min| firmware >= min)
+            && self.max_firmware.is_none_or(|max| firmware <= max)
+    }
+}
+
+impl Display for Firmwares {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.min_firmware, self.max_firmware) {
+            (Some(min), Some(max)) => write!(f, "firmware {} to {}", min, max),
+            (Some(min), None) => write!(f, "firmware {} and later", min),
+            (None, Some(max)) => write!(f, "firmware {} and earlier", max),
+            (None, None) => write!(f, "any firmware"),
+        }
+    }
+}
+
+/// Firmware version as reported in the identity reply, written `1.0.2.1` in schemas.
+/// Missing trailing numbers are zero, so `2` is the same version as `2.0.0.0`.
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Copy, JsonSchema,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct Firmware(pub [u8; 4]);
+
+impl FromStr for Firmware {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut version = [0; 4];
+        let parts: Vec<&str> = s.split('.').collect();
+        if parts.len() > version.len() {
+            return Err(format!("firmware version {} has more than 4 numbers", s));
+        }
+        for (number, part) in version.iter_mut().zip(parts) {
+            *number = u8::from_str(part).map_err(|_| format!("bad firmware version {}", s))?;
+        }
+        Ok(Firmware(version))
+    }
+}
+
+impl TryFrom<String> for Firmware {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        Firmware::from_str(&s)
+    }
+}
+
+impl From<Firmware> for String {
+    fn from(firmware: Firmware) -> String {
+        firmware.to_string()
+    }
+}
+
+impl Display for Firmware {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u8::to_string).collect();
+        f.write_str(&parts.join("."))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::schema::{parse, Device};
+    use crate::schema::{device, parse, parse_with, Device, Dump, Form, DEVICES};
 
     #[test]
     fn test_parse() {
@@ -100,6 +1349,7 @@ mod test {
             r"
 name: MicroBrute
 vendor: Arturia
+manufacturer: [0x00, 0x20, 0x6b]
 port_prefix: MicroBrute
 sysex:
 - 0x05
@@ -140,4 +1390,138 @@ parameters:
         .unwrap();
         dbg!(z);
     }
+
+    #[cfg(feature = "codegen")]
+    #[test]
+    fn test_generated() {
+        use crate::devices::bound_codes;
+        use crate::generated::microbrute::{MidiRecvChan, SeqStep};
+        use crate::schema::TypedParam;
+
+        let brute = DEVICES.get("MicroBrute").unwrap();
+        let step = SeqStep::V1_16;
+        assert_eq!(step.value_ids(), vec!["1/16"]);
+        assert!(bound_codes(&brute.param(SeqStep::NAME).unwrap(), &step.value_ids()).is_ok());
+        assert_eq!(
+            MidiRecvChan::from_value_ids(&["All".to_string()]),
+            Some(MidiRecvChan::All)
+        );
+        assert_eq!(
+            MidiRecvChan::from_value_ids(&["3".to_string()]),
+            Some(MidiRecvChan::Value(3))
+        );
+
+        use crate::devices::modal_codes;
+        use crate::generated::beatstep::Encoder;
+        let encoder = Encoder::CC(vec!["Channel=2".to_string()]);
+        assert_eq!(encoder.value_ids(), vec!["CC", "Channel=2"]);
+        assert_eq!(
+            Encoder::from_value_ids(&encoder.value_ids()),
+            Some(encoder.clone())
+        );
+        let beatstep = DEVICES.get("BeatStep").unwrap();
+        let param = beatstep.param("Encoder/1").unwrap();
+        assert!(modal_codes(&param, &encoder.value_ids()).is_ok());
+    }
+
+    #[test]
+    fn test_include() {
+        let resolve = |file: &str| {
+            match file {
+            "Base.yaml" => Some("name: Base\naliases: [b]\nvendor: V\nmanufacturer: [0x7d]\nport_prefix: B\nsysex: [0x01]\nparameters: {}"),
+            "Shared.yaml" => Some("parameters:\n  Tempo:\n    sysex: [0x01, 0x02]\n    bounds: [{type: Range, lo: 30, hi: 127, sysex_offset: 0}]\n  Swing:\n    sysex: [0x01, 0x03]\n    bounds: [{type: Values, Off: 0}]"),
+            "Loop.yaml" => Some("include: [Loop.yaml]"),
+            _ => None,
+        }
+        };
+        let dev = parse_with(
+            "name: Derived\nextends: Base.yaml\ninclude: [Shared.yaml]\nparameters:\n  Swing:\n    sysex: [0x01, 0x04]",
+            &resolve,
+        )
+        .unwrap();
+        assert_eq!(dev.name, "Derived");
+        assert!(dev.aliases.is_empty());
+        assert_eq!(dev.port_prefix, "B");
+        let names: Vec<&String> = dev.parameters.keys().collect();
+        assert_eq!(names, vec!["Swing", "Tempo"]);
+        let swing = dev.param("Swing").unwrap();
+        assert_eq!(swing.schema.sysex.get(Form::Update), &[0x01, 0x04]);
+        assert_eq!(swing.bounds().len(), 1);
+        let origin = |path: &[&str]| {
+            let path: Vec<String> = path.iter().map(|key| key.to_string()).collect();
+            dev.origins.get(&path).cloned()
+        };
+        assert_eq!(origin(&["port_prefix"]), Some("Base.yaml".to_string()));
+        assert_eq!(origin(&["parameters", "Swing"]), None);
+        assert_eq!(
+            origin(&["parameters", "Swing", "bounds"]),
+            Some("Shared.yaml".to_string())
+        );
+        assert!(parse_with("include: [Missing.yaml]", &resolve).is_err());
+        assert!(parse_with("include: [Loop.yaml]", &resolve).is_err());
+    }
+
+    #[test]
+    fn test_bundled() {
+        let brute = DEVICES.get("MicroBrute").unwrap();
+        assert_eq!(brute.param("Seq/8").unwrap().index, Some(7));
+        assert_eq!(brute.param("Seq/8").unwrap().to_string(), "Seq/8");
+        assert!(brute.param("Seq/9").is_err());
+        assert_eq!(device("mb").unwrap().name, "MicroBrute");
+        assert_eq!(device("microbrute").unwrap().name, "MicroBrute");
+        assert!(device("micro").is_err());
+        assert!(brute.param("Seq").is_err());
+        assert!(brute.param("Gate/1").is_err());
+        assert!(brute.param("").is_err());
+        assert!(brute.param("Gate\u{7}").is_err());
+        assert!(brute.param(&"Gate".repeat(20)).is_err());
+        assert_eq!(brute.globals().len(), 14 + 8);
+        assert_eq!(brute.expand(&["*".to_string()]).unwrap(), brute.globals());
+        let seqs = brute.expand(&["Gate".to_string(), "seq/*".to_string()]);
+        assert_eq!(seqs.unwrap().len(), 1 + 8);
+        assert!(brute.expand(&["Gate/*".to_string()]).is_err());
+        assert_eq!(
+            brute.expand(&["keynotepriority".to_string()]).unwrap(),
+            vec!["KeyNotePriority".to_string()]
+        );
+        let step = brute.param("SeqStep").unwrap();
+        assert_eq!(step.bounds()[0].example(), "1/4");
+        assert_eq!(step.schema.sysex.get(Form::Update), &[0x01, 0x38]);
+        assert_eq!(step.schema.sysex.get(Form::Query), &[0x01, 0x39]);
+        assert_eq!(step.schema.sysex.get(Form::Reply), &[0x01, 0x38]);
+        assert_eq!(step.category(), "Seq");
+        assert_eq!(brute.param("Gate").unwrap().category(), "Gate");
+    }
+
+    #[test]
+    fn test_matches_port() {
+        let beatstep = device("BeatStep").unwrap();
+        let pro = device("BeatStepPro").unwrap();
+        assert!(beatstep.matches_port("Arturia BeatStep MIDI 1"));
+        assert!(!beatstep.matches_port("Arturia BeatStep Pro MIDI 1"));
+        assert!(pro.matches_port("Arturia BeatStep Pro MIDI 1"));
+        assert!(!pro.matches_port("Arturia BeatStep MIDI 1"));
+        let brute = device("MicroBrute").unwrap();
+        assert!(brute.matches_port("Arturia MicroBrute"));
+        assert!(brute.matches_port("2- microbrute"));
+        assert!(!brute.matches_port("MiniBrute 2"));
+        let bad = "name: Bad\nvendor: V\nmanufacturer: [0x7d]\nport_prefix: B\nport_pattern: '(b'\nsysex: [0x01]\nparameters: {}";
+        assert!(parse(bad).is_err());
+    }
+
+    #[test]
+    fn test_packed_dump() {
+        let dump = Dump {
+            max_len: 16,
+            packed: true,
+            firmware: Default::default(),
+        };
+        let data = [0x80, 0x01, 0xff, 0x00, 0x00, 0x00, 0x7f, 0x81];
+        let bytes = dump.encode(&data);
+        assert_eq!(
+            bytes,
+            vec![0x05, 0x00, 0x01, 0x7f, 0x00, 0x00, 0x00, 0x7f, 0x01, 0x01]
+        );
+        assert_eq!(dump.decode(&bytes), data.to_vec());
+    }
 }
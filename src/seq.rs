@@ -0,0 +1,351 @@
+//! Edits of note sequences, whose steps are note names as listed by `get`, or `_` for rests.
+
+use crate::devices::{DeviceError, Hint, MidiNote, Result, REST, TIE};
+use crate::schema::NoteSeq;
+use crate::smf;
+
+use std::str::FromStr;
+
+/// Semitones of the degrees of each mode above its root
+const MODES: &[(&str, &[u8])] = &[
+    ("major", &[0, 2, 4, 5, 7, 9, 11]),
+    ("ionian", &[0, 2, 4, 5, 7, 9, 11]),
+    ("dorian", &[0, 2, 3, 5, 7, 9, 10]),
+    ("phrygian", &[0, 1, 3, 5, 7, 8, 10]),
+    ("lydian", &[0, 2, 4, 6, 7, 9, 11]),
+    ("mixolydian", &[0, 2, 4, 5, 7, 9, 10]),
+    ("minor", &[0, 2, 3, 5, 7, 8, 10]),
+    ("aeolian", &[0, 2, 3, 5, 7, 8, 10]),
+    ("locrian", &[0, 1, 3, 5, 6, 8, 10]),
+    ("harmonic-minor", &[0, 2, 3, 5, 7, 8, 11]),
+    ("major-pentatonic", &[0, 2, 4, 7, 9]),
+    ("minor-pentatonic", &[0, 3, 5, 7, 10]),
+    ("blues", &[0, 3, 5, 6, 7, 10]),
+];
+
+/// The notes of a mode from a root note, e.g. `D-dorian` or `F#-minor`
+#[derive(Debug)]
+pub struct Scale {
+    root: u8,
+    degrees: &'static [u8],
+}
+
+impl FromStr for Scale {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '-');
+        let root = parts.next().unwrap_or_default();
+        let mode = parts.next().unwrap_or("major");
+        let root = MidiNote::from_str(&format!("{}0", root.to_ascii_uppercase()))?.number() % 12;
+        let degrees = MODES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(mode))
+            .map(|(_, degrees)| *degrees)
+            .ok_or_else(|| DeviceError::UnknownValue {
+                value_name: mode.to_string(),
+                hint: Hint::closest(mode, MODES.iter().map(|(name, _)| *name)),
+            })?;
+        Ok(Scale { root, degrees })
+    }
+}
+
+impl Scale {
+    fn contains(&self, note: u8) -> bool {
+        self.degrees.contains(&((note + 12 - self.root) % 12))
+    }
+
+    /// The note of the scale nearest to a note, the lower one if two are as near
+    pub fn nearest(&self, note: &MidiNote) -> Option<MidiNote> {
+        let note = note.number();
+        (0..12)
+            .flat_map(|distance| vec![note.checked_sub(distance), note.checked_add(distance)])
+            .flatten()
+            .filter(|note| self.contains(*note))
+            .find_map(MidiNote::from_number)
+    }
+}
+
+/// Move the notes of a sequence to the nearest notes of a scale, leaving rests and ties
+pub fn to_scale(steps: &mut [String], scale: &Scale) -> Result<()> {
+    for step in steps
+        .iter_mut()
+        .filter(|step| *step != REST && *step != TIE)
+    {
+        let note = MidiNote::from_str(step)?;
+        if let Some(nearest) = scale.nearest(&note) {
+            *step = nearest.to_string();
+        }
+    }
+    Ok(())
+}
+
+/// Steps of a sequence playing some notes, `steps_per_beat` steps to a quarter note of
+/// `division` ticks. Notes are moved to the nearest step, and only the first note starting on
+/// a step is kept. Steps on which no note starts are rests.
+pub fn quantize(notes: &[smf::Note], division: u16, steps_per_beat: u16) -> Result<Vec<String>> {
+    let ticks_per_step = (u32::from(division) / u32::from(steps_per_beat.max(1))).max(1);
+    let mut steps: Vec<String> = vec![];
+    for note in notes {
+        let step = ((note.tick + ticks_per_step / 2) / ticks_per_step) as usize;
+        if step < steps.len() {
+            continue;
+        }
+        let name = MidiNote::from_number(note.key).ok_or_else(|| DeviceError::ValueOutOfBound {
+            value_name: format!("MIDI note {}", note.key),
+        })?;
+        steps.resize(step, REST.to_string());
+        steps.push(name.to_string());
+    }
+    Ok(steps)
+}
+
+/// Set a step, numbered from 1, to a note or to `rest`. Steps added up to it are rests.
+pub fn set_step(steps: &mut Vec<String>, step: usize, note: &str, max_len: usize) -> Result<()> {
+    if step == 0 || step > max_len {
+        return Err(Box::new(DeviceError::ValueOutOfBound {
+            value_name: step.to_string(),
+        }));
+    }
+    if steps.len() < step {
+        steps.resize(step, REST.to_string());
+    }
+    steps[step - 1] = step_name(note);
+    Ok(())
+}
+
+/// Insert notes or rests before a step, numbered from 1, moving the following steps later
+pub fn insert(
+    steps: &mut Vec<String>,
+    step: usize,
+    notes: &[String],
+    max_len: usize,
+) -> Result<()> {
+    if step == 0 || step > steps.len() + 1 {
+        return Err(Box::new(DeviceError::ValueOutOfBound {
+            value_name: step.to_string(),
+        }));
+    }
+    if steps.len() + notes.len() > max_len {
+        return Err(too_long(steps.len() + notes.len(), max_len));
+    }
+    let notes = notes.iter().map(|note| step_name(note));
+    steps.splice(step - 1..step - 1, notes);
+    Ok(())
+}
+
+/// Remove `count` steps from a step, numbered from 1, moving the following steps earlier
+pub fn delete(steps: &mut Vec<String>, step: usize, count: usize) -> Result<()> {
+    if step == 0 || step + count > steps.len() + 1 {
+        return Err(Box::new(DeviceError::ValueOutOfBound {
+            value_name: step.to_string(),
+        }));
+    }
+    steps.drain(step - 1..step - 1 + count);
+    Ok(())
+}
+
+/// Move all steps later by `offset` steps, after as many rests, or earlier if it is negative,
+/// dropping the first steps
+pub fn shift(steps: &mut Vec<String>, offset: i32, max_len: usize) -> Result<()> {
+    let moved = offset.unsigned_abs() as usize;
+    if offset < 0 {
+        steps.drain(..moved.min(steps.len()));
+    } else if steps.len() + moved > max_len {
+        return Err(too_long(steps.len() + moved, max_len));
+    } else {
+        steps.splice(0..0, vec![REST.to_string(); moved]);
+    }
+    Ok(())
+}
+
+/// Play a sequence backwards. Ties still follow the notes they hold.
+pub fn reverse(steps: &mut Vec<String>) {
+    let mut groups: Vec<Vec<String>> = vec![];
+    for step in steps.drain(..) {
+        match groups.last_mut() {
+            Some(group) if step == TIE => group.push(step),
+            _ => groups.push(vec![step]),
+        }
+    }
+    steps.extend(groups.into_iter().rev().flatten());
+}
+
+/// Move all steps later by `offset` steps, those past the end coming back at the start, or
+/// earlier if it is negative
+pub fn rotate(steps: &mut [String], offset: i32) {
+    if steps.is_empty() {
+        return;
+    }
+    let moved = offset.rem_euclid(steps.len() as i32) as usize;
+    steps.rotate_right(moved);
+}
+
+/// Adapt a sequence to the bounds of another: steps past its maximum length are dropped, and
+/// ties are replaced by the note they hold if it has none. Returns the number of steps dropped.
+pub fn fit(steps: &mut Vec<String>, seq: &NoteSeq) -> usize {
+    let dropped = steps.len().saturating_sub(seq.max_len as usize);
+    steps.truncate(seq.max_len as usize);
+    if seq.tie.is_none() {
+        let mut held = REST.to_string();
+        for step in steps.iter_mut() {
+            if step == TIE {
+                *step = held.clone();
+            } else {
+                held = step.clone();
+            }
+        }
+    }
+    dropped
+}
+
+/// The step of a note, of `rest` or of `tie`
+fn step_name(note: &str) -> String {
+    if note.eq_ignore_ascii_case("rest") {
+        REST.to_string()
+    } else if note.eq_ignore_ascii_case("tie") {
+        TIE.to_string()
+    } else {
+        note.to_string()
+    }
+}
+
+fn too_long(len: usize, max_len: usize) -> Box<dyn std::error::Error> {
+    Box::new(DeviceError::ValueOutOfBound {
+        value_name: format!("{} steps, at most {}", len, max_len),
+    })
+}
+
+/// Lines of a grid showing the steps of a sequence across and their notes down, highest first.
+/// Notes held by a tie are shown with `=`.
+pub fn piano_roll(steps: &[String]) -> Vec<String> {
+    let mut held = None;
+    let notes: Vec<(Option<u8>, char)> = steps
+        .iter()
+        .map(|step| {
+            if step == TIE {
+                return (held, '=');
+            }
+            held = MidiNote::from_str(step).ok().map(|note| note.number());
+            (held, '#')
+        })
+        .collect();
+    let numbers = || notes.iter().filter_map(|(note, _)| *note);
+    let (lo, hi) = match (numbers().min(), numbers().max()) {
+        (Some(lo), Some(hi)) => (lo, hi),
+        _ => return vec![],
+    };
+    let mut lines = vec![];
+    for row in (lo..=hi).rev().filter_map(MidiNote::from_number) {
+        let mut line = format!("{:>4} ", row.to_string());
+        for (step, (note, mark)) in notes.iter().enumerate() {
+            if step > 0 && step % 4 == 0 {
+                line.push('|');
+            }
+            line.push(if *note == Some(row.number()) {
+                *mark
+            } else {
+                '.'
+            });
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use crate::schema::{self, Bounds};
+    use crate::seq::{
+        delete, fit, insert, piano_roll, quantize, reverse, rotate, set_step, shift, to_scale,
+        Scale,
+    };
+    use crate::smf::Note;
+
+    #[test]
+    fn test_quantize() {
+        let notes = [
+            Note { tick: 0, key: 48 },
+            Note { tick: 5, key: 50 },
+            Note { tick: 50, key: 52 },
+            Note { tick: 96, key: 53 },
+        ];
+        let steps = quantize(&notes, 96, 4).unwrap();
+        assert_eq!(steps, vec!["C3", "_", "E3", "_", "F3"]);
+        assert!(quantize(&[Note { tick: 0, key: 3 }], 96, 4).is_err());
+    }
+
+    #[test]
+    fn test_set_step() {
+        let mut steps = vec!["C3".to_string(), "D3".to_string()];
+        set_step(&mut steps, 2, "rest", 4).unwrap();
+        set_step(&mut steps, 4, "E3", 4).unwrap();
+        assert_eq!(steps, vec!["C3", "_", "_", "E3"]);
+        assert!(set_step(&mut steps, 5, "E3", 4).is_err());
+        assert!(set_step(&mut steps, 0, "E3", 4).is_err());
+    }
+
+    #[test]
+    fn test_insert_delete_shift() {
+        let notes = |s: &str| -> Vec<String> { s.split(' ').map(str::to_string).collect() };
+        let mut steps = notes("C3 D3");
+        insert(&mut steps, 2, &notes("E3 rest"), 5).unwrap();
+        assert_eq!(steps, notes("C3 E3 _ D3"));
+        assert!(insert(&mut steps, 1, &notes("F3 G3"), 5).is_err());
+        assert!(insert(&mut steps, 6, &notes("F3"), 5).is_err());
+        delete(&mut steps, 2, 2).unwrap();
+        assert_eq!(steps, notes("C3 D3"));
+        assert!(delete(&mut steps, 2, 2).is_err());
+        shift(&mut steps, 2, 5).unwrap();
+        assert_eq!(steps, notes("_ _ C3 D3"));
+        assert!(shift(&mut steps, 2, 5).is_err());
+        shift(&mut steps, -3, 5).unwrap();
+        assert_eq!(steps, notes("D3"));
+
+        let mut steps = notes("C3 - _ D3 E3");
+        reverse(&mut steps);
+        assert_eq!(steps, notes("E3 D3 _ C3 -"));
+        rotate(&mut steps, 2);
+        assert_eq!(steps, notes("C3 - E3 D3 _"));
+        rotate(&mut steps, -6);
+        assert_eq!(steps, notes("- E3 D3 _ C3"));
+
+        let param = schema::device("MicroBrute")
+            .unwrap()
+            .param("Seq/1")
+            .unwrap();
+        let seq = match param.bounds().first() {
+            Some(Bounds::NoteSeq(seq)) => seq,
+            _ => panic!("Seq/1 is a sequence"),
+        };
+        let mut steps = notes("C3 - - _ D3");
+        assert_eq!(fit(&mut steps, seq), 0);
+        assert_eq!(steps, notes("C3 C3 C3 _ D3"));
+        let mut steps = vec!["C3".to_string(); 70];
+        assert_eq!(fit(&mut steps, seq), 6);
+    }
+
+    #[test]
+    fn test_to_scale() {
+        let mut steps: Vec<String> = "C3 C#3 _ F3 G#3 B3"
+            .split(' ')
+            .map(str::to_string)
+            .collect();
+        to_scale(&mut steps, &"D-dorian".parse::<Scale>().unwrap()).unwrap();
+        assert_eq!(steps.join(" "), "C3 C3 _ F3 G3 B3");
+        to_scale(&mut steps, &"f#-minor-pentatonic".parse::<Scale>().unwrap()).unwrap();
+        assert_eq!(steps.join(" "), "B2 B2 _ E3 F#3 B3");
+        let err = "D-dorain".parse::<Scale>().unwrap_err();
+        assert!(err.to_string().ends_with("did you mean dorian?"));
+    }
+
+    #[test]
+    fn test_piano_roll() {
+        let steps: Vec<String> = "C3 D3 _ C3 D3 -".split(' ').map(str::to_string).collect();
+        assert_eq!(
+            piano_roll(&steps),
+            vec!["  D3 .#..|#=", " C#3 ....|..", "  C3 #..#|.."]
+        );
+        assert!(piano_roll(&[]).is_empty());
+    }
+}
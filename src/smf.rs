@@ -0,0 +1,148 @@
+//! Standard MIDI files, read for the notes of a track to import in a sequence.
+
+use crate::devices::{DeviceError, Result};
+
+/// A note of a track, timed in ticks from the start of the track
+#[derive(Debug, PartialEq)]
+pub struct Note {
+    pub tick: u32,
+    pub key: u8,
+}
+
+/// A MIDI file's ticks per quarter note, and the notes of its first track that has some
+pub fn notes(bytes: &[u8]) -> Result<(u16, Vec<Note>)> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let (_, header) = reader.chunk()?;
+    if header.len() < 6 {
+        return Err(reader.error());
+    }
+    let tracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 {
+        // SMPTE time has no beats to divide in steps
+        return Err(reader.error());
+    }
+    for _ in 0..tracks {
+        let start = reader.pos;
+        let (id, track) = reader.chunk()?;
+        if id != b"MTrk" {
+            continue;
+        }
+        let notes = track_notes(track).map_err(|pos| DeviceError::MidiFileFormat {
+            pos: start + 8 + pos,
+        })?;
+        if !notes.is_empty() {
+            return Ok((division, notes));
+        }
+    }
+    Ok((division, vec![]))
+}
+
+/// Notes started by the events of a track, or the position of the first malformed event
+fn track_notes(bytes: &[u8]) -> std::result::Result<Vec<Note>, usize> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let mut notes = vec![];
+    let mut tick = 0;
+    let mut running = None;
+    while reader.pos < bytes.len() {
+        tick += reader.var_len().ok_or(reader.pos)?;
+        let mut status = reader.byte().ok_or(reader.pos)?;
+        if status < 0x80 {
+            // running status, the byte read is the first data byte
+            status = running.ok_or(reader.pos)?;
+            reader.pos -= 1;
+        }
+        match status {
+            0xff => {
+                reader.byte().ok_or(reader.pos)?;
+                let len = reader.var_len().ok_or(reader.pos)?;
+                reader.take(len as usize).ok_or(reader.pos)?;
+            }
+            0xf0 | 0xf7 => {
+                let len = reader.var_len().ok_or(reader.pos)?;
+                reader.take(len as usize).ok_or(reader.pos)?;
+            }
+            _ => {
+                running = Some(status);
+                let data = match status & 0xf0 {
+                    0xc0 | 0xd0 => reader.take(1),
+                    _ => reader.take(2),
+                }
+                .ok_or(reader.pos)?;
+                if status & 0xf0 == 0x90 && data[1] > 0 {
+                    notes.push(Note { tick, key: data[0] });
+                }
+            }
+        }
+    }
+    Ok(notes)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self) -> Box<dyn std::error::Error> {
+        Box::new(DeviceError::MidiFileFormat { pos: self.pos })
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let taken = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    /// A variable length quantity, 7 bits per byte, most significant first
+    fn var_len(&mut self) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | u32::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// The id and contents of the next chunk
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8])> {
+        let id = self.take(4).ok_or_else(|| self.error())?;
+        let len = self.take(4).ok_or_else(|| self.error())?;
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]);
+        let data = self.take(len as usize).ok_or_else(|| self.error())?;
+        Ok((id, data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::smf::{notes, Note};
+
+    #[test]
+    fn test_notes() {
+        let mut file = b"MThd\0\0\0\x06\0\x01\0\x02\0\x60".to_vec();
+        // tempo track, then notes with a running status and a note on of velocity 0
+        file.extend_from_slice(b"MTrk\0\0\0\x0b\0\xff\x51\x03\x07\xa1\x20\0\xff\x2f\0");
+        file.extend_from_slice(b"MTrk\0\0\0\x0e\0\x90\x3c\x40\x60\x3c\0\0\x3e\x40\0\xff\x2f\0");
+        let (division, keys) = notes(&file).unwrap();
+        assert_eq!(division, 0x60);
+        assert_eq!(
+            keys,
+            vec![
+                Note { tick: 0, key: 0x3c },
+                Note {
+                    tick: 0x60,
+                    key: 0x3e
+                }
+            ]
+        );
+        assert!(notes(&file[..30]).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::devices::Result;
+use linked_hash_map::LinkedHashMap;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::warn;
+
+/// Parameter values of a device, as saved to file
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub device: String,
+    /// Hash of the device name and values, to detect edited or truncated files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    pub values: LinkedHashMap<String, Vec<String>>,
+}
+
+impl Snapshot {
+    pub fn new(device: String, values: LinkedHashMap<String, Vec<String>>) -> Self {
+        let mut snap = Snapshot {
+            device,
+            checksum: None,
+            values,
+        };
+        snap.checksum = Some(snap.digest());
+        snap
+    }
+
+    /// Load from file, warning if the file does not match its checksum
+    pub fn load(path: &Path) -> Result<Snapshot> {
+        let snap: Snapshot = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+        if !snap.is_intact() {
+            warn!(
+                "{} does not match its checksum, it may have been edited or truncated",
+                path.display()
+            );
+        }
+        Ok(snap)
+    }
+
+    /// Write to file, or to stdout if no path is given
+    pub fn save(&self, path: Option<&Path>) -> Result<()> {
+        match path {
+            Some(path) => fs::write(path, serde_yaml::to_string(self)?)?,
+            None => serde_yaml::to_writer(io::stdout(), self)?,
+        }
+        Ok(())
+    }
+
+    /// Files without a checksum are considered intact
+    pub fn is_intact(&self) -> bool {
+        self.checksum
+            .as_ref()
+            .is_none_or(|sum| *sum == self.digest())
+    }
+
+    fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(self.device.as_bytes());
+        for (param, values) in &self.values {
+            hasher.input([0]);
+            hasher.input(param.as_bytes());
+            for value in values {
+                hasher.input([0x20]);
+                hasher.input(value.as_bytes());
+            }
+        }
+        hex::encode(hasher.result())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::snapshot::Snapshot;
+    use linked_hash_map::LinkedHashMap;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut values = LinkedHashMap::new();
+        values.insert("Gate".to_string(), vec!["Short".to_string()]);
+        values.insert("Seq/1".to_string(), vec!["C3".to_string(), "_".to_string()]);
+        let snap = Snapshot::new("MicroBrute".to_string(), values);
+        let yaml = serde_yaml::to_string(&snap).unwrap();
+        let loaded = serde_yaml::from_str::<Snapshot>(&yaml).unwrap();
+        assert_eq!(loaded, snap);
+        assert!(loaded.is_intact());
+
+        let edited = yaml.replace("Short", "Long");
+        assert!(!serde_yaml::from_str::<Snapshot>(&edited)
+            .unwrap()
+            .is_intact());
+        let unsigned = yaml.replace(snap.checksum.as_ref().unwrap(), "~");
+        assert!(serde_yaml::from_str::<Snapshot>(&unsigned)
+            .unwrap()
+            .is_intact());
+    }
+}
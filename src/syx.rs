@@ -0,0 +1,41 @@
+//! Standard `.syx` files: raw sysex messages, concatenated.
+
+use crate::devices::{DeviceError, Result};
+
+/// Split the contents of a `.syx` file into its sysex messages
+pub fn parse(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut messages = vec![];
+    let mut current: Option<Vec<u8>> = None;
+    for (pos, byte) in bytes.iter().enumerate() {
+        match (*byte, current.as_mut()) {
+            (0xf0, None) => current = Some(vec![0xf0]),
+            (0xf7, Some(msg)) => {
+                msg.push(0xf7);
+                messages.extend(current.take());
+            }
+            (b, Some(msg)) if b < 0x80 => msg.push(b),
+            _ => return Err(Box::new(DeviceError::SyxFormat { pos })),
+        }
+    }
+    if current.is_some() {
+        return Err(Box::new(DeviceError::SyxFormat { pos: bytes.len() }));
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::syx::parse;
+
+    #[test]
+    fn test_parse() {
+        let messages = parse(&[0xf0, 0x7e, 0x01, 0xf7, 0xf0, 0x00, 0xf7]).unwrap();
+        assert_eq!(
+            messages,
+            vec![vec![0xf0, 0x7e, 0x01, 0xf7], vec![0xf0, 0x00, 0xf7]]
+        );
+        assert!(parse(&[0xf0, 0x7e]).is_err());
+        assert!(parse(&[0x7e, 0xf7]).is_err());
+        assert!(parse(&[0xf0, 0x90, 0xf7]).is_err());
+    }
+}